@@ -0,0 +1,170 @@
+use crate::datatypes::Ray;
+use crate::datatypes::Vec3;
+use crate::datatypes::Color3;
+use crate::datatypes::HitRecord;
+use crate::utils::MatUtil;
+use crate::utils::ColorUtil;
+
+
+/// Buffers `Camera::render_aovs` fills next to the beauty image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AovKinds {
+    Albedo,         // Material color at the first hit
+    Normal,         // Shading normal at the first hit
+    Depth,          // Distance to the first hit, in every channel
+    Position,       // World position of the first hit
+    ObjectId,       // Index of the hit object plus one, 0 for the background
+    MaterialId,     // `MatUtil::id` of the hit material plus one, 0 for the background
+    Emission,       // Light seen directly, from emitters or the sky
+    Direct,         // Light after one bounce
+    Indirect        // Light after two or more bounces
+}
+#[allow(unused)]
+impl AovKinds {
+    pub const ALL: [AovKinds; 9] = [AovKinds::Albedo, AovKinds::Normal, AovKinds::Depth, AovKinds::Position,
+                                    AovKinds::ObjectId, AovKinds::MaterialId, AovKinds::Emission,
+                                    AovKinds::Direct, AovKinds::Indirect];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AovKinds::Albedo => "albedo",
+            AovKinds::Normal => "normal",
+            AovKinds::Depth => "depth",
+            AovKinds::Position => "position",
+            AovKinds::ObjectId => "object_id",
+            AovKinds::MaterialId => "material_id",
+            AovKinds::Emission => "emission",
+            AovKinds::Direct => "direct",
+            AovKinds::Indirect => "indirect",
+        }
+    }
+        // Ids can't be averaged, pixels keep the first sample that hit something.
+    fn is_id(&self) -> bool {
+        matches!(self, AovKinds::ObjectId | AovKinds::MaterialId)
+    }
+}
+
+
+/// Light arriving along a camera ray, split by how many bounces it took.
+#[derive(Clone, Debug)]
+pub struct LightSplit {
+    pub emission: Color3,
+    pub direct: Color3,
+    pub indirect: Color3
+}
+impl LightSplit {
+    pub fn zero() -> Self {
+        LightSplit { emission: Color3::zero(), direct: Color3::zero(), indirect: Color3::zero() }
+    }
+    /// Light from integrators that can't tell bounces apart.
+    pub fn indirect(indirect: Color3) -> Self {
+        LightSplit { indirect, ..Self::zero() }
+    }
+    /// Adds `light` that reached the camera after `bounces` bounces.
+    pub fn add(&mut self, bounces: usize, light: Color3) {
+        match bounces {
+            0 => self.emission += light,
+            1 => self.direct += light,
+            _ => self.indirect += light,
+        }
+    }
+    pub fn scaled(&self, weight: f64) -> Self {
+        LightSplit { emission: &self.emission * weight, direct: &self.direct * weight, indirect: &self.indirect * weight }
+    }
+    pub fn total(&self) -> Color3 {
+        &(&self.emission + &self.direct) + &self.indirect
+    }
+}
+
+
+/// Running sums of the AOV samples of one pixel.
+#[derive(Clone)]
+pub struct AovPixel {
+    values: [Color3; 9],
+    samples: u32
+}
+impl AovPixel {
+    pub fn new() -> Self {
+        AovPixel { values: std::array::from_fn(|_| Color3::zero()), samples: 0 }
+    }
+    /// Adds one camera sample along `ray`, which first hit `hit_rec` and carried `split`.
+    pub fn add(&mut self, ray: &Ray, hit_rec: Option<&HitRecord>, split: &LightSplit) {
+        let mut sample = AovPixel::new();
+        sample.samples = 1;
+        sample.values[AovKinds::Emission as usize] = split.emission.clone();
+        sample.values[AovKinds::Direct as usize] = split.direct.clone();
+        sample.values[AovKinds::Indirect as usize] = split.indirect.clone();
+        if let Some(hr) = hit_rec {
+            sample.values[AovKinds::Albedo as usize] = MatUtil::albedo(&hr.material, hr);
+            sample.values[AovKinds::Normal as usize] = hr.shading_normal.clone();
+            sample.values[AovKinds::Depth as usize] = Color3::one() * (hr.t * ray.direction().len());
+            sample.values[AovKinds::Position as usize] = hr.p.clone();
+            sample.values[AovKinds::ObjectId as usize] = Color3::one() * (hr.object_id + 1) as f64;
+            sample.values[AovKinds::MaterialId as usize] = Color3::one() * (MatUtil::id(&hr.material) + 1) as f64;
+        }
+        self.merge(&sample);
+    }
+    /// Adds light that reached the pixel from other pixels' samples, such as BDPT
+    /// splats, already averaged per sample.
+    pub fn add_splat(&mut self, split: &LightSplit) {
+        let samples = u32::max(self.samples, 1) as f64;
+        self.values[AovKinds::Emission as usize] += &split.emission * samples;
+        self.values[AovKinds::Direct as usize] += &split.direct * samples;
+        self.values[AovKinds::Indirect as usize] += &split.indirect * samples;
+    }
+    /// Adds the samples of `other`, from another pass over the same pixel.
+    pub fn merge(&mut self, other: &AovPixel) {
+        for kind in AovKinds::ALL {
+            let (value, other) = (&mut self.values[kind as usize], &other.values[kind as usize]);
+            if !kind.is_id() {
+                *value += other.clone();
+            }
+            else if value.x == 0.0 {
+                *value = other.clone();
+            }
+        }
+        self.samples += other.samples;
+    }
+    fn value(&self, kind: AovKinds) -> Color3 {
+        let value = self.values[kind as usize].clone();
+        if kind.is_id() || self.samples == 0 { value } else { value / self.samples as f64 }
+    }
+}
+
+
+/// Finished AOV images, one buffer of linear values per `AovKinds`.
+pub struct Aovs {
+    layers: Vec<Vec<Color3>>
+}
+#[allow(unused)]
+impl Aovs {
+    pub fn new(pixels: &[AovPixel]) -> Self {
+        let layers = AovKinds::ALL.iter().map(|kind| pixels.iter().map(|pixel| pixel.value(*kind)).collect()).collect();
+        Aovs { layers }
+    }
+    pub fn layer(&self, kind: AovKinds) -> &[Color3] {
+        &self.layers[kind as usize]
+    }
+    /// Every layer with its name, in `AovKinds::ALL` order, e.g. for `ImageUtil::save_exr`.
+    pub fn layers(&self) -> Vec<(&'static str, &[Color3])> {
+        AovKinds::ALL.iter().map(|kind| (kind.name(), self.layer(*kind))).collect()
+    }
+    /// The layer mapped into [0, 1] for 8-bit images: normals from [-1, 1], depth and
+    /// positions by their largest value, ids to flat colors.
+    pub fn preview(&self, kind: AovKinds) -> Vec<Color3> {
+        let layer = self.layer(kind);
+        match kind {
+            AovKinds::Normal => layer.iter().map(|n| 0.5 * &(n + &Vec3::one())).collect(),
+            AovKinds::Depth | AovKinds::Position => {
+                let max = layer.iter().map(|c| f64::max(c.x.abs(), f64::max(c.y.abs(), c.z.abs()))).fold(0.0, f64::max);
+                let scale = if max > 0.0 { 1.0 / max } else { 1.0 };
+                layer.iter().map(|c| match kind {
+                    AovKinds::Depth => c * scale,
+                    _ => 0.5 * &(&(c * scale) + &Vec3::one()),
+                }).collect()
+            },
+            AovKinds::ObjectId | AovKinds::MaterialId => layer.iter().map(|c| ColorUtil::id_color(c.x as usize)).collect(),
+            _ => layer.to_vec(),
+        }
+    }
+}
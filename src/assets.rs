@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use crate::datatypes::Color3;
+use crate::materials::Materials;
+use crate::materials::PrincipledBuilder;
+
+
+/// Materials of a Wavefront MTL library, as modelling tools export them with the
+/// PBR extension. Every `newmtl` becomes a principled material, mapped as
+///
+///     Kd -> base color        Pm -> metallic          Pr -> roughness
+///     Ps -> sheen (mean)      Pc -> clearcoat         Pcr -> 1 - clearcoat gloss
+///     d -> 1 - transmission   Tr -> transmission      Ni -> index of refraction
+///     Tf -> base color of transmissive materials
+///
+/// `Ks` is left out: it is a Phong specular colour, which says nothing about the
+/// principled specular level, and exporters write one for every material anyway.
+/// `Tf` only counts once `d` or `Tr` let light through, as exporters also write
+/// `Tf 1 1 1` on opaque materials.
+/// Parameters MTL has no statement for keep the `PrincipledBuilder` defaults.
+/// Other statements (`Ka`, `illum`, texture maps, ...) and malformed ones are skipped.
+pub struct MtlLibrary;
+#[allow(unused)]
+impl MtlLibrary {
+    pub fn load(path: &str) -> std::io::Result<HashMap<String, Materials>> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+    /// Materials of the library `source`, by name.
+    pub fn parse(source: &str) -> HashMap<String, Materials> {
+        let mut materials = HashMap::new();
+        let mut current: Option<MtlMaterial> = None;
+        for line in source.lines() {
+            let (keyword, rest) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+            if keyword == "newmtl" {
+                if let Some(material) = current.take() {
+                    materials.insert(material.name.clone(), material.build());
+                }
+                current = Some(MtlMaterial::new(rest.trim()));
+                continue;
+            }
+            let values: Option<Vec<f64>> = rest.split_whitespace().map(|word| word.parse().ok()).collect();
+            if let Some(values) = values {
+                current = current.map(|material| material.apply(keyword, &values));
+            }
+        }
+        if let Some(material) = current {
+            materials.insert(material.name.clone(), material.build());
+        }
+        materials
+    }
+}
+
+
+    // One `newmtl` block while it is read. Transmission and its filter colour are
+    // kept aside, since `Tf` only replaces the base colour once the block is done.
+struct MtlMaterial {
+    name: String,
+    builder: PrincipledBuilder,
+    transmission: f64,
+    filter: Option<Color3>
+}
+impl MtlMaterial {
+    fn new(name: &str) -> Self {
+        MtlMaterial { name: name.to_string(), builder: PrincipledBuilder::new(Color3::new(0.8, 0.8, 0.8)), transmission: 0.0, filter: None }
+    }
+        // The block with the statement `keyword values` applied.
+    fn apply(mut self, keyword: &str, values: &[f64]) -> Self {
+        let builder = self.builder;
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        self.builder = match (keyword, values) {
+            ("Kd", [r, g, b]) => builder.base_color(Color3::new(*r, *g, *b)),
+            ("Kd", [gray]) => builder.base_color(Color3::one() * *gray),
+            ("Pm", [metallic]) => builder.metallic(*metallic),
+            ("Pr", [roughness]) => builder.roughness(*roughness),
+            ("Ps", [_] | [_, _, _]) => builder.sheen(mean),
+            ("Pc", [clearcoat]) => builder.clearcoat(*clearcoat),
+            ("Pcr", [roughness]) => builder.clearcoat_gloss(1.0 - roughness),
+            ("Ni", [ir]) => builder.ir(*ir),
+            ("d", [dissolve]) => {
+                self.transmission = 1.0 - dissolve;
+                builder
+            },
+            ("Tr", [transmission]) => {
+                self.transmission = *transmission;
+                builder
+            },
+            ("Tf", [r, g, b]) => {
+                self.filter = Some(Color3::new(*r, *g, *b));
+                builder
+            },
+            ("Tf", [gray]) => {
+                self.filter = Some(Color3::one() * *gray);
+                builder
+            },
+            _ => builder,
+        };
+        self
+    }
+    fn build(self) -> Materials {
+        let builder = match self.filter {
+            Some(filter) if self.transmission > 0.0 => self.builder.base_color(filter),
+            _ => self.builder,
+        };
+        Materials::Principled(builder.transmission(self.transmission).build())
+    }
+}
@@ -0,0 +1,546 @@
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
+
+use crate::aovs::LightSplit;
+use crate::camera::Camera;
+use crate::integrators::PathDepth;
+use crate::datatypes::Ray;
+use crate::datatypes::Vec3;
+use crate::datatypes::Point3;
+use crate::datatypes::Color3;
+use crate::datatypes::Interval;
+use crate::datatypes::Hittable;
+use crate::datatypes::HitRecord;
+use crate::lights::LightPosition;
+use crate::light_tree::LightPowerTable;
+use crate::materials::Materials;
+use crate::shapes::HittableList;
+use crate::utils::MatUtil;
+use crate::utils::LightUtil;
+use crate::utils::SkyUtil;
+
+
+/// Bidirectional path tracer (Veach 1997, laid out after pbrt-v3). Each camera sample
+/// also traces a light path, every pair of vertices is connected and the strategies
+/// are combined with the balance heuristic. Connections straight to the camera land
+/// on arbitrary pixels and go to a `SplatBuffer` instead.
+/// Lights at infinity and the sky can't start light paths; they are reached by the
+/// camera path alone, like in the path tracer.
+pub struct Bdpt<'a> {
+    camera: &'a Camera,
+    world: &'a HittableList,
+    power: &'a LightPowerTable,
+    max_depth: usize
+}
+
+#[derive(Clone, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3,
+    normal: Option<Vec3>,               // Geometric normal of vertices on surfaces
+    shading_normal: Option<Vec3>,
+    from: Point3,                       // Where the subpath arrived from
+    hit_rec: Option<HitRecord>,         // Surface vertices, facing `from`
+    material: Option<Materials>,        // Resolved material of surface vertices
+    light: Option<usize>,               // Light the vertex lies on
+    beta: Color3,                       // Path throughput up to the vertex
+    delta: bool,                        // Scattered by a specular material
+    pdf_fwd: f64,                       // Area density of the own subpath reaching the vertex
+    pdf_rev: f64                        // Area density of the other subpath reaching it
+}
+
+impl Vertex {
+    fn camera(p: Point3, beta: Color3) -> Self {
+        Vertex { kind: VertexKind::Camera, from: p.clone(), p, normal: None, shading_normal: None, hit_rec: None,
+                 material: None, light: None, beta, delta: false, pdf_fwd: 0.0, pdf_rev: 0.0 }
+    }
+    fn light(light: usize, position: &LightPosition, beta: Color3, pdf_fwd: f64) -> Self {
+        Vertex { kind: VertexKind::Light, p: position.p.clone(), from: position.p.clone(), normal: position.normal.clone(),
+                 shading_normal: position.normal.clone(), hit_rec: None, material: None, light: Some(light),
+                 beta, delta: false, pdf_fwd, pdf_rev: 0.0 }
+    }
+    fn surface(hit_rec: HitRecord, material: Materials, from: Point3, light: Option<usize>, beta: Color3) -> Self {
+        Vertex { kind: VertexKind::Surface, p: hit_rec.p.clone(), from, normal: Some(hit_rec.normal.clone()),
+                 shading_normal: Some(hit_rec.shading_normal.clone()), hit_rec: Some(hit_rec), material: Some(material),
+                 light, beta, delta: false, pdf_fwd: 0.0, pdf_rev: 0.0 }
+    }
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Surface => !self.delta,
+            _ => true,
+        }
+    }
+    fn is_delta_light(&self) -> bool {
+        self.kind == VertexKind::Light && self.normal.is_none()
+    }
+        // BSDF value for light going between `from` and `next`. Paths carrying importance
+        // get the shading normal correction, since the BSDF isn't symmetric with it.
+    fn f(&self, next: &Vertex, importance: bool) -> Color3 {
+        self.f_towards(&(&next.p - &self.p).unit(), importance)
+    }
+    fn f_towards(&self, wi: &Vec3, importance: bool) -> Color3 {
+        let (Some(hit_rec), Some(material)) = (&self.hit_rec, &self.material) else {
+            return Color3::zero();
+        };
+        let wo = (&self.from - &self.p).unit();
+        let f = MatUtil::eval(material, &Ray::new(self.from.clone(), -&wo), hit_rec, wi);
+        if importance { f * Self::shading_correction(hit_rec, &wo, wi) } else { f }
+    }
+    fn shading_correction(hit_rec: &HitRecord, wo: &Vec3, wi: &Vec3) -> f64 {
+        let denom = wo.dot(&hit_rec.normal).abs() * wi.dot(&hit_rec.shading_normal).abs();
+        if denom == 0.0 {
+            return 0.0;
+        }
+        wo.dot(&hit_rec.shading_normal).abs() * wi.dot(&hit_rec.normal).abs() / denom
+    }
+        // Turns a solid angle density at this vertex into an area density at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = &next.p - &self.p;
+        let dist_sqr = w.len_sqr();
+        if dist_sqr == 0.0 {
+            return 0.0;
+        }
+        let cos_next = next.normal.as_ref().map_or(1.0, |n| n.dot(&w).abs() / f64::sqrt(dist_sqr));
+        pdf * cos_next / dist_sqr
+    }
+        // Area density at `next` of this vertex scattering towards it, arriving from `prev`.
+    fn pdf(&self, camera: &Camera, world: &HittableList, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        match self.kind {
+            VertexKind::Camera => {
+                let (_, pdf_dir) = camera.pdf_ray(&(&next.p - &self.p).unit());
+                self.convert_density(pdf_dir, next)
+            },
+            VertexKind::Light => self.pdf_light(world, next),
+            VertexKind::Surface => {
+                let (Some(prev), Some(hit_rec), Some(material)) = (prev, &self.hit_rec, &self.material) else {
+                    return 0.0;
+                };
+                let ray = Ray::new(prev.p.clone(), (&self.p - &prev.p).unit());
+                let wi = (&next.p - &self.p).unit();
+                let pdf_dir = MatUtil::pdf(material, &ray, &hit_rec.facing(&prev.p), &wi);
+                self.convert_density(pdf_dir, next)
+            },
+        }
+    }
+        // Area density at `next` of light leaving this vertex, which lies on a light.
+    fn pdf_light(&self, world: &HittableList, next: &Vertex) -> f64 {
+        let Some(light) = self.light else {
+            return 0.0;
+        };
+        let direction = (&next.p - &self.p).unit();
+        let pdf_dir = LightUtil::pdf_direction(&world.lights()[light], self.normal.as_ref(), &direction);
+        self.convert_density(pdf_dir, next)
+    }
+        // Area density of a light path starting at this vertex.
+    fn pdf_light_origin(&self, world: &HittableList, power: &LightPowerTable) -> f64 {
+        self.light.map_or(0.0, |light| power.pmf(light) * LightUtil::pdf_position(&world.lights()[light]))
+    }
+        // Radiance given off by an emissive surface, the same on both sides.
+    fn le(&self) -> Color3 {
+        match (&self.hit_rec, &self.material) {
+            (Some(hit_rec), Some(material)) => MatUtil::emitted(material, hit_rec),
+            _ => Color3::zero(),
+        }
+    }
+}
+
+/// Camera path that left the scene, to be lit by the sky.
+struct Escape {
+    direction: Vec3,
+    beta: Color3,
+    specular: bool      // No light was sampled at the last vertex
+}
+
+#[allow(unused)]
+impl<'a> Bdpt<'a> {
+    pub fn new(camera: &'a Camera, world: &'a HittableList, power: &'a LightPowerTable, max_depth: u32) -> Self {
+        Bdpt { camera, world, power, max_depth: max_depth as usize }
+    }
+    /// Radiance arriving along the camera `ray`. Light tracing contributions go to `splats`.
+    pub fn li(&self, ray: &Ray, splats: &SplatBuffer) -> Color3 {
+        self.li_split(ray, splats).0.total()
+    }
+    /// `li` split by the number of bounces of each strategy's path, along with the
+    /// first hit of `ray`.
+    pub fn li_split(&self, ray: &Ray, splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        let direction = ray.direction().unit();
+        let mut camera_path = vec![Vertex::camera(ray.origin().clone(), Color3::one())];
+        let (_, pdf_dir) = self.camera.pdf_ray(&direction);
+        let escape = self.random_walk(Ray::new(ray.origin().clone(), direction), Color3::one(), pdf_dir,
+                                      false, self.max_depth + 2, &mut camera_path);
+        let light_path = self.light_subpath();
+
+            // A strategy's path has s + t - 2 bounces between the light and the camera.
+        let mut radiance = LightSplit::zero();
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > self.max_depth {
+                    continue;
+                }
+                if t == 1 {
+                    if let Some((contribution, (i, j))) = self.connect_camera(&light_path, s) {
+                        splats.add(i, j, s - 1, &contribution);
+                    }
+                }
+                else {
+                    radiance.add(s + t - 2, self.connect(&light_path, &camera_path, s, t));
+                }
+            }
+        }
+        self.infinite_lights(&camera_path, &mut radiance);
+        if let Some(escape) = escape {
+            let mut sky = SkyUtil::radiance(&self.world.sky, &escape.direction);
+            if escape.specular {
+                for light in self.world.lights() {
+                    sky += LightUtil::le(light, &escape.direction);
+                }
+            }
+            radiance.add(camera_path.len() - 1, escape.beta * sky);
+        }
+        (radiance, Self::first_hit(ray, &camera_path))
+    }
+        // The camera path walks a unit length copy of `ray`, so the distance of its
+        // first vertex is rescaled to `ray`. Media steps before it don't count, as
+        // they fold into the vertex.
+    fn first_hit(ray: &Ray, camera_path: &[Vertex]) -> Option<HitRecord> {
+        let mut hit_rec = camera_path.get(1)?.hit_rec.clone()?;
+        hit_rec.t /= ray.direction().len();
+        Some(hit_rec)
+    }
+    fn light_subpath(&self) -> Vec<Vertex> {
+        let mut path = Vec::new();
+        let Some((index, pmf)) = self.power.sample() else {
+            return path;
+        };
+        let light = &self.world.lights()[index];
+        let Some(position) = LightUtil::sample_position(light) else {
+            return path;
+        };
+        let Some((direction, pdf_dir)) = LightUtil::sample_direction(light, &position) else {
+            return path;
+        };
+        if pdf_dir <= 0.0 {
+            return path;
+        }
+        let le = LightUtil::emission(light, &position, &direction);
+        let cos_theta = position.normal.as_ref().map_or(1.0, |n| n.dot(&direction).abs());
+        let beta = &le * (cos_theta / (pmf * position.pdf * pdf_dir));
+        let pdf_origin = position.pdf * pmf;
+
+        path.push(Vertex::light(index, &position, le, pdf_origin));
+        self.random_walk(Ray::new(position.p, direction), beta, pdf_dir, true, self.max_depth + 1, &mut path);
+        path
+    }
+        // Extends `path` by scattering off the scene, filling in both directions'
+        // densities as it goes. Returns how the camera path left the scene, if it did.
+    fn random_walk(&self, mut ray: Ray, mut beta: Color3, mut pdf_dir: f64, importance: bool,
+                   max_vertices: usize, path: &mut Vec<Vertex>) -> Option<Escape> {
+        let mut medium_steps = PathDepth::MEDIUM_STEPS;
+        while path.len() < max_vertices {
+            let prev = path.len() - 1;
+            let Some(hit_rec) = self.world.hit(&ray, &Interval::HIT_EVAL) else {
+                if importance {
+                    return None;
+                }
+                let specular = path[prev].kind == VertexKind::Camera || path[prev].delta;
+                return Some(Escape { direction: ray.direction().clone(), beta, specular });
+            };
+
+            let material = MatUtil::resolve(&hit_rec.material, &hit_rec).clone();
+            let emissive = MatUtil::is_emissive(&material);
+            let light = if emissive { self.world.light_index(&hit_rec) } else { None };
+            let scattered = if emissive { None } else { MatUtil::scatter(&material, &ray, &hit_rec) };
+            let delta = MatUtil::is_specular(&material);
+
+                // Steps of a walk inside a medium fold into the delta vertex where
+                // the walk started, rather than using up vertices.
+            if MatUtil::is_medium_step(&material, &hit_rec) {
+                let Some((attenuation, Some(next_ray))) = scattered else {
+                    return None;
+                };
+                if medium_steps == 0 {
+                    return None;
+                }
+                medium_steps -= 1;
+                beta = beta * attenuation;
+                ray = next_ray;
+                continue;
+            }
+
+            let mut vertex = Vertex::surface(hit_rec, material, ray.origin().clone(), light, beta.clone());
+            vertex.pdf_fwd = path[prev].convert_density(pdf_dir, &vertex);
+            vertex.delta = delta;
+
+                // Emitters and absorbing surfaces end the path.
+            let Some((attenuation, Some(next_ray))) = scattered else {
+                path.push(vertex);
+                return None;
+            };
+            let hit_rec = vertex.hit_rec.as_ref().unwrap();
+            let material = vertex.material.as_ref().unwrap();
+            let wi = next_ray.direction().unit();
+            let wo = -&ray.direction().unit();
+            let (pdf_fwd, pdf_rev) = if delta {
+                (0.0, 0.0)
+            }
+            else {
+                let beyond = &vertex.p + &wi;
+                let reverse = Ray::new(beyond.clone(), -&wi);
+                (MatUtil::pdf(material, &ray, hit_rec, &wi), MatUtil::pdf(material, &reverse, &hit_rec.facing(&beyond), &wo))
+            };
+            if !delta && pdf_fwd <= 0.0 {
+                path.push(vertex);
+                return None;
+            }
+
+            beta = beta * attenuation;
+            if importance {
+                beta *= Vertex::shading_correction(hit_rec, &wo, &wi);
+            }
+            path[prev].pdf_rev = vertex.convert_density(pdf_rev, &path[prev]);
+            path.push(vertex);
+
+            pdf_dir = pdf_fwd;
+            ray = Ray::new(next_ray.origin().clone(), wi);
+        }
+        None
+    }
+        // Strategy with `s` light and `t` >= 2 camera vertices.
+    fn connect(&self, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize) -> Color3 {
+        let pt = &camera_path[t - 1];
+        let mut sampled: Option<Vertex> = None;
+
+        let contribution = if s == 0 {
+            pt.beta.clone() * pt.le()
+        }
+        else if s == 1 {
+            if !pt.is_connectible() {
+                return Color3::zero();
+            }
+            let Some(light_vertex) = self.sample_light_vertex(pt) else {
+                return Color3::zero();
+            };
+            let contribution = pt.beta.clone() * pt.f(&light_vertex, false) * light_vertex.beta.clone() * self.g(pt, &light_vertex);
+            sampled = Some(light_vertex);
+            contribution
+        }
+        else {
+            let qs = &light_path[s - 1];
+            if !qs.is_connectible() || !pt.is_connectible() {
+                return Color3::zero();
+            }
+            qs.beta.clone() * qs.f(pt, true) * pt.f(qs, false) * pt.beta.clone() * self.g(qs, pt)
+        };
+
+        if contribution.is_near_zero() {
+            return Color3::zero();
+        }
+        let weight = self.mis_weight(light_path, camera_path, sampled.as_ref(), s, t);
+        contribution * weight
+    }
+        // Strategy with `s` light vertices connected straight to the camera, and the pixel it lands on.
+    fn connect_camera(&self, light_path: &[Vertex], s: usize) -> Option<(Color3, (u32, u32))> {
+        let qs = &light_path[s - 1];
+        if !qs.is_connectible() {
+            return None;
+        }
+        let lens = self.camera.sample_lens();
+        let raster = self.camera.raster(&lens, &qs.p)?;
+        let to_camera = &lens - &qs.p;
+        let dist_sqr = to_camera.len_sqr();
+        let direction = -&to_camera.unit();
+        let cos_lens = direction.dot(&self.camera.forward());
+        if cos_lens <= 0.0 {
+            return None;
+        }
+
+            // Importance over the solid angle density of picking this lens point.
+        let pdf = dist_sqr / (cos_lens * self.camera.lens_area());
+        let camera_vertex = Vertex::camera(lens, Color3::one() * (self.camera.importance(&direction) / pdf));
+        let cos_qs = qs.shading_normal.as_ref().map_or(1.0, |n| n.dot(&direction).abs());
+        let contribution = qs.beta.clone() * qs.f(&camera_vertex, true) * camera_vertex.beta.clone() * cos_qs;
+        if contribution.is_near_zero() || !self.visible(&qs.p, &camera_vertex.p) {
+            return None;
+        }
+        let weight = self.mis_weight(light_path, &[], Some(&camera_vertex), s, 1);
+        Some((contribution * weight, raster))
+    }
+        // Fresh light vertex for the s = 1 strategy at `pt`, picked by power and area.
+    fn sample_light_vertex(&self, pt: &Vertex) -> Option<Vertex> {
+        let (index, pmf) = self.power.sample()?;
+        let light = &self.world.lights()[index];
+        let position = LightUtil::sample_position(light)?;
+        let direction = (&pt.p - &position.p).unit();
+        let le = LightUtil::emission(light, &position, &direction);
+        let beta = le / (pmf * position.pdf);
+        let pdf_origin = pmf * position.pdf;
+        Some(Vertex::light(index, &position, beta, pdf_origin))
+    }
+        // Geometry term between two vertices, zero when something is in between.
+    fn g(&self, a: &Vertex, b: &Vertex) -> f64 {
+        let d = &b.p - &a.p;
+        let dist_sqr = d.len_sqr();
+        if dist_sqr == 0.0 {
+            return 0.0;
+        }
+        let w = &d / f64::sqrt(dist_sqr);
+        let cos_a = a.shading_normal.as_ref().map_or(1.0, |n| n.dot(&w).abs());
+        let cos_b = b.shading_normal.as_ref().map_or(1.0, |n| n.dot(&w).abs());
+        if !self.visible(&a.p, &b.p) {
+            return 0.0;
+        }
+        cos_a * cos_b / dist_sqr
+    }
+    fn visible(&self, a: &Point3, b: &Point3) -> bool {
+        let d = b - a;
+        let dist = d.len();
+        let shadow_ray = Ray::new(a.clone(), &d / dist);
+        self.world.hit(&shadow_ray, &Interval::new(Interval::HIT_EVAL.min, dist * (1.0 - 1e-6))).is_none()
+    }
+        // Balance heuristic weight of strategy (s, t) against every other way of
+        // building the same path. `sampled` replaces the endpoint for s = 1 or t = 1.
+    fn mis_weight(&self, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let qs = if s == 1 { sampled } else if s > 1 { Some(&light_path[s - 1]) } else { None };
+        let pt = if t == 1 { sampled.unwrap() } else { &camera_path[t - 1] };
+        let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+        let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+        if s == 0 && pt.light.is_none() {
+            return 1.0;
+        }
+
+            // (pdf_fwd, pdf_rev, delta) of each vertex, with the endpoints updated for this strategy.
+        let entry = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut camera: Vec<(f64, f64, bool)> = camera_path.iter().take(t).map(entry).collect();
+        let mut light: Vec<(f64, f64, bool)> = light_path.iter().take(s).map(entry).collect();
+        if t == 1 {
+            camera = vec![entry(pt)];
+        }
+        if s == 1 {
+            light = vec![entry(qs.unwrap())];
+        }
+        camera[t - 1].2 = false;
+        if s > 0 {
+            light[s - 1].2 = false;
+        }
+        camera[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(self.camera, self.world, qs_minus, pt),
+            None => pt.pdf_light_origin(self.world, self.power),
+        };
+        if let Some(pt_minus) = pt_minus {
+            camera[t - 2].1 = match qs {
+                Some(qs) => pt.pdf(self.camera, self.world, Some(qs), pt_minus),
+                None => pt.pdf_light(self.world, pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light[s - 1].1 = pt.pdf(self.camera, self.world, pt_minus, qs);
+        }
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            light[s - 2].1 = qs.pdf(self.camera, self.world, Some(pt), qs_minus);
+        }
+
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum_ri = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum_ri += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap(light[i].1) / remap(light[i].0);
+            let delta_light = if i > 0 {
+                light[i - 1].2
+            }
+            else if s == 1 {
+                qs.unwrap().is_delta_light()
+            }
+            else {
+                light_path[0].is_delta_light()
+            };
+            if !light[i].2 && !delta_light {
+                sum_ri += ri;
+            }
+        }
+        1.0 / (1.0 + sum_ri)
+    }
+        // Lights at infinity sampled from every camera vertex.
+    fn infinite_lights(&self, camera_path: &[Vertex], radiance: &mut LightSplit) {
+        for (bounces, pt) in camera_path.iter().enumerate().skip(1).take(self.max_depth) {
+            if !pt.is_connectible() || pt.light.is_some() {
+                continue;
+            }
+            for light in self.world.lights().iter().filter(|light| LightUtil::bounds(light).is_none()) {
+                let Some(sample) = LightUtil::sample_li(light, &pt.p) else {
+                    continue;
+                };
+                let cos_theta = pt.shading_normal.as_ref().map_or(0.0, |n| n.dot(&sample.direction).abs());
+                let f = pt.f_towards(&sample.direction, false);
+                if f.is_near_zero() || cos_theta <= 0.0 {
+                    continue;
+                }
+                let shadow_ray = Ray::new(pt.p.clone(), sample.direction.clone());
+                if self.world.hit(&shadow_ray, &Interval::new(Interval::HIT_EVAL.min, f64::INFINITY)).is_some() {
+                    continue;
+                }
+                radiance.add(bounces, pt.beta.clone() * f * sample.radiance * (cos_theta / sample.pdf));
+            }
+        }
+    }
+}
+
+
+/// Image that light paths add to from many threads at once, kept apart by bounces
+/// like `LightSplit`. Values are summed in fixed point, so the totals don't depend
+/// on the order threads add them in.
+pub struct SplatBuffer {
+    width: u32,
+    pixels: Vec<[[AtomicI64; 3]; 3]>      // Emission, direct and indirect RGB
+}
+#[allow(unused)]
+impl SplatBuffer {
+        // 24 fractional bits, so sums reach ±2^39 (about 5.5e11) per channel. Larger
+        // splats and sums saturate there instead of wrapping around, and only once
+        // saturated does the order of adds matter.
+    const SCALE: f64 = 16777216.0;
+
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = (0..width * height).map(|_| std::array::from_fn(|_| std::array::from_fn(|_| AtomicI64::new(0)))).collect();
+        SplatBuffer { width, pixels }
+    }
+    /// Adds `color` to pixel (i, j), as light that took `bounces` bounces.
+    pub fn add(&self, i: u32, j: u32, bounces: usize, color: &Color3) {
+        let pixel = &self.pixels[(j * self.width + i) as usize][usize::min(bounces, 2)];
+        for (channel, value) in pixel.iter().zip([color.x, color.y, color.z]) {
+            if value == 0.0 || !value.is_finite() {
+                continue;
+            }
+                // `as` saturates, the sum has to be told to.
+            let fixed = (value * Self::SCALE).round() as i64;
+            let _ = channel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| Some(sum.saturating_add(fixed)));
+        }
+    }
+    pub fn get(&self, index: usize) -> Color3 {
+        self.get_split(index).total()
+    }
+    pub fn get_split(&self, index: usize) -> LightSplit {
+        let value = |[r, g, b]: &[AtomicI64; 3]| {
+            let channel = |channel: &AtomicI64| channel.load(Ordering::Relaxed) as f64 / Self::SCALE;
+            Color3::new(channel(r), channel(g), channel(b))
+        };
+        let [emission, direct, indirect] = &self.pixels[index];
+        LightSplit { emission: value(emission), direct: value(direct), indirect: value(indirect) }
+    }
+}
@@ -1,21 +1,42 @@
 
+use std::f64::consts::PI;
+use std::time::Instant;
+
 use indicatif::ProgressBar;
 use rayon::prelude::*;
 
+use crate::aovs::Aovs;
+use crate::aovs::AovPixel;
+use crate::bdpt::SplatBuffer;
+use crate::progressive::AdaptiveSampling;
+use crate::progressive::Moments;
+use crate::progressive::PassInfo;
+use crate::progressive::PixelMoments;
+use crate::progressive::RenderBudget;
+use crate::filters::Filters;
+use crate::filters::FilterFactory;
+use crate::filters::FilterSampler;
+use crate::samplers::Samplers;
+use crate::tiles::TileOrders;
+use crate::tiles::TileStats;
+use crate::samplers::SamplerFactory;
+use crate::integrators::IntPath;
+use crate::integrators::Integrators;
+use crate::integrators::IntegratorFactory;
 use crate::datatypes::Ray;
 use crate::datatypes::Vec3;
 use crate::datatypes::Point3;
 use crate::datatypes::Color3;
-use crate::datatypes::Interval;
-use crate::datatypes::Hittable;
 use crate::shapes::HittableList;
-use crate::utils::MatUtil;
+use crate::utils::ColorUtil;
+use crate::utils::HitUtil;
+use crate::utils::IntegratorUtil;
 use crate::utils::MathUtil;
+use crate::utils::SamplerUtil;
 
 pub struct Camera {
     aspect_ratio: f64,  // Aspect ratio
     pixel_samples: u32, // Number of samples per pixel
-    pixel_sample_scale: f64, // Scale factor for pixel samples
     max_bounces: u32,   // Maximum number of bounces
     im_width: u32,      // Rendered image width
     im_height: u32,     // Rendered image height
@@ -32,6 +53,13 @@ pub struct Camera {
     lookfrom: Point3,
     focus_dist: f64,
     vup: Vec3,
+    integrator: Integrators,
+    adaptive: Option<AdaptiveSampling>,
+    sampler: Samplers,
+    seed: u64,          // Same seed, same image
+    filter: FilterSampler,
+    tile_size: u32,     // Tile width and height in pixels
+    tile_order: TileOrders,
 }
 #[allow(clippy::too_many_arguments)]
 #[allow(unused)]
@@ -68,12 +96,9 @@ impl Camera {
         let defocus_dsk_u = defocus_radius * &u;
         let defocus_dsk_v = defocus_radius * &v;
 
-        let pixel_sample_scale = 1.0 / pixel_samples as f64;
-
         Self {
             aspect_ratio,
             pixel_samples,
-            pixel_sample_scale,
             max_bounces,
             im_width,
             im_height,
@@ -90,6 +115,13 @@ impl Camera {
             lookfrom,
             focus_dist,
             vup,
+            integrator: IntegratorFactory::make_path(),
+            adaptive: None,
+            sampler: SamplerFactory::make_independent(),
+            seed: 0,
+            filter: FilterSampler::new(FilterFactory::make_box(0.5)),
+            tile_size: 16,
+            tile_order: TileOrders::Spiral,
         }
     }
     pub fn set_lookat(&mut self, lookat: Point3) {
@@ -107,6 +139,28 @@ impl Camera {
     pub fn set_vfov(&mut self, vfov: f64) {
         self.vfov = vfov;
     }
+    pub fn set_integrator(&mut self, integrator: Integrators) {
+        self.integrator = integrator;
+    }
+    /// Samples pixels until they converge instead of taking `samples_per_pixel` each.
+    pub fn set_adaptive(&mut self, adaptive: Option<AdaptiveSampling>) {
+        self.adaptive = adaptive;
+    }
+    pub fn set_sampler(&mut self, sampler: Samplers) {
+        self.sampler = sampler;
+    }
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+    /// Pixel filter of camera samples. Light paths that connect to the camera, as in
+    /// BDPT, still land in the one pixel they hit.
+    pub fn set_filter(&mut self, filter: Filters) {
+        self.filter = FilterSampler::new(filter);
+    }
+    pub fn set_tiles(&mut self, tile_size: u32, tile_order: TileOrders) {
+        self.tile_size = tile_size;
+        self.tile_order = tile_order;
+    }
     pub fn update(&mut self) {
         self.im_height = u32::max((self.im_width as f64 / self.aspect_ratio) as u32, 1);
         self.center = self.lookfrom.clone();
@@ -137,76 +191,240 @@ impl Camera {
         self.defocus_dsk_u = defocus_radius * &u;
         self.defocus_dsk_v = defocus_radius * &v;
 
-        self.pixel_sample_scale = 1.0 / self.pixel_samples as f64;
     }
     pub fn ray_color(ray: &Ray, bounces: u32, world: &HittableList) -> Color3 {
-        if bounces == 0 {
-            return Color3::zero();
-        }
-
-        match world.hit(ray, &Interval::HIT_EVAL) {
-            Some(hr) => {
-                match MatUtil::scatter(&hr.material, ray, &hr) {
-                    Some((att, sc_ray)) => {
-                        match sc_ray {
-                            Some(ray) => att * Self::ray_color(&ray, bounces - 1, world),
-                            None => att,
-                        }
-                    },
-                    None => Color3::zero(),
-                }
-            },
-            None => {
-                    // Sky box
-                let unit_dir = ray.direction().unit();
-                let a = 0.5 * (unit_dir.y + 1.0);
-                (1.0 - a) * Color3::one() + (a * Color3::new(0.5, 0.7, 1.0))
-                // Color3::one() * 0.1
-            },
-        }
+        IntPath::ray_color(ray, bounces, world)
     }
     pub fn render(&self, world: &HittableList) -> Vec<Color3> {
-        let mut pixels: Vec<Color3> = Vec::new();
-        let mut points: Vec<(u32, u32)> = Vec::new();
-
-        for j in 0..self.im_height {
-            for i in 0..self.im_width {
-                points.push((i, j));
-            }
+        self.render_layers(world, false, &self.single_pass(), &mut |_, _| {}, &|_, _| {}).image
+    }
+    /// Renders the beauty image along with the `AovKinds` buffers.
+    pub fn render_aovs(&self, world: &HittableList) -> (Vec<Color3>, Aovs) {
+        let layers = self.render_layers(world, true, &self.single_pass(), &mut |_, _| {}, &|_, _| {});
+        (layers.image, Aovs::new(&layers.aovs))
+    }
+    /// Renders in passes until `budget` is spent, calling `on_pass` with the image so far after each one.
+    pub fn render_progressive<F: FnMut(&[Color3], &PassInfo)>(&self, world: &HittableList, budget: &RenderBudget,
+                                                               mut on_pass: F) -> Vec<Color3> {
+        self.render_layers(world, false, budget, &mut on_pass, &|_, _| {}).image
+    }
+    /// Renders like `render`, calling `on_tile` from the rendering thread with the pixels
+    /// of every tile as soon as it's done, and returns what each tile cost.
+    pub fn render_tiles<F: Fn(&TileStats, &[Color3]) + Sync>(&self, world: &HittableList, on_tile: F) -> (Vec<Color3>, Vec<TileStats>) {
+        let layers = self.render_layers(world, false, &self.single_pass(), &mut |_, _| {}, &on_tile);
+        (layers.image, layers.tiles)
+    }
+        // All of `samples_per_pixel` in one pass, as far as the integrator allows. Adaptive
+        // sampling stops by itself once every pixel converged or took `max_samples`.
+    fn single_pass(&self) -> RenderBudget {
+        match self.adaptive {
+            Some(adaptive) => RenderBudget::new().with_samples(adaptive.max_samples),
+            None => RenderBudget::new().with_samples(self.pixel_samples).with_pass_samples(self.pixel_samples),
         }
-
-        let progress_bar = ProgressBar::new(points.len() as u64);
-        points.par_iter().map(|(i, j)| {
-            let mut pixel = Color3::zero();
-            for _sample in 0..self.pixel_samples {
-                let ray = self.get_ray(*i, *j);
-                pixel += Self::ray_color(&ray, self.max_bounces, world);
+    }
+        // Every pass adds `pass_samples` samples to each pixel, or a single one for
+        // integrators that `prepare` each pass. With adaptive sampling pixels take as many
+        // as `AdaptiveSampling::samples_for` asks instead, and the render ends once none
+        // asks for more. Threads take tiles in `tile_order`, one at a time. AOV sums are
+        // only kept, and the extra first hit only traced, when `with_aovs`.
+    fn render_layers<F: FnMut(&[Color3], &PassInfo)>(&self, world: &HittableList, with_aovs: bool, budget: &RenderBudget,
+                                                      on_pass: &mut F, on_tile: &(dyn Fn(&TileStats, &[Color3]) + Sync)) -> Layers {
+        let budget = budget.or_samples(self.pixel_samples);
+        let count = (self.im_width * self.im_height) as usize;
+        let mut integrator = self.integrator.clone();
+        let progressive = IntegratorUtil::is_progressive(&integrator);
+        let splats = SplatBuffer::new(self.im_width, self.im_height);
+        let tiles = self.tile_order.tiles(self.im_width, self.im_height, self.tile_size);
+
+        let mut sums = vec![Color3::zero(); count];
+        let mut moments = PixelMoments::new(count);
+        let mut aovs = if with_aovs { vec![AovPixel::new(); count] } else { Vec::new() };
+        let mut tile_stats = Vec::new();
+        let mut image = Vec::new();
+        let mut samples = 0;
+
+        let pass_samples = if progressive { 1 } else { budget.pass_samples };
+        let progress_bar = match (budget.samples, self.adaptive) {
+            (Some(total), None) => ProgressBar::new(total.div_ceil(pass_samples) as u64 * tiles.len() as u64),
+            _ => ProgressBar::no_length(),
+        };
+        let start = Instant::now();
+        for pass in 0.. {
+            if progressive || pass == 0 {
+                MathUtil::seed(MathUtil::hash(&[self.seed, pass as u64]));
+                IntegratorUtil::prepare(&mut integrator, self, world, pass);
             }
-            progress_bar.inc(1);
-            pixel * self.pixel_sample_scale
-        }).collect_into_vec(&mut pixels);
+            let pass_samples = budget.samples.map_or(pass_samples, |total| u32::min(pass_samples, total - samples));
+                // `par_bridge` hands the tiles out in order, unlike splitting the list.
+            let results: Vec<(TileStats, Vec<PixelSamples>)> = tiles.iter().par_bridge().map(|tile| {
+                let (tile_start, tests) = (Instant::now(), HitUtil::intersection_tests());
+                let results: Vec<PixelSamples> = tile.pixels().map(|(i, j)| {
+                    let index = (j * self.im_width + i) as usize;
+                    let pixel_samples = match self.adaptive {
+                        Some(adaptive) if progressive => u32::min(adaptive.samples_for(moments.get(index)), 1),
+                        Some(adaptive) => adaptive.samples_for(moments.get(index)),
+                        None => pass_samples,
+                    };
+                    let first = moments.get(index).count;
+                    self.sample_pixel(&integrator, world, &splats, i, j, first, pixel_samples, with_aovs)
+                }).collect();
+
+                let stats = TileStats {
+                    tile: *tile,
+                    pass: pass + 1,
+                    samples: results.iter().map(|pixel| pixel.moments.count as u64).sum(),
+                    elapsed: tile_start.elapsed(),
+                    intersection_tests: HitUtil::intersection_tests() - tests,
+                };
+                let pixels: Vec<Color3> = tile.pixels().zip(&results).map(|((i, j), pixel)| {
+                    let index = (j * self.im_width + i) as usize;
+                    let pixel_samples = u32::max(moments.get(index).count + pixel.moments.count, 1) as f64;
+                    (&sums[index] + &pixel.color) / pixel_samples
+                }).collect();
+                on_tile(&stats, &pixels);
+                progress_bar.inc(1);
+                (stats, results)
+            }).collect();
+
+            let mut taken = 0;
+            for (stats, results) in results {
+                taken += stats.samples;
+                for ((i, j), pixel) in stats.tile.pixels().zip(results) {
+                    let index = (j * self.im_width + i) as usize;
+                    moments.merge(index, &pixel.moments);
+                    sums[index] += pixel.color;
+                    if let Some(aov) = pixel.aov {
+                        aovs[index].merge(&aov);
+                    }
+                }
+                tile_stats.push(stats);
+            }
+            samples = (moments.samples() / count as u64) as u32;
+
+                // Light paths that reached the camera directly, e.g. from BDPT, are spread
+                // over the image and so count by the mean samples per pixel.
+            let splat_scale = count as f64 / moments.samples() as f64;
+            image = sums.iter().enumerate().map(|(index, sum)| {
+                let pixel_samples = u32::max(moments.get(index).count, 1) as f64;
+                sum / pixel_samples + &splats.get(index) * splat_scale
+            }).collect();
+
+            let info = PassInfo { pass: pass + 1, samples, elapsed: start.elapsed(), noise: moments.relative_error() };
+            on_pass(&image, &info);
+            if budget.is_spent(&info) || (self.adaptive.is_some() && taken == 0) {
+                break;
+            }
+        }
 
-        pixels
+        let splat_scale = count as f64 / moments.samples() as f64;
+        for (index, aov) in aovs.iter_mut().enumerate() {
+            aov.add_splat(&splats.get_split(index).scaled(splat_scale));
+        }
+        tile_stats.sort_by_key(|stats| (stats.pass, stats.tile.index));
+        Layers { image, aovs, tiles: tile_stats }
+    }
+        // Sum and luminance moments of `samples` camera samples through pixel (i, j), which
+        // took `first` samples before.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(&self, integrator: &Integrators, world: &HittableList, splats: &SplatBuffer,
+                    i: u32, j: u32, first: u32, samples: u32, with_aovs: bool) -> PixelSamples {
+        let mut pixel = Color3::zero();
+        let mut moments = Moments::default();
+        let mut aov = if with_aovs { Some(AovPixel::new()) } else { None };
+        for sample in 0..samples {
+            SamplerUtil::start_sample(&self.sampler, self.seed, (i, j), first + sample, (sample, samples));
+            let (ray, weight) = self.get_ray(i, j);
+            let color = match aov.as_mut() {
+                Some(aov) => {
+                    let (split, hit) = IntegratorUtil::li_split(integrator, &ray, self, world, splats);
+                    let split = split.scaled(weight);
+                    aov.add(&ray, hit.as_ref(), &split);
+                    split.total()
+                },
+                None => IntegratorUtil::li(integrator, &ray, self, world, splats) * weight,
+            };
+            moments.add(ColorUtil::luminance(&color));
+            pixel += color;
+        }
+        SamplerUtil::end_sample();
+        PixelSamples { color: pixel, moments, aov }
     }
         // TODO I think there's a better way to do this.
-    pub fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let offset = Self::sample_square();
+    /// Camera ray through pixel (i, j), offset from its center by the pixel filter,
+    /// along with the filter weight of the sample.
+    pub fn get_ray(&self, i: u32, j: u32) -> (Ray, f64) {
+        let (offset_x, offset_y, weight) = self.filter.sample();
         let pixel_sample = &self.px_00_loc
-                    + &((i as f64 + offset.x) * &self.px_delta_u)
-                    + (j as f64 + offset.y) * &self.px_delta_v;
+                    + &((i as f64 + offset_x) * &self.px_delta_u)
+                    + (j as f64 + offset_y) * &self.px_delta_v;
 
         let ray_origin = if self.defocus_angle <= 0.0 { &self.center } else { &self.defocus_disk_sample() };
         let ray_dir = &pixel_sample - ray_origin;
 
-        Ray::new(ray_origin.clone(), ray_dir)
+        (Ray::new(ray_origin.clone(), ray_dir), weight)
+    }
+    /// Point on the lens, for paths that connect to the camera from the scene.
+    pub fn sample_lens(&self) -> Point3 {
+        if self.defocus_angle <= 0.0 { self.center.clone() } else { self.defocus_disk_sample() }
+    }
+    /// Pixel hit by the ray from `origin` on the lens through `p`, if any.
+    pub fn raster(&self, origin: &Point3, p: &Point3) -> Option<(u32, u32)> {
+        let forward = self.forward();
+        let direction = p - origin;
+        let cos_theta = direction.dot(&forward);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let plane_dist = self.focus_dist - (origin - &self.center).dot(&forward);
+        let on_plane = origin + &(plane_dist / cos_theta * &direction);
+        let offset = on_plane - (&self.px_00_loc - &(0.5 * (&self.px_delta_u + &self.px_delta_v)));
+        let x = offset.dot(&self.px_delta_u) / self.px_delta_u.len_sqr();
+        let y = offset.dot(&self.px_delta_v) / self.px_delta_v.len_sqr();
+        if x < 0.0 || y < 0.0 || x >= self.im_width as f64 || y >= self.im_height as f64 {
+            return None;
+        }
+        Some((x as u32, y as u32))
+    }
+    /// Importance the camera gives off along the unit `direction`, the adjoint of
+    /// radiance arriving at the film. Normalized over the whole image.
+    pub fn importance(&self, direction: &Vec3) -> f64 {
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.film_area() * self.lens_area() * cos_theta.powi(4))
+    }
+    /// Lens area and solid angle densities of `get_ray` picking a ray along the unit `direction`.
+    pub fn pdf_ray(&self, direction: &Vec3) -> (f64, f64) {
+        let cos_theta = direction.dot(&self.forward());
+        if cos_theta <= 0.0 {
+            return (0.0, 0.0);
+        }
+        (1.0 / self.lens_area(), 1.0 / (self.film_area() * cos_theta.powi(3)))
+    }
+    pub fn forward(&self) -> Vec3 {
+        self.px_delta_u.cross(&self.px_delta_v).unit()
     }
-    fn sample_square() -> Vec3 {
-        Vec3::new(MathUtil::rand() - 0.5, MathUtil::rand() - 0.5, 0.0)
+    pub fn lens_area(&self) -> f64 {
+            // Pinholes count as a unit area so densities stay finite.
+        if self.defocus_angle <= 0.0 { 1.0 } else { PI * self.defocus_dsk_u.len_sqr() }
+    }
+        // Image area on a plane at unit distance.
+    fn film_area(&self) -> f64 {
+        let width = self.im_width as f64 * self.px_delta_u.len();
+        let height = self.im_height as f64 * self.px_delta_v.len();
+        width * height / (self.focus_dist * self.focus_dist)
     }
     fn defocus_disk_sample(&self) -> Point3 {
         let p = Vec3::random_in_unit_disk();
         &self.center + &(p.x * &self.defocus_dsk_u) + (p.y * &self.defocus_dsk_v)
     }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn max_bounces(&self) -> u32 {
+        self.max_bounces
+    }
     pub fn im_width(&self) -> u32 {
         self.im_width
     }
@@ -215,6 +433,20 @@ impl Camera {
     }
 }
 
+    // What `Camera::render_layers` fills.
+struct Layers {
+    image: Vec<Color3>,
+    aovs: Vec<AovPixel>,
+    tiles: Vec<TileStats>
+}
+
+    // Camera samples one pass took through a pixel.
+struct PixelSamples {
+    color: Color3,          // Sum of the weighted samples
+    moments: Moments,
+    aov: Option<AovPixel>
+}
+
 pub struct CameraBuilder {
     aspect_ratio: f64,
     im_width: u32,
@@ -226,6 +458,13 @@ pub struct CameraBuilder {
     vup: Vec3,
     defocus_angle: f64,
     focus_dist: f64,
+    integrator: Integrators,
+    adaptive: Option<AdaptiveSampling>,
+    sampler: Samplers,
+    seed: u64,
+    filter: Filters,
+    tile_size: u32,
+    tile_order: TileOrders,
 }
 
 impl CameraBuilder {
@@ -241,6 +480,13 @@ impl CameraBuilder {
             vup: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 1.0,
+            integrator: IntegratorFactory::make_path(),
+            adaptive: None,
+            sampler: SamplerFactory::make_independent(),
+            seed: 0,
+            filter: FilterFactory::make_box(0.5),
+            tile_size: 16,
+            tile_order: TileOrders::Spiral,
         }
     }
 
@@ -294,8 +540,44 @@ impl CameraBuilder {
         self
     }
 
+    pub fn integrator(mut self, integrator: Integrators) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn adaptive_sampling(mut self, adaptive: AdaptiveSampling) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn sampler(mut self, sampler: Samplers) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filters) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn tiles(mut self, tile_size: u32, tile_order: TileOrders) -> Self {
+        self.tile_size = tile_size;
+        self.tile_order = tile_order;
+        self
+    }
+
+    pub fn tile_order(mut self, tile_order: TileOrders) -> Self {
+        self.tile_order = tile_order;
+        self
+    }
+
     pub fn build(self) -> Camera {
-        Camera::new(
+        let mut camera = Camera::new(
             self.aspect_ratio,
             self.im_width,
             self.pixel_samples,
@@ -306,6 +588,13 @@ impl CameraBuilder {
             self.vup,
             self.defocus_angle,
             self.focus_dist,
-        )
+        );
+        camera.set_integrator(self.integrator);
+        camera.set_adaptive(self.adaptive);
+        camera.set_sampler(self.sampler);
+        camera.set_seed(self.seed);
+        camera.set_filter(self.filter);
+        camera.set_tiles(self.tile_size, self.tile_order);
+        camera
     }
 }
\ No newline at end of file
@@ -6,6 +6,7 @@ use std::ops::Add;
 use std::ops::Mul;
 use std::ops::Div;
 use std::ops::Index;
+use std::ops::IndexMut;
 use std::ops::AddAssign;
 use std::ops::MulAssign;
 use std::ops::DivAssign;
@@ -50,13 +51,12 @@ impl Vec3 {
         Self::new(MathUtil::rand_ran(min, max), MathUtil::rand_ran(min, max), MathUtil::rand_ran(min, max))
     }
     pub fn random_unit() -> Self {
-        loop {
-            let p = Self::random_ran(-1.0, 1.0);
-            let lensq = p.len_sqr();
-            if 1e-160 < lensq && lensq <= 1.0 {  // Smaller than 1e-160 underflows to zero when squared.
-                 return p / f64::sqrt(lensq);
-            }
-        }
+        // Uniform in z and in the angle around Z, which is uniform on the sphere and
+        // always takes two random numbers, unlike rejection.
+        let z = 1.0 - 2.0 * MathUtil::rand();
+        let phi = 2.0 * f64::consts::PI * MathUtil::rand();
+        let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+        Self::new(r * f64::cos(phi), r * f64::sin(phi), z)
     }
     pub fn random_on_hemisphere(normal: &Vec3) -> Self {
         let on_unit_sphere = Self::random_unit();
@@ -67,12 +67,26 @@ impl Vec3 {
         }
     }
     pub fn random_in_unit_disk() -> Self {
-        loop {
-            let p = Vec3::new(MathUtil::rand_ran(-1.0, 1.0), MathUtil::rand_ran(-1.0, 1.0), 0.0);
-            if p.len_sqr() < 1.0 {
-                return p;
-            }
+        // Concentric mapping (Shirley and Chiu 1997), which keeps the two random numbers
+        // stratified, unlike rejection.
+        let (a, b) = (MathUtil::rand_ran(-1.0, 1.0), MathUtil::rand_ran(-1.0, 1.0));
+        if a == 0.0 && b == 0.0 {
+            return Vec3::zero();
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, f64::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (b, f64::consts::FRAC_PI_2 - f64::consts::FRAC_PI_4 * (a / b))
+        };
+        Vec3::new(r * f64::cos(theta), r * f64::sin(theta), 0.0)
+    }
+    pub fn random_cosine_direction() -> Self {
+        // Cosine-weighted direction around +Z.
+        let r1 = MathUtil::rand();
+        let r2 = MathUtil::rand();
+        let phi = 2.0 * f64::consts::PI * r1;
+        let r = f64::sqrt(r2);
+        Vec3::new(f64::cos(phi) * r, f64::sin(phi) * r, f64::sqrt(1.0 - r2))
     }
     pub fn len_sqr(&self) -> f64 {
         self.x * self.x + self.y * self.y + self.z * self.z
@@ -124,6 +138,17 @@ impl Index<usize> for Vec3 {
     }
 }
 
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        if index == 0 { &mut self.x }
+        else if index == 1 { &mut self.y }
+        else if index == 2 { &mut self.z }
+        else {
+            panic!("Index out of bounds: {index}")
+        }
+    }
+}
+
 impl Add for Vec3 {
     type Output = Vec3;
 
@@ -265,22 +290,160 @@ pub trait Hittable {
     fn hit(&self, ray: &Ray, t_i: &Interval) -> Option<HitRecord>;
 }
 
+/// Shapes that can be sampled directly, so they can act as area lights.
+pub trait Sampleable {
+    /// Picks a point on the surface as seen from `origin`.
+    fn sample(&self, origin: &Point3) -> Option<SurfaceSample>;
+    /// Density of `sample` picking the point in `hit_rec` from `origin`.
+    fn pdf(&self, origin: &Point3, hit_rec: &HitRecord) -> f64;
+    /// Picks a point uniformly by area, normal facing outwards.
+    fn sample_area(&self) -> HitRecord;
+    fn area(&self) -> f64;
+    fn bounds(&self) -> Aabb;
+}
+
+pub struct SurfaceSample {
+    pub hit_rec: HitRecord,     // Sampled point, normal facing `origin`
+    pub pdf: f64                // Solid angle density at `origin`
+}
+impl SurfaceSample {
+        // Converts an area density into a solid angle density seen from `origin`.
+    pub fn area_to_solid_angle(pdf_area: f64, origin: &Point3, p: &Point3, normal: &Vec3) -> f64 {
+        let to_point = p - origin;
+        let dist_sqr = to_point.len_sqr();
+        let cos_theta = normal.dot(&to_point).abs() / f64::sqrt(dist_sqr);
+        if cos_theta <= 0.0 { 0.0 } else { pdf_area * dist_sqr / cos_theta }
+    }
+}
+
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
+    pub shading_normal: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
     pub material: Materials,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    pub object_id: usize,
     pub is_front_face: bool
 }
 impl HitRecord {
     pub fn new(p: Point3, normal: Vec3, t: f64, material: Materials) -> Self {
-        Self { p, normal, material, t, is_front_face: false }
+        let frame = Onb::from_normal(&normal);
+        Self { p, shading_normal: normal.clone(), normal, tangent: frame.u, bitangent: frame.v,
+               material, t, u: 0.0, v: 0.0, object_id: 0, is_front_face: false }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
                 // Sets the hit record normal vector.
                 // outward_normal is assumed to have unit length.
         self.is_front_face = ray.direction().dot(outward_normal) < 0.0;
         self.normal = if self.is_front_face { outward_normal.clone() } else { -outward_normal };
+        self.shading_normal = self.normal.clone();
+        self.set_tangent(&self.tangent.clone());
+    }
+    /// Sets a perturbed shading normal for a hit seen along `wo`, pointing away
+    /// from the surface. The normal is bent towards `normal` where needed so that
+    /// `wo` lies above it and mirrors about it to above the geometric surface.
+    pub fn set_shading_normal(&mut self, shading_normal: &Vec3, wo: &Vec3) {
+                // Only used for shading; `normal` stays geometric for face
+                // orientation and self-intersection checks.
+        self.shading_normal = Self::visible_normal(&self.normal, shading_normal, &wo.unit());
+        self.set_tangent(&self.tangent.clone());
+    }
+        // Same idea as Cycles' `ensure_valid_reflection`, solved by bisection on how far
+        // the shading normal is blended towards the geometric one.
+    fn visible_normal(normal: &Vec3, shading_normal: &Vec3, wo: &Vec3) -> Vec3 {
+        let threshold = f64::min(0.9 * wo.dot(normal), 0.01);
+        let is_valid = |n: &Vec3| {
+            let reflected = &(2.0 * n.dot(wo) * n) - wo;
+            n.dot(wo) > 0.0 && reflected.dot(normal) >= threshold
+        };
+        let shading_normal = if shading_normal.dot(normal) < 0.0 {
+            shading_normal - &(2.0 * shading_normal.dot(normal) * normal)
+        } else {
+            shading_normal.clone()
+        };
+        if is_valid(&shading_normal) {
+            return shading_normal;
+        }
+        let blend = |t: f64| ((1.0 - t) * &shading_normal + t * normal).unit();
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..20 {
+            let mid = 0.5 * (low + high);
+            if is_valid(&blend(mid)) { high = mid } else { low = mid }
+        }
+        blend(high)
+    }
+    pub fn set_uv(&mut self, u: f64, v: f64) {
+        self.u = u;
+        self.v = v;
+    }
+    pub fn set_tangent(&mut self, tangent: &Vec3) {
+                // Gram-Schmidt against the normal; falls back to an arbitrary
+                // frame when the tangent is degenerate (e.g. at sphere poles).
+        let projected = tangent - &(&self.shading_normal * self.shading_normal.dot(tangent));
+        let frame = if projected.len_sqr() > 1e-12 {
+            Onb::new(&self.shading_normal, &projected)
+        } else {
+            Onb::from_normal(&self.shading_normal)
+        };
+        self.tangent = frame.u;
+        self.bitangent = frame.v;
+    }
+    /// Copy with the normals facing `origin`, to shade the surface from a side
+    /// other than the one it was hit from.
+    pub fn facing(&self, origin: &Point3) -> HitRecord {
+        let mut hit_rec = self.clone();
+        if (origin - &self.p).dot(&self.normal) < 0.0 {
+            hit_rec.normal = -&self.normal;
+            hit_rec.shading_normal = -&self.shading_normal;
+            hit_rec.bitangent = -&self.bitangent;
+            hit_rec.is_front_face = !self.is_front_face;
+        }
+        hit_rec
+    }
+    pub fn frame(&self) -> Onb {
+        Onb { u: self.tangent.clone(), v: self.bitangent.clone(), w: self.shading_normal.clone() }
+    }
+}
+
+
+/// Orthonormal basis with `w` along the surface normal.
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3
+}
+impl Onb {
+    pub fn new(normal: &Vec3, tangent: &Vec3) -> Self {
+        let w = normal.unit();
+        let u = tangent.unit();
+        let v = w.cross(&u);
+        Self { u, v, w }
+    }
+    pub fn from_normal(normal: &Vec3) -> Self {
+                // Duff et al. 2017, branchless basis construction.
+        let sign = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+        let a = -1.0 / (sign + normal.z);
+        let b = normal.x * normal.y * a;
+        let u = Vec3::new(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x);
+        let v = Vec3::new(b, sign + normal.y * normal.y * a, -normal.y);
+        Self { u, v, w: normal.clone() }
+    }
+    pub fn rotated(&self, angle: f64) -> Self {
+        let (sin, cos) = f64::sin_cos(angle);
+        let u = cos * &self.u + sin * &self.v;
+        let v = self.w.cross(&u);
+        Self { u, v, w: self.w.clone() }
+    }
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        a * &self.u + b * &self.v + c * &self.w
+    }
+    pub fn to_local(&self, dir: &Vec3) -> Vec3 {
+        Vec3::new(dir.dot(&self.u), dir.dot(&self.v), dir.dot(&self.w))
     }
 }
 
@@ -312,3 +475,41 @@ impl Interval {
     pub const HIT_EVAL: Interval = Self { min: 0.001, max: f64::INFINITY };
 }
 
+
+
+/// Axis aligned bounding box.
+#[derive(Clone, Debug)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3
+}
+impl Aabb {
+    pub fn new(a: &Point3, b: &Point3) -> Self {
+        Self {
+            min: Point3::new(f64::min(a.x, b.x), f64::min(a.y, b.y), f64::min(a.z, b.z)),
+            max: Point3::new(f64::max(a.x, b.x), f64::max(a.y, b.y), f64::max(a.z, b.z))
+        }
+    }
+    pub fn empty() -> Self {
+        Self { min: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+               max: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY) }
+    }
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut union = self.clone();
+        for axis in 0..3 {
+            union.min[axis] = f64::min(self.min[axis], other.min[axis]);
+            union.max[axis] = f64::max(self.max[axis], other.max[axis]);
+        }
+        union
+    }
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (&self.min + &self.max)
+    }
+    pub fn diagonal(&self) -> Vec3 {
+        &self.max - &self.min
+    }
+    pub fn longest_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x >= d.y && d.x >= d.z { 0 } else if d.y >= d.z { 1 } else { 2 }
+    }
+}
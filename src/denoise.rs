@@ -0,0 +1,86 @@
+use rayon::prelude::*;
+
+use crate::aovs::Aovs;
+use crate::aovs::AovKinds;
+use crate::datatypes::Color3;
+
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). Repeated 5x5 B3-spline
+/// blurs with doubling holes between taps, where each tap is down-weighted by how
+/// much its color, normal and albedo differ from the center pixel. The image is
+/// divided by albedo first so textures stay sharp and only lighting gets smoothed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Denoiser {
+    pub iterations: u32,        // Passes, the footprint is 4 * 2^iterations pixels wide
+    pub sigma_color: f64,       // Lighting difference tolerated, halved every pass
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64
+}
+#[allow(unused)]
+impl Denoiser {
+    const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+    pub fn new() -> Self {
+        Denoiser { iterations: 5, sigma_color: 1.0, sigma_normal: 0.3, sigma_albedo: 0.1 }
+    }
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+    pub fn with_sigmas(mut self, color: f64, normal: f64, albedo: f64) -> Self {
+        self.sigma_color = color;
+        self.sigma_normal = normal;
+        self.sigma_albedo = albedo;
+        self
+    }
+    /// Filters `pixels` guided by the albedo and normal buffers of `aovs`.
+    pub fn denoise_aovs(&self, pixels: &[Color3], aovs: &Aovs, width: u32, height: u32) -> Vec<Color3> {
+        self.denoise(pixels, aovs.layer(AovKinds::Albedo), aovs.layer(AovKinds::Normal), width, height)
+    }
+    pub fn denoise(&self, pixels: &[Color3], albedo: &[Color3], normal: &[Color3], width: u32, height: u32) -> Vec<Color3> {
+        let mut lighting: Vec<Color3> = pixels.iter().zip(albedo).map(|(c, a)| Self::demodulate(c, a)).collect();
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let sigma_color = self.sigma_color / (1 << iteration) as f64;
+            lighting = (0..lighting.len()).into_par_iter().map(|index| {
+                self.filter_pixel(&lighting, albedo, normal, width, height, index, step, sigma_color)
+            }).collect();
+        }
+        lighting.iter().zip(albedo).map(|(l, a)| Self::remodulate(l, a)).collect()
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn filter_pixel(&self, lighting: &[Color3], albedo: &[Color3], normal: &[Color3], width: u32, height: u32,
+                    index: usize, step: i64, sigma_color: f64) -> Color3 {
+        let (i, j) = ((index as u32 % width) as i64, (index as u32 / width) as i64);
+        let mut sum = Color3::zero();
+        let mut weights = 0.0;
+        for (dj, kj) in Self::KERNEL.iter().enumerate() {
+            for (di, ki) in Self::KERNEL.iter().enumerate() {
+                let (x, y) = (i + (di as i64 - 2) * step, j + (dj as i64 - 2) * step);
+                if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                    continue;
+                }
+                let tap = (y * width as i64 + x) as usize;
+                let weight = kj * ki
+                    * Self::edge_stop(&lighting[index], &lighting[tap], sigma_color)
+                    * Self::edge_stop(&normal[index], &normal[tap], self.sigma_normal)
+                    * Self::edge_stop(&albedo[index], &albedo[tap], self.sigma_albedo);
+                sum += &lighting[tap] * weight;
+                weights += weight;
+            }
+        }
+            // The center tap always has weight, so `weights` is never zero.
+        sum / weights
+    }
+    fn edge_stop(center: &Color3, tap: &Color3, sigma: f64) -> f64 {
+        f64::exp(-(center - tap).len_sqr() / (sigma * sigma))
+    }
+    fn demodulate(color: &Color3, albedo: &Color3) -> Color3 {
+        let divide = |c: f64, a: f64| if a > 1e-3 { c / a } else { c };
+        Color3::new(divide(color.x, albedo.x), divide(color.y, albedo.y), divide(color.z, albedo.z))
+    }
+    fn remodulate(lighting: &Color3, albedo: &Color3) -> Color3 {
+        let multiply = |l: f64, a: f64| if a > 1e-3 { l * a } else { l };
+        Color3::new(multiply(lighting.x, albedo.x), multiply(lighting.y, albedo.y), multiply(lighting.z, albedo.z))
+    }
+}
@@ -0,0 +1,208 @@
+use std::f64::consts::PI;
+
+use crate::utils::FilterUtil;
+use crate::utils::MathUtil;
+
+
+/// Pixel reconstruction filters: how much a sample at an offset from the pixel center
+/// counts towards the pixel. All of them are separable, `evaluate` gives one axis.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filters {
+    Box(FltBox),
+    Tent(FltTent),
+    Gaussian(FltGaussian),
+    Mitchell(FltMitchell),
+    Lanczos(FltLanczos)
+}
+
+
+pub struct FilterFactory;
+#[allow(unused)]
+impl FilterFactory {
+    pub fn make_box(radius: f64) -> Filters {
+        Filters::Box(FltBox { radius })
+    }
+    pub fn make_tent(radius: f64) -> Filters {
+        Filters::Tent(FltTent { radius })
+    }
+    pub fn make_gaussian(radius: f64, sigma: f64) -> Filters {
+        Filters::Gaussian(FltGaussian { radius, sigma })
+    }
+    pub fn make_mitchell(radius: f64, b: f64, c: f64) -> Filters {
+        Filters::Mitchell(FltMitchell { radius, b, c })
+    }
+    pub fn make_lanczos(radius: f64) -> Filters {
+        Filters::Lanczos(FltLanczos { radius })
+    }
+    /// Filter picked by name on the command line, with its usual radius.
+    pub fn from_name(name: &str) -> Option<Filters> {
+        match name {
+            "box" => Some(Self::make_box(0.5)),
+            "tent" => Some(Self::make_tent(1.0)),
+            "gaussian" => Some(Self::make_gaussian(1.5, 0.5)),
+            "mitchell" => Some(Self::make_mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0)),
+            "lanczos" => Some(Self::make_lanczos(3.0)),
+            _ => None,
+        }
+    }
+}
+
+
+pub trait Filter {
+    /// Half width of the filter, in pixels.
+    fn radius(&self) -> f64;
+    /// Weight of a sample `x` pixels from the center along one axis.
+    fn evaluate(&self, x: f64) -> f64;
+}
+
+
+/// Every sample inside the pixel counts the same.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FltBox {
+    pub radius: f64
+}
+impl Filter for FltBox {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64) -> f64 {
+        if x.abs() <= self.radius { 1.0 } else { 0.0 }
+    }
+}
+
+
+/// Weight falling off linearly to zero at `radius`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FltTent {
+    pub radius: f64
+}
+impl Filter for FltTent {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64) -> f64 {
+        f64::max(self.radius - x.abs(), 0.0)
+    }
+}
+
+
+/// Gaussian, shifted down so it reaches zero at `radius`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FltGaussian {
+    pub radius: f64,
+    pub sigma: f64
+}
+impl FltGaussian {
+    fn gaussian(&self, x: f64) -> f64 {
+        f64::exp(-x * x / (2.0 * self.sigma * self.sigma))
+    }
+}
+impl Filter for FltGaussian {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64) -> f64 {
+        f64::max(self.gaussian(x) - self.gaussian(self.radius), 0.0)
+    }
+}
+
+
+/// Mitchell-Netravali cubic (1988), stretched over `radius`. `b` = `c` = 1/3 is their
+/// recommended balance of blurring and ringing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FltMitchell {
+    pub radius: f64,
+    pub b: f64,
+    pub c: f64
+}
+impl Filter for FltMitchell {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x / self.radius).abs();
+        let weight = if x < 1.0 {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+        } else if x < 2.0 {
+            (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+        } else {
+            0.0
+        };
+        weight / 6.0
+    }
+}
+
+
+/// Sinc windowed by a wider sinc, with `radius` lobes on each side.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FltLanczos {
+    pub radius: f64
+}
+impl FltLanczos {
+    fn sinc(x: f64) -> f64 {
+        if x.abs() < 1e-5 { 1.0 } else { f64::sin(PI * x) / (PI * x) }
+    }
+}
+impl Filter for FltLanczos {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate(&self, x: f64) -> f64 {
+        if x.abs() > self.radius { 0.0 } else { Self::sinc(x) * Self::sinc(x / self.radius) }
+    }
+}
+
+
+/// Filter importance sampling: sample offsets are drawn in proportion to the filter's
+/// magnitude, from a table per axis, so each sample only needs a weight close to ±1
+/// instead of splatting to its neighbours. The weights average to 1 over the pixel,
+/// so pixels stay plain means of weighted samples. A filter without any area, like a
+/// zero radius or sigma, acts as a delta: every sample at the center with weight 1.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FilterSampler {
+    filter: Filters,
+    radius: f64,
+    cdf: Vec<f64>,          // Over the table's bins of |filter|, from 0 to 1, empty for a delta
+    integral: f64           // Integral of the filter over one axis
+}
+impl FilterSampler {
+    const BINS: usize = 64;
+
+    pub fn new(filter: Filters) -> Self {
+        let radius = FilterUtil::radius(&filter);
+        let width = 2.0 * radius / Self::BINS as f64;
+        let values: Vec<f64> = (0..Self::BINS).map(|bin| FilterUtil::evaluate(&filter, -radius + (bin as f64 + 0.5) * width)).collect();
+        let abs_integral = values.iter().map(|v| v.abs()).sum::<f64>() * width;
+        let integral = values.iter().sum::<f64>() * width;
+        if radius <= 0.0 || !integral.is_finite() || integral <= 0.0 {
+            return FilterSampler { filter, radius: 0.0, cdf: Vec::new(), integral: 1.0 };
+        }
+        let mut cdf = vec![0.0; Self::BINS + 1];
+        for (bin, value) in values.iter().enumerate() {
+            cdf[bin + 1] = cdf[bin] + value.abs() * width / abs_integral;
+        }
+        FilterSampler { filter, radius, cdf, integral }
+    }
+    /// Offset from the pixel center in [-radius, radius]² and the sample's weight.
+    pub fn sample(&self) -> (f64, f64, f64) {
+        let (x, weight_x) = self.sample_axis(MathUtil::rand());
+        let (y, weight_y) = self.sample_axis(MathUtil::rand());
+        (x, y, weight_x * weight_y)
+    }
+    fn sample_axis(&self, u: f64) -> (f64, f64) {
+        if self.cdf.is_empty() {
+            return (0.0, 1.0);
+        }
+        let bin = self.cdf.partition_point(|c| *c <= u).clamp(1, Self::BINS) - 1;
+        let (low, high) = (self.cdf[bin], self.cdf[bin + 1]);
+        let t = if high > low { (u - low) / (high - low) } else { 0.5 };
+        let width = 2.0 * self.radius / Self::BINS as f64;
+        let x = -self.radius + (bin as f64 + t) * width;
+        let pdf = (high - low) / width;
+        if pdf <= 0.0 {
+            return (x, 0.0);
+        }
+        (x, FilterUtil::evaluate(&self.filter, x) / (pdf * self.integral))
+    }
+}
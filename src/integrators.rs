@@ -0,0 +1,458 @@
+use crate::aovs::LightSplit;
+use crate::bdpt::Bdpt;
+use crate::bdpt::SplatBuffer;
+use crate::camera::Camera;
+use crate::photons::PhotonMap;
+use crate::photons::PhotonMapper;
+use crate::datatypes::Ray;
+use crate::datatypes::Vec3;
+use crate::datatypes::Color3;
+use crate::datatypes::Interval;
+use crate::datatypes::Hittable;
+use crate::datatypes::HitRecord;
+use crate::light_tree::LightPowerTable;
+use crate::materials::Materials;
+use crate::shapes::Hittables;
+use crate::shapes::HittableList;
+use crate::utils::HitUtil;
+use crate::utils::ColorUtil;
+use crate::utils::MatUtil;
+use crate::utils::LightUtil;
+use crate::utils::SkyUtil;
+use crate::utils::MathUtil;
+
+
+/// Light transport algorithms `Camera::render` can drive.
+#[derive(Clone)]
+pub enum Integrators {
+    Path(IntPath),
+    Whitted(IntWhitted),
+    Bidirectional(IntBidirectional),
+    PhotonMapping(IntPhotonMapping),
+    AmbientOcclusion(IntAmbientOcclusion),
+    Debug(IntDebug)
+}
+
+
+pub struct IntegratorFactory;
+#[allow(unused)]
+impl IntegratorFactory {
+    pub fn make_path() -> Integrators {
+        Integrators::Path(IntPath)
+    }
+    pub fn make_whitted() -> Integrators {
+        Integrators::Whitted(IntWhitted)
+    }
+    pub fn make_bidirectional() -> Integrators {
+        Integrators::Bidirectional(IntBidirectional::new())
+    }
+    pub fn make_photon_mapping(photons: usize, radius: f64) -> Integrators {
+        Integrators::PhotonMapping(IntPhotonMapping::new(photons, radius))
+    }
+    pub fn make_ambient_occlusion(radius: f64) -> Integrators {
+        Integrators::AmbientOcclusion(IntAmbientOcclusion::new(radius))
+    }
+    pub fn make_debug(mode: DebugModes) -> Integrators {
+        Integrators::Debug(IntDebug::new(mode))
+    }
+    /// Integrator picked by name on the command line, with default settings.
+    pub fn from_name(name: &str) -> Option<Integrators> {
+        match name {
+            "path" => Some(Self::make_path()),
+            "whitted" => Some(Self::make_whitted()),
+            "bdpt" => Some(Self::make_bidirectional()),
+            "photons" => Some(Self::make_photon_mapping(200_000, 0.05)),
+            "ao" => Some(Self::make_ambient_occlusion(1.0)),
+            "normals" => Some(Self::make_debug(DebugModes::Normal)),
+            "depth" => Some(Self::make_debug(DebugModes::Depth { far: 20.0 })),
+            "uv" => Some(Self::make_debug(DebugModes::Uv)),
+            "barycentric" => Some(Self::make_debug(DebugModes::Barycentric)),
+            "material" => Some(Self::make_debug(DebugModes::MaterialId)),
+            "cost" => Some(Self::make_debug(DebugModes::IntersectionCost { max_tests: 200.0 })),
+            _ => None,
+        }
+    }
+}
+
+
+pub trait Integrator {
+    /// Sets up what every sample of pass `pass` shares. Called once per render, or
+    /// before every pass when `is_progressive`.
+    fn prepare(&mut self, _camera: &Camera, _world: &HittableList, _pass: u32) {}
+    /// Whether each sample pass needs its own `prepare`.
+    fn is_progressive(&self) -> bool {
+        false
+    }
+    /// Radiance arriving at the camera along `ray`. Light that lands on other
+    /// pixels goes to `splats`.
+    fn li(&self, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> Color3;
+    /// `li` split into emission, direct and indirect light, along with the first hit
+    /// of `ray`. Views that aren't light transport, like ambient occlusion, report it
+    /// all as indirect.
+    fn li_split(&self, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>);
+}
+
+
+/// How much longer a path may get. Steps of a random walk inside a medium (see
+/// `MatSubsurface`) have their own cap, since one walk easily takes more steps
+/// than a whole path has bounces.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PathDepth {
+    pub bounces: u32,
+    pub medium_steps: u32
+}
+impl PathDepth {
+    pub const MEDIUM_STEPS: u32 = 256;
+
+    pub fn new(bounces: u32) -> Self {
+        PathDepth { bounces, medium_steps: Self::MEDIUM_STEPS }
+    }
+    pub fn is_spent(&self) -> bool {
+        self.bounces == 0 || self.medium_steps == 0
+    }
+    /// Depth left after scattering off `material` at `hit_rec`.
+    pub fn next(&self, material: &Materials, hit_rec: &HitRecord) -> Self {
+        if MatUtil::is_medium_step(material, hit_rec) {
+            PathDepth { medium_steps: self.medium_steps - 1, ..*self }
+        }
+        else {
+            PathDepth { bounces: self.bounces - 1, ..*self }
+        }
+    }
+}
+
+
+/// Unidirectional path tracer with next event estimation. One light is picked per
+/// bounce by the light tree, and area lights are weighted against BSDF sampling.
+#[derive(Clone)]
+pub struct IntPath;
+#[allow(unused)]
+impl IntPath {
+    pub fn ray_color(ray: &Ray, bounces: u32, world: &HittableList) -> Color3 {
+        Self::trace(ray, PathDepth::new(bounces), world, None).total()
+    }
+        // `bsdf_pdf` is the density the previous vertex sampled `ray` with. It is
+        // `None` for camera rays and specular bounces, which never sample the lights,
+        // so emitters they hit are counted in full.
+    fn trace(ray: &Ray, depth: PathDepth, world: &HittableList, bsdf_pdf: Option<f64>) -> LightSplit {
+        if depth.is_spent() {
+            return LightSplit::zero();
+        }
+        Self::shade(ray, world.hit(ray, &Interval::HIT_EVAL).as_ref(), depth, world, bsdf_pdf)
+    }
+        // `trace` once `ray` is known to first hit `hit`.
+    fn shade(ray: &Ray, hit: Option<&HitRecord>, depth: PathDepth, world: &HittableList, bsdf_pdf: Option<f64>) -> LightSplit {
+        if depth.is_spent() {
+            return LightSplit::zero();
+        }
+
+        match hit {
+            Some(hr) => {
+                let material = MatUtil::resolve(&hr.material, hr);
+                let direct = Self::sample_light(ray, hr, material, world);
+                match MatUtil::scatter(material, ray, hr) {
+                    Some((att, sc_ray)) => {
+                        match sc_ray {
+                            Some(sc_ray) => {
+                                let pdf = if MatUtil::is_specular(material) {
+                                    None
+                                }
+                                else {
+                                    Some(MatUtil::pdf(material, ray, hr, sc_ray.direction()))
+                                };
+                                let next = Self::trace(&sc_ray, depth.next(material, hr), world, pdf);
+                                LightSplit {
+                                    emission: Color3::zero(),
+                                    direct: direct + att.clone() * next.emission,
+                                    indirect: att * (next.direct + next.indirect),
+                                }
+                            },
+                            None => LightSplit { emission: att * Self::emitter_weight(ray, hr, world, bsdf_pdf), ..LightSplit::zero() },
+                        }
+                    },
+                    None => LightSplit { direct, ..LightSplit::zero() },
+                }
+            },
+            None => {
+                    // Sky box
+                let mut sky = SkyUtil::radiance(&world.sky, ray.direction());
+                if bsdf_pdf.is_none() {
+                    for light in world.lights() {
+                        sky += LightUtil::le(light, ray.direction());
+                    }
+                }
+                LightSplit { emission: sky, ..LightSplit::zero() }
+            },
+        }
+    }
+    /// MIS weight of an emitter found by a BSDF sample, against it being sampled as a light.
+    pub fn emitter_weight(ray: &Ray, hit_rec: &HitRecord, world: &HittableList, bsdf_pdf: Option<f64>) -> f64 {
+        match (bsdf_pdf, world.light_index(hit_rec)) {
+            (Some(bsdf_pdf), Some(index)) => {
+                let pmf = world.light_tree().pmf(ray.origin(), index);
+                let light_pdf = pmf * LightUtil::pdf_li(&world.lights()[index], ray.origin(), hit_rec);
+                MathUtil::power_heuristic(bsdf_pdf, light_pdf)
+            },
+            _ => 1.0,
+        }
+    }
+    /// Direct light from one light picked by the light tree. Area lights are
+    /// weighted against the BSDF finding them on its own.
+    pub fn sample_light(ray: &Ray, hit_rec: &HitRecord, material: &Materials, world: &HittableList) -> Color3 {
+        if MatUtil::is_specular(material) {
+            return Color3::zero();
+        }
+        let Some((index, pmf)) = world.light_tree().sample(&hit_rec.p) else {
+            return Color3::zero();
+        };
+        let Some(sample) = LightUtil::sample_li(&world.lights()[index], &hit_rec.p) else {
+            return Color3::zero();
+        };
+        let cos_theta = sample.direction.dot(&hit_rec.shading_normal).abs();
+        let f = MatUtil::eval(material, ray, hit_rec, &sample.direction);
+        if f.is_near_zero() || cos_theta <= 0.0 {
+            return Color3::zero();
+        }
+        let shadow_ray = Ray::new(hit_rec.p.clone(), sample.direction.clone());
+        if world.hit(&shadow_ray, &Interval::new(Interval::HIT_EVAL.min, sample.distance * (1.0 - 1e-6))).is_some() {
+            return Color3::zero();
+        }
+        let light_pdf = pmf * sample.pdf;
+        let weight = if sample.is_area {
+            MathUtil::power_heuristic(light_pdf, MatUtil::pdf(material, ray, hit_rec, &sample.direction))
+        }
+        else {
+            1.0
+        };
+        f * sample.radiance * (weight * cos_theta / light_pdf)
+    }
+}
+impl Integrator for IntPath {
+    fn li(&self, ray: &Ray, camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> Color3 {
+        Self::ray_color(ray, camera.max_bounces(), world)
+    }
+    fn li_split(&self, ray: &Ray, camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        let hit = world.hit(ray, &Interval::HIT_EVAL);
+        (Self::shade(ray, hit.as_ref(), PathDepth::new(camera.max_bounces()), world, None), hit)
+    }
+}
+
+
+/// Whitted style ray tracer: mirror and glass bounces are followed, every other
+/// surface only takes direct light from each scene light. No indirect diffuse light.
+#[derive(Clone)]
+pub struct IntWhitted;
+impl IntWhitted {
+    fn trace(ray: &Ray, depth: PathDepth, world: &HittableList) -> LightSplit {
+        if depth.is_spent() {
+            return LightSplit::zero();
+        }
+        Self::shade(ray, world.hit(ray, &Interval::HIT_EVAL).as_ref(), depth, world)
+    }
+        // `trace` once `ray` is known to first hit `hit`.
+    fn shade(ray: &Ray, hit: Option<&HitRecord>, depth: PathDepth, world: &HittableList) -> LightSplit {
+        if depth.is_spent() {
+            return LightSplit::zero();
+        }
+
+        let Some(hr) = hit else {
+            let mut sky = SkyUtil::radiance(&world.sky, ray.direction());
+            for light in world.lights() {
+                sky += LightUtil::le(light, ray.direction());
+            }
+            return LightSplit { emission: sky, ..LightSplit::zero() };
+        };
+
+        let material = MatUtil::resolve(&hr.material, hr);
+        if MatUtil::is_specular(material) {
+            return match MatUtil::scatter(material, ray, hr) {
+                Some((att, Some(sc_ray))) => {
+                    let next = Self::trace(&sc_ray, depth.next(material, hr), world);
+                    LightSplit { emission: Color3::zero(), direct: att.clone() * next.emission, indirect: att * (next.direct + next.indirect) }
+                },
+                Some((att, None)) => LightSplit { emission: att, ..LightSplit::zero() },
+                None => LightSplit::zero(),
+            };
+        }
+
+        let mut direct = Color3::zero();
+        for light in world.lights() {
+            let Some(sample) = LightUtil::sample_li(light, &hr.p) else {
+                continue;
+            };
+            let cos_theta = sample.direction.dot(&hr.shading_normal).abs();
+            let f = MatUtil::eval(material, ray, hr, &sample.direction);
+            if f.is_near_zero() || cos_theta <= 0.0 || sample.pdf <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray::new(hr.p.clone(), sample.direction.clone());
+            if world.hit(&shadow_ray, &Interval::new(Interval::HIT_EVAL.min, sample.distance * (1.0 - 1e-6))).is_some() {
+                continue;
+            }
+            direct += f * sample.radiance * (cos_theta / sample.pdf);
+        }
+        LightSplit { direct, ..LightSplit::zero() }
+    }
+}
+impl Integrator for IntWhitted {
+    fn li(&self, ray: &Ray, camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> Color3 {
+        Self::trace(ray, PathDepth::new(camera.max_bounces()), world).total()
+    }
+    fn li_split(&self, ray: &Ray, camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        let hit = world.hit(ray, &Interval::HIT_EVAL);
+        (Self::shade(ray, hit.as_ref(), PathDepth::new(camera.max_bounces()), world), hit)
+    }
+}
+
+
+/// Bidirectional path tracer, see `Bdpt`.
+#[derive(Clone)]
+pub struct IntBidirectional {
+    power: LightPowerTable
+}
+impl IntBidirectional {
+    pub fn new() -> Self {
+        IntBidirectional { power: LightPowerTable::new(&[]) }
+    }
+}
+impl Integrator for IntBidirectional {
+    fn prepare(&mut self, _camera: &Camera, world: &HittableList, _pass: u32) {
+        self.power = LightPowerTable::new(world.lights());
+    }
+    fn li(&self, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> Color3 {
+        Bdpt::new(camera, world, &self.power, camera.max_bounces()).li(ray, splats)
+    }
+    fn li_split(&self, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        Bdpt::new(camera, world, &self.power, camera.max_bounces()).li_split(ray, splats)
+    }
+}
+
+
+/// Progressive photon mapping, see `PhotonMapper`. Every pass shoots `photons`
+/// into a fresh map; the first one gathers them within `radius`.
+#[derive(Clone)]
+pub struct IntPhotonMapping {
+    pub photons: usize,
+    pub radius: f64,
+    power: LightPowerTable,
+    map: PhotonMap,
+    radius_sqr: f64
+}
+impl IntPhotonMapping {
+    pub fn new(photons: usize, radius: f64) -> Self {
+        IntPhotonMapping { photons, radius, power: LightPowerTable::new(&[]), map: PhotonMap::new(Vec::new()),
+                           radius_sqr: radius * radius }
+    }
+}
+impl Integrator for IntPhotonMapping {
+    fn prepare(&mut self, camera: &Camera, world: &HittableList, pass: u32) {
+        if pass == 0 {
+            self.power = LightPowerTable::new(world.lights());
+        }
+        let seed = MathUtil::hash(&[camera.seed(), pass as u64]);
+        self.map = PhotonMapper::new(world, &self.power, camera.max_bounces()).shoot(self.photons, seed);
+        self.radius_sqr = PhotonMapper::radius_sqr(self.radius, pass);
+    }
+    fn is_progressive(&self) -> bool {
+        true
+    }
+    fn li(&self, ray: &Ray, camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> Color3 {
+        PhotonMapper::new(world, &self.power, camera.max_bounces()).li(ray, &self.map, self.radius_sqr)
+    }
+    fn li_split(&self, ray: &Ray, camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        PhotonMapper::new(world, &self.power, camera.max_bounces()).li_split(ray, &self.map, self.radius_sqr)
+    }
+}
+
+
+/// Ambient occlusion: the share of cosine weighted directions above the first hit
+/// that leave without meeting anything closer than `radius`.
+#[derive(Clone)]
+pub struct IntAmbientOcclusion {
+    pub radius: f64
+}
+impl IntAmbientOcclusion {
+    pub fn new(radius: f64) -> Self {
+        IntAmbientOcclusion { radius }
+    }
+        // Whether a cosine weighted direction above `hit` leaves unoccluded.
+    fn occlusion(&self, hit: Option<&HitRecord>, world: &HittableList) -> Color3 {
+        let Some(hr) = hit else {
+            return Color3::one();
+        };
+        let frame = hr.frame();
+        let local = Vec3::random_cosine_direction();
+        let direction = frame.local(local.x, local.y, local.z);
+        let occlusion_ray = Ray::new(hr.p.clone(), direction);
+        match world.hit(&occlusion_ray, &Interval::new(Interval::HIT_EVAL.min, self.radius)) {
+            Some(_) => Color3::zero(),
+            None => Color3::one(),
+        }
+    }
+}
+impl Integrator for IntAmbientOcclusion {
+    fn li(&self, ray: &Ray, _camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> Color3 {
+        self.occlusion(world.hit(ray, &Interval::HIT_EVAL).as_ref(), world)
+    }
+    fn li_split(&self, ray: &Ray, _camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        let hit = world.hit(ray, &Interval::HIT_EVAL);
+        (LightSplit::indirect(self.occlusion(hit.as_ref(), world)), hit)
+    }
+}
+/// What `IntDebug` shows at the first hit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugModes {
+    Normal,                                 // Shading normal mapped to [0, 1]
+    Depth { far: f64 },                     // Hit distance, white at `far`
+    Uv,                                     // Surface coordinates in red and green
+    Barycentric,                            // Triangle barycentrics, black elsewhere
+    MaterialId,                             // Flat color per material kind
+    IntersectionCost { max_tests: f64 }     // Primitive tests per ray, red at `max_tests`
+}
+
+/// Non-physical views of the first hit, for look-dev and bug hunting.
+#[derive(Clone)]
+pub struct IntDebug {
+    pub mode: DebugModes
+}
+impl IntDebug {
+    pub fn new(mode: DebugModes) -> Self {
+        IntDebug { mode }
+    }
+        // Blue to green to red ramp over [0, 1].
+    fn heat_color(t: f64) -> Color3 {
+        let t = t.clamp(0.0, 1.0);
+        if t < 0.5 {
+            Color3::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+        }
+        else {
+            Color3::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+        }
+    }
+        // What `mode` shows for `ray`, which first hit `hit` after `tests` primitive tests.
+    fn view(&self, ray: &Ray, hit: Option<&HitRecord>, tests: u64, world: &HittableList) -> Color3 {
+        match (self.mode, hit) {
+            (DebugModes::IntersectionCost { max_tests }, _) => Self::heat_color(tests as f64 / max_tests),
+            (_, None) => Color3::zero(),
+            (DebugModes::Normal, Some(hr)) => 0.5 * &(&hr.shading_normal + &Vec3::one()),
+            (DebugModes::Depth { far }, Some(hr)) => Color3::one() * f64::min(hr.t * ray.direction().len() / far, 1.0),
+            (DebugModes::Uv, Some(hr)) => Color3::new(hr.u, hr.v, 0.0),
+            (DebugModes::Barycentric, Some(hr)) => match world.objects().get(hr.object_id) {
+                Some(Hittables::Triangle(_)) => Color3::new(1.0 - hr.u - hr.v, hr.u, hr.v),
+                _ => Color3::zero(),
+            },
+            (DebugModes::MaterialId, Some(hr)) => ColorUtil::id_color(MatUtil::id(&hr.material) + 1),
+        }
+    }
+}
+impl Integrator for IntDebug {
+    fn li(&self, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> Color3 {
+        self.li_split(ray, camera, world, splats).0.total()
+    }
+    fn li_split(&self, ray: &Ray, _camera: &Camera, world: &HittableList, _splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        let tests_before = HitUtil::intersection_tests();
+        let hit = world.hit(ray, &Interval::HIT_EVAL);
+        let tests = HitUtil::intersection_tests() - tests_before;
+        (LightSplit::indirect(self.view(ray, hit.as_ref(), tests, world)), hit)
+    }
+}
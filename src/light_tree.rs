@@ -0,0 +1,205 @@
+use crate::datatypes::Aabb;
+use crate::datatypes::Point3;
+use crate::lights::Lights;
+use crate::utils::LightUtil;
+use crate::utils::MathUtil;
+
+
+/// Bounding volume hierarchy over the scene lights. Picks a light with probability
+/// proportional to its estimated contribution at the shading point, by walking the
+/// tree and choosing between children by power over squared distance.
+/// Lights at infinity live outside the tree and are picked uniformly.
+#[derive(Clone)]
+pub struct LightTree {
+    nodes: Vec<LightNode>,
+    infinite: Vec<usize>,
+    trails: Vec<Option<(u64, u32)>>     // Branch bits and depth of each bounded light
+}
+
+#[derive(Clone)]
+struct LightNode {
+    bounds: Aabb,
+    power: f64,
+    kind: NodeKind
+}
+
+#[derive(Clone)]
+enum NodeKind {
+    Leaf(usize),        // Light index
+    Interior(usize)     // Second child, the first one is the next node
+}
+
+#[allow(unused)]
+impl LightTree {
+    pub fn new(lights: &[Lights]) -> Self {
+        let mut tree = LightTree { nodes: Vec::new(), infinite: Vec::new(), trails: vec![None; lights.len()] };
+        let mut bounded: Vec<(usize, Aabb, f64)> = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match LightUtil::bounds(light) {
+                Some(bounds) => {
+                    let power = LightUtil::power(light);
+                    if power > 0.0 {
+                        bounded.push((index, bounds, power));
+                    }
+                },
+                None => tree.infinite.push(index),
+            }
+        }
+        if !bounded.is_empty() {
+            tree.build(&mut bounded, 0, 0);
+        }
+        tree
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.infinite.is_empty()
+    }
+    /// Picks a light to sample from `p`, returning its index and probability.
+    pub fn sample(&self, p: &Point3) -> Option<(usize, f64)> {
+        let p_infinite = self.infinite_probability();
+        if p_infinite > 0.0 && MathUtil::rand() < p_infinite {
+            let choice = usize::min((MathUtil::rand() * self.infinite.len() as f64) as usize, self.infinite.len() - 1);
+            return Some((self.infinite[choice], p_infinite / self.infinite.len() as f64));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut pmf = 1.0 - p_infinite;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(index) => return Some((index, pmf)),
+                NodeKind::Interior(second) => {
+                    let (first_imp, second_imp) = (self.importance(node + 1, p), self.importance(second, p));
+                    if first_imp + second_imp <= 0.0 {
+                        return None;
+                    }
+                    let p_first = first_imp / (first_imp + second_imp);
+                    if MathUtil::rand() < p_first {
+                        pmf *= p_first;
+                        node += 1;
+                    }
+                    else {
+                        pmf *= 1.0 - p_first;
+                        node = second;
+                    }
+                },
+            }
+        }
+    }
+    /// Probability of `sample` picking light `index` from `p`.
+    pub fn pmf(&self, p: &Point3, index: usize) -> f64 {
+        let p_infinite = self.infinite_probability();
+        if self.infinite.contains(&index) {
+            return p_infinite / self.infinite.len() as f64;
+        }
+        let Some(Some((bits, depth))) = self.trails.get(index) else {
+            return 0.0;
+        };
+
+        let mut pmf = 1.0 - p_infinite;
+        let mut node = 0;
+        for level in 0..*depth {
+            let NodeKind::Interior(second) = self.nodes[node].kind else {
+                break;
+            };
+            let (first_imp, second_imp) = (self.importance(node + 1, p), self.importance(second, p));
+            if first_imp + second_imp <= 0.0 {
+                return 0.0;
+            }
+            if bits & (1 << level) == 0 {
+                pmf *= first_imp / (first_imp + second_imp);
+                node += 1;
+            }
+            else {
+                pmf *= second_imp / (first_imp + second_imp);
+                node = second;
+            }
+        }
+        pmf
+    }
+    fn infinite_probability(&self) -> f64 {
+        let n_infinite = self.infinite.len() as f64;
+        let n_tree = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        if n_infinite == 0.0 { 0.0 } else { n_infinite / (n_infinite + n_tree) }
+    }
+        // Power over squared distance, with the distance kept outside the node's
+        // bounds so nearby nodes don't blow up.
+    fn importance(&self, node: usize, p: &Point3) -> f64 {
+        let node = &self.nodes[node];
+        let dist_sqr = (&node.bounds.centroid() - p).len_sqr();
+        let radius_sqr = 0.25 * node.bounds.diagonal().len_sqr();
+        node.power / f64::max(dist_sqr, f64::max(radius_sqr, 1e-12))
+    }
+        // Splits at the median centroid along the longest axis of the centroid bounds.
+    fn build(&mut self, lights: &mut [(usize, Aabb, f64)], bits: u64, depth: u32) -> usize {
+        let node = self.nodes.len();
+        let bounds = lights.iter().fold(Aabb::empty(), |acc, (_, b, _)| acc.union(b));
+        let power = lights.iter().map(|(_, _, power)| power).sum();
+
+        if lights.len() == 1 {
+            self.trails[lights[0].0] = Some((bits, depth));
+            self.nodes.push(LightNode { bounds, power, kind: NodeKind::Leaf(lights[0].0) });
+            return node;
+        }
+
+        let centroids = lights.iter().fold(Aabb::empty(), |acc, (_, b, _)| {
+            let c = b.centroid();
+            acc.union(&Aabb::new(&c, &c))
+        });
+        let axis = centroids.longest_axis();
+        lights.sort_by(|a, b| a.1.centroid()[axis].total_cmp(&b.1.centroid()[axis]));
+        let mid = lights.len() / 2;
+
+        self.nodes.push(LightNode { bounds, power, kind: NodeKind::Leaf(0) });
+        let (first, second) = lights.split_at_mut(mid);
+        self.build(first, bits, depth + 1);
+        let second = self.build(second, bits | (1 << depth), depth + 1);
+        self.nodes[node].kind = NodeKind::Interior(second);
+        node
+    }
+}
+
+
+/// Picks lights in proportion to their power, independent of any shading point.
+/// Used to start light paths, so lights at infinity are left out.
+#[derive(Clone)]
+pub struct LightPowerTable {
+    cdf: Vec<f64>,
+    indices: Vec<usize>,
+    pmfs: Vec<f64>          // Probability of each scene light
+}
+#[allow(unused)]
+impl LightPowerTable {
+    pub fn new(lights: &[Lights]) -> Self {
+        let mut table = LightPowerTable { cdf: Vec::new(), indices: Vec::new(), pmfs: vec![0.0; lights.len()] };
+        let mut total = 0.0;
+        for (index, light) in lights.iter().enumerate() {
+            let power = LightUtil::power(light);
+            if LightUtil::bounds(light).is_some() && power > 0.0 {
+                total += power;
+                table.cdf.push(total);
+                table.indices.push(index);
+                table.pmfs[index] = power;
+            }
+        }
+        table.cdf.iter_mut().for_each(|c| *c /= total);
+        table.pmfs.iter_mut().for_each(|p| *p /= total);
+        table
+    }
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+    pub fn sample(&self) -> Option<(usize, f64)> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        let u = MathUtil::rand();
+        let choice = usize::min(self.cdf.partition_point(|&c| c <= u), self.indices.len() - 1);
+        let index = self.indices[choice];
+        Some((index, self.pmfs[index]))
+    }
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.pmfs.get(index).copied().unwrap_or(0.0)
+    }
+}
@@ -0,0 +1,339 @@
+use std::f64::consts::PI;
+
+use crate::datatypes::Onb;
+use crate::datatypes::Aabb;
+use crate::datatypes::Vec3;
+use crate::datatypes::Point3;
+use crate::datatypes::Color3;
+use crate::datatypes::HitRecord;
+use crate::shapes::Hittables;
+use crate::utils::HitUtil;
+use crate::utils::ColorUtil;
+use crate::utils::MatUtil;
+use crate::utils::MathUtil;
+
+
+/// Lights reached through explicit sampling. All but area lights are invisible to rays.
+#[derive(Clone)]
+pub enum Lights {
+    Point(LightPoint),
+    Spot(LightSpot),
+    Directional(LightDirectional),
+    Area(LightArea)
+}
+
+
+pub struct LightFactory;
+#[allow(unused)]
+impl LightFactory {
+    pub fn make_point(position: Point3, color: Color3, intensity: f64) -> Lights {
+        Lights::Point(LightPoint::new(position, color, intensity))
+    }
+    pub fn make_spot(position: Point3, direction: Vec3, color: Color3, intensity: f64,
+                     cone_angle: f64, edge_angle: f64) -> Lights {
+        Lights::Spot(LightSpot::new(position, direction, color, intensity, cone_angle, edge_angle))
+    }
+    pub fn make_directional(direction: Vec3, color: Color3, intensity: f64) -> Lights {
+        Lights::Directional(LightDirectional::new(direction, color, intensity))
+    }
+}
+
+
+/// Incident light at a shading point from one light sample.
+pub struct LightSample {
+    pub direction: Vec3,    // Unit direction from the shading point towards the light
+    pub distance: f64,      // Distance to the light, infinite for distant lights
+    pub radiance: Color3,   // Incident radiance (irradiance for delta lights)
+    pub pdf: f64,           // Solid angle density, 1 for delta lights
+    pub is_area: bool       // Whether a BSDF sample could also hit this light
+}
+
+/// Point a light path leaves from.
+#[derive(Clone)]
+pub struct LightPosition {
+    pub p: Point3,
+    pub normal: Option<Vec3>,           // Outward normal, `None` for point-like lights
+    pub hit_rec: Option<HitRecord>,     // Surface record on area lights
+    pub pdf: f64                        // Area density, 1 for point-like lights
+}
+
+pub trait Light {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample>;
+    /// Solid angle density of `sample_li` from `origin` picking the point in `hit_rec`.
+    fn pdf_li(&self, _origin: &Point3, _hit_rec: &HitRecord) -> f64 {
+        0.0
+    }
+    /// Rough emitted power, used to pick between lights.
+    fn power(&self) -> f64;
+    /// Region the light emits from, `None` for lights at infinity.
+    fn bounds(&self) -> Option<Aabb>;
+    /// Picks where a light path starts. Lights at infinity have no such point.
+    fn sample_position(&self) -> Option<LightPosition> {
+        None
+    }
+    /// Area density of `sample_position`.
+    fn pdf_position(&self) -> f64 {
+        0.0
+    }
+    /// Picks the direction a light path leaves `position` in, with its solid angle density.
+    fn sample_direction(&self, _position: &LightPosition) -> Option<(Vec3, f64)> {
+        None
+    }
+    /// Solid angle density of `sample_direction` picking `direction` from a point with `normal`.
+    fn pdf_direction(&self, _normal: Option<&Vec3>, _direction: &Vec3) -> f64 {
+        0.0
+    }
+    /// Radiance leaving `position` along `direction`, intensity for point-like lights.
+    fn emission(&self, _position: &LightPosition, _direction: &Vec3) -> Color3 {
+        Color3::zero()
+    }
+    /// Radiance seen by a ray escaping the scene along `direction`.
+    fn le(&self, _direction: &Vec3) -> Color3 {
+        Color3::zero()
+    }
+}
+
+
+/// Isotropic point light with inverse square falloff.
+#[derive(Clone)]
+pub struct LightPoint {
+    pub position: Point3,
+    pub color: Color3,
+    pub intensity: f64
+}
+impl LightPoint {
+    pub fn new(position: Point3, color: Color3, intensity: f64) -> Self {
+        LightPoint { position, color, intensity }
+    }
+}
+impl Light for LightPoint {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = &self.position - p;
+        let distance = to_light.len();
+        if distance <= 0.0 {
+            return None;
+        }
+        let radiance = &self.color * (self.intensity / (distance * distance));
+        Some(LightSample { direction: to_light / distance, distance, radiance, pdf: 1.0, is_area: false })
+    }
+    fn power(&self) -> f64 {
+        4.0 * PI * self.intensity * ColorUtil::luminance(&self.color)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(&self.position, &self.position))
+    }
+    fn sample_position(&self) -> Option<LightPosition> {
+        Some(LightPosition { p: self.position.clone(), normal: None, hit_rec: None, pdf: 1.0 })
+    }
+    fn pdf_position(&self) -> f64 {
+        1.0
+    }
+    fn sample_direction(&self, _position: &LightPosition) -> Option<(Vec3, f64)> {
+        Some((Vec3::random_unit(), 1.0 / (4.0 * PI)))
+    }
+    fn pdf_direction(&self, _normal: Option<&Vec3>, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+    fn emission(&self, _position: &LightPosition, _direction: &Vec3) -> Color3 {
+        &self.color * self.intensity
+    }
+}
+
+
+/// Point light restricted to a cone. `cone_angle` is the full opening angle in
+/// degrees; intensity fades smoothly over the outer `edge_angle` degrees.
+#[derive(Clone)]
+pub struct LightSpot {
+    pub position: Point3,
+    pub direction: Vec3,
+    pub color: Color3,
+    pub intensity: f64,
+    pub cone_angle: f64,
+    pub edge_angle: f64
+}
+impl LightSpot {
+    pub fn new(position: Point3, direction: Vec3, color: Color3, intensity: f64,
+               cone_angle: f64, edge_angle: f64) -> Self {
+        LightSpot { position, direction: direction.unit(), color, intensity, cone_angle, edge_angle }
+    }
+    fn cos_outer(&self) -> f64 {
+        f64::cos(MathUtil::degrees_to_radians(self.cone_angle / 2.0))
+    }
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        let outer = MathUtil::degrees_to_radians(self.cone_angle / 2.0);
+        let inner = f64::max(0.0, outer - MathUtil::degrees_to_radians(self.edge_angle));
+        let (cos_outer, cos_inner) = (f64::cos(outer), f64::cos(inner));
+        if cos_theta <= cos_outer {
+            0.0
+        }
+        else if cos_theta >= cos_inner {
+            1.0
+        }
+        else {
+            let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+impl Light for LightSpot {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = &self.position - p;
+        let distance = to_light.len();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = to_light / distance;
+        let falloff = self.falloff(self.direction.dot(&-&direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+        let radiance = &self.color * (self.intensity * falloff / (distance * distance));
+        Some(LightSample { direction, distance, radiance, pdf: 1.0, is_area: false })
+    }
+    fn power(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_outer()) * self.intensity * ColorUtil::luminance(&self.color)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(&self.position, &self.position))
+    }
+    fn sample_position(&self) -> Option<LightPosition> {
+        Some(LightPosition { p: self.position.clone(), normal: None, hit_rec: None, pdf: 1.0 })
+    }
+    fn pdf_position(&self) -> f64 {
+        1.0
+    }
+    fn sample_direction(&self, _position: &LightPosition) -> Option<(Vec3, f64)> {
+            // Uniform over the cone, the falloff is left to `emission`.
+        let cos_outer = self.cos_outer();
+        let cos_theta = 1.0 - MathUtil::rand() * (1.0 - cos_outer);
+        let sin_theta = f64::sqrt(f64::max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * PI * MathUtil::rand();
+        let direction = Onb::from_normal(&self.direction).local(sin_theta * f64::cos(phi), sin_theta * f64::sin(phi), cos_theta);
+        Some((direction, 1.0 / (2.0 * PI * (1.0 - cos_outer))))
+    }
+    fn pdf_direction(&self, _normal: Option<&Vec3>, direction: &Vec3) -> f64 {
+        let cos_outer = self.cos_outer();
+        if self.direction.dot(&direction.unit()) < cos_outer { 0.0 } else { 1.0 / (2.0 * PI * (1.0 - cos_outer)) }
+    }
+    fn emission(&self, _position: &LightPosition, direction: &Vec3) -> Color3 {
+        &self.color * (self.intensity * self.falloff(self.direction.dot(&direction.unit())))
+    }
+}
+
+
+/// Light arriving from infinitely far away along `direction`, like the sun.
+/// `intensity` is the irradiance on a surface facing the light. A non-zero
+/// `angular_radius` (degrees) turns it into a disk that casts soft shadows and
+/// shows up in reflections.
+#[derive(Clone)]
+pub struct LightDirectional {
+    pub direction: Vec3,    // Direction the light travels in
+    pub color: Color3,
+    pub intensity: f64,
+    pub angular_radius: f64
+}
+impl LightDirectional {
+    pub fn new(direction: Vec3, color: Color3, intensity: f64) -> Self {
+        LightDirectional { direction: direction.unit(), color, intensity, angular_radius: 0.0 }
+    }
+    pub fn with_angular_radius(mut self, angular_radius: f64) -> Self {
+        self.angular_radius = angular_radius;
+        self
+    }
+    fn cos_max(&self) -> f64 {
+        f64::cos(MathUtil::degrees_to_radians(self.angular_radius))
+    }
+    fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_max())
+    }
+}
+impl Light for LightDirectional {
+    fn sample_li(&self, _p: &Point3) -> Option<LightSample> {
+        let irradiance = &self.color * self.intensity;
+        if self.angular_radius <= 0.0 {
+            return Some(LightSample { direction: -&self.direction, distance: f64::INFINITY, radiance: irradiance, pdf: 1.0, is_area: false });
+        }
+            // Uniform direction inside the disk's cone.
+        let cos_theta = 1.0 - MathUtil::rand() * (1.0 - self.cos_max());
+        let sin_theta = f64::sqrt(f64::max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * PI * MathUtil::rand();
+        let frame = Onb::from_normal(&-&self.direction);
+        let direction = frame.local(sin_theta * f64::cos(phi), sin_theta * f64::sin(phi), cos_theta);
+        let pdf = 1.0 / self.solid_angle();
+        Some(LightSample { direction, distance: f64::INFINITY, radiance: irradiance * pdf, pdf, is_area: false })
+    }
+    fn le(&self, direction: &Vec3) -> Color3 {
+        if self.angular_radius <= 0.0 || direction.unit().dot(&-&self.direction) < self.cos_max() {
+            return Color3::zero();
+        }
+        &self.color * (self.intensity / self.solid_angle())
+    }
+    fn power(&self) -> f64 {
+        f64::INFINITY
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+
+/// Shape with an emissive material, sampled over the part of it seen from the shading point.
+#[derive(Clone)]
+pub struct LightArea {
+    pub shape: Box<Hittables>
+}
+impl LightArea {
+    pub fn new(shape: Hittables) -> Self {
+        LightArea { shape: Box::new(shape) }
+    }
+}
+impl Light for LightArea {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let sample = HitUtil::sample(&self.shape, p)?;
+        let to_light = &sample.hit_rec.p - p;
+        let distance = to_light.len();
+        if distance <= 0.0 || sample.pdf <= 0.0 || !sample.pdf.is_finite() {
+            return None;
+        }
+        let radiance = MatUtil::emitted(&sample.hit_rec.material, &sample.hit_rec);
+        Some(LightSample { direction: to_light / distance, distance, radiance, pdf: sample.pdf, is_area: true })
+    }
+    fn pdf_li(&self, origin: &Point3, hit_rec: &HitRecord) -> f64 {
+        HitUtil::pdf(&self.shape, origin, hit_rec)
+    }
+    fn power(&self) -> f64 {
+            // Emission in the middle of the surface's texture, times the area, times π
+            // per side since emitters shine from both.
+        let Some(material) = HitUtil::material(&self.shape) else {
+            return 0.0;
+        };
+        let mut hit_rec = HitRecord::new(HitUtil::bounds(&self.shape).centroid(), Vec3::y_u(), 0.0, material.clone());
+        hit_rec.set_uv(0.5, 0.5);
+        2.0 * PI * HitUtil::area(&self.shape) * ColorUtil::luminance(&MatUtil::emitted(material, &hit_rec))
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        Some(HitUtil::bounds(&self.shape))
+    }
+    fn sample_position(&self) -> Option<LightPosition> {
+        let hit_rec = HitUtil::sample_area(&self.shape)?;
+        Some(LightPosition { p: hit_rec.p.clone(), normal: Some(hit_rec.normal.clone()), hit_rec: Some(hit_rec), pdf: self.pdf_position() })
+    }
+    fn pdf_position(&self) -> f64 {
+        1.0 / HitUtil::area(&self.shape)
+    }
+    fn sample_direction(&self, position: &LightPosition) -> Option<(Vec3, f64)> {
+            // Emitters shine from both sides, so pick a side and go cosine weighted.
+        let normal = position.normal.as_ref()?;
+        let normal = if MathUtil::rand() < 0.5 { normal.clone() } else { -normal };
+        let local = Vec3::random_cosine_direction();
+        let direction = Onb::from_normal(&normal).local(local.x, local.y, local.z);
+        let pdf = self.pdf_direction(Some(&normal), &direction);
+        Some((direction, pdf))
+    }
+    fn pdf_direction(&self, normal: Option<&Vec3>, direction: &Vec3) -> f64 {
+        normal.map_or(0.0, |normal| normal.dot(&direction.unit()).abs() / (2.0 * PI))
+    }
+    fn emission(&self, position: &LightPosition, _direction: &Vec3) -> Color3 {
+        position.hit_rec.as_ref().map_or(Color3::zero(), |hit_rec| MatUtil::emitted(&hit_rec.material, hit_rec))
+    }
+}
@@ -4,31 +4,152 @@ mod utils;
 mod tests;
 mod shapes;
 mod camera;
+mod bdpt;
+mod photons;
+mod integrators;
+mod aovs;
+mod tonemap;
+mod denoise;
+mod progressive;
+mod samplers;
+mod filters;
+mod tiles;
 mod materials;
+mod assets;
+mod textures;
+mod lights;
+mod light_tree;
+mod sky;
 
 use std::time::Instant;
 
+use aovs::AovKinds;
+use aovs::Aovs;
+use camera::Camera;
 use camera::CameraBuilder;
+use integrators::Integrators;
+use integrators::IntegratorFactory;
+use samplers::Samplers;
+use samplers::SamplerFactory;
+use filters::Filters;
+use filters::FilterFactory;
+use tiles::TileOrders;
 use datatypes::Color3;
+use denoise::Denoiser;
 use datatypes::Point3;
 use datatypes::Vec3;
 use materials::MatDielectric;
 use materials::MatMetal;
 use materials::MaterialFactory;
 use materials::Materials;
+use assets::MtlLibrary;
 use materials::MatLambertian;
 use shapes::ShapeFactory;
+use sky::Skies;
+use sky::SkyGradient;
+use sky::SkyPreetham;
 use shapes::Sphere;
 use shapes::Hittables;
 use shapes::HittableList;
 use utils::MathUtil;
+use progressive::AdaptiveSampling;
+use tonemap::Display;
+use tonemap::ToneMappers;
 use utils::ImageUtil;
+use utils::ExrPrecisions;
 
 #[allow(unreachable_code)]
 fn main() {
-    // make_cover();
-    // dev_scene();
-    bench_scene();
+        // `--scene <name>`, `--integrator <name>`, `--sampler <name>`, `--seed <number>`,
+        // `--filter <name>`, `--tiles <order>`, `--tonemap <name>` and `--exposure <stops>`,
+        // names as in `IntegratorFactory::from_name`, `SamplerFactory::from_name`,
+        // `FilterFactory::from_name`, `TileOrders::from_name` and `ToneMappers::from_name`.
+        // The materials scene also takes `--mtl <path>`. `--aovs`, `--denoise`, `--exr` and
+        // `--hdr` save more than the scene's PNG, see `Options::save`.
+    let args: Vec<String> = std::env::args().collect();
+    let option = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1));
+    let integrator = match option("--integrator") {
+        Some(name) => match IntegratorFactory::from_name(name) {
+            Some(integrator) => integrator,
+            None => {
+                eprintln!("Unknown integrator: {name}");
+                return;
+            },
+        },
+        None => IntegratorFactory::make_path(),
+    };
+    let sampler = match option("--sampler") {
+        Some(name) => match SamplerFactory::from_name(name) {
+            Some(sampler) => sampler,
+            None => {
+                eprintln!("Unknown sampler: {name}");
+                return;
+            },
+        },
+        None => SamplerFactory::make_independent(),
+    };
+    let seed = match option("--seed").map(|seed| seed.parse::<u64>()) {
+        Some(Ok(seed)) => seed,
+        Some(Err(_)) => {
+            eprintln!("Seed must be a number");
+            return;
+        },
+        None => 0,
+    };
+    let filter = match option("--filter") {
+        Some(name) => match FilterFactory::from_name(name) {
+            Some(filter) => Some(filter),
+            None => {
+                eprintln!("Unknown filter: {name}");
+                return;
+            },
+        },
+        None => None,
+    };
+    let tile_order = match option("--tiles") {
+        Some(name) => match TileOrders::from_name(name) {
+            Some(tile_order) => Some(tile_order),
+            None => {
+                eprintln!("Unknown tile order: {name}");
+                return;
+            },
+        },
+        None => None,
+    };
+    let tone_mapper = match option("--tonemap") {
+        Some(name) => match ToneMappers::from_name(name) {
+            Some(tone_mapper) => Some(tone_mapper),
+            None => {
+                eprintln!("Unknown tone mapper: {name}");
+                return;
+            },
+        },
+        None => None,
+    };
+    let exposure = match option("--exposure").map(|exposure| exposure.parse::<f64>()) {
+        Some(Ok(exposure)) => Some(exposure),
+        Some(Err(_)) => {
+            eprintln!("Exposure must be a number");
+            return;
+        },
+        None => None,
+    };
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let options = Options { integrator, sampler, seed, filter, tile_order, tone_mapper, exposure,
+                            aovs: flag("--aovs"), denoise: flag("--denoise"), exr: flag("--exr"), hdr: flag("--hdr") };
+        // Random scene content follows the seed as well.
+    MathUtil::seed(seed);
+    match option("--scene").map(String::as_str) {
+        Some("cover") => make_cover(&options),
+        Some("dev") => dev_scene(&options),
+        Some("bench") | None => bench_scene(&options),
+        Some("adaptive") => adaptive_scene(&options),
+        Some("outdoor") => outdoor_scene(&options),
+        Some("lights") => many_lights_scene(&options),
+        Some("materials") => materials_scene(&options, option("--mtl")),
+        Some("caustics") => caustics_scene(&options),
+        Some(name) => eprintln!("Unknown scene: {name}"),
+    }
     return;
     let aspect_ratio = 16.0 / 9.0;
     let im_width: u32 = 400;
@@ -94,8 +215,80 @@ fn main() {
     let _ = image.save("output.png");
 }
 
+/// What the command line picked, applied over each scene's own settings.
+struct Options {
+    integrator: Integrators,
+    sampler: Samplers,
+    seed: u64,
+    filter: Option<Filters>,
+    tile_order: Option<TileOrders>,
+    tone_mapper: Option<ToneMappers>,
+    exposure: Option<f64>,
+    aovs: bool,         // Extra outputs, next to each scene's PNG
+    denoise: bool,
+    exr: bool,
+    hdr: bool
+}
+impl Options {
+    /// `builder` rendering with the picked integrator, sampler and seed, and the picked
+    /// filter and tile order, if any.
+    fn camera(&self, builder: CameraBuilder) -> CameraBuilder {
+        let mut builder = builder.integrator(self.integrator.clone()).sampler(self.sampler).seed(self.seed);
+        if let Some(filter) = self.filter {
+            builder = builder.filter(filter);
+        }
+        if let Some(tile_order) = self.tile_order {
+            builder = builder.tile_order(tile_order);
+        }
+        builder
+    }
+    /// The scene's `display`, with the tone mapper and exposure picked instead, if any.
+    fn display(&self, display: Display) -> Display {
+        Display::new(self.exposure.unwrap_or(display.exposure), self.tone_mapper.unwrap_or(display.tone_mapper))
+    }
+    /// Renders `world`, along with the AOVs if they or the denoiser were picked.
+    fn render(&self, camera: &Camera, world: &HittableList) -> (Vec<Color3>, Option<Aovs>) {
+        if self.aovs || self.denoise {
+            let (pixels, aovs) = camera.render_aovs(world);
+            (pixels, Some(aovs))
+        }
+        else {
+            (camera.render(world), None)
+        }
+    }
+    /// Saves `pixels` as `<stem>.png` through the scene's `display`, and whatever else was
+    /// picked: AOV previews, a denoised PNG, an EXR of all layers and a Radiance HDR.
+    fn save(&self, stem: &str, pixels: &[Color3], aovs: Option<&Aovs>, camera: &Camera, display: Display) {
+        let (width, height) = (camera.im_width(), camera.im_height());
+        let display = self.display(display);
+        let _ = ImageUtil::get_display_image(pixels, width, height, &display).save(format!("{stem}.png"));
+        if let Some(aovs) = aovs {
+            if self.aovs {
+                for kind in AovKinds::ALL {
+                    let image = ImageUtil::get_rgb_image(aovs.preview(kind), width, height);
+                    let _ = image.save(format!("{stem}-{}.png", kind.name()));
+                }
+            }
+            if self.denoise {
+                let denoised = Denoiser::new().denoise_aovs(pixels, aovs, width, height);
+                let _ = ImageUtil::get_display_image(&denoised, width, height, &display).save(format!("{stem}-denoised.png"));
+            }
+        }
+        if self.exr {
+            let mut layers = vec![("beauty", pixels)];
+            if let Some(aovs) = aovs {
+                layers.extend(aovs.layers());
+            }
+            let _ = ImageUtil::save_exr(&format!("{stem}.exr"), &layers, width, height, ExrPrecisions::Half);
+        }
+        if self.hdr {
+            let _ = ImageUtil::save_hdr(&format!("{stem}.hdr"), pixels, width, height);
+        }
+    }
+}
+
 #[allow(dead_code)]
-fn make_cover() {
+fn make_cover(options: &Options) {
     let mut world = HittableList::new();
 
     let ground_material = Materials::DifuseLamb(MatLambertian::new(Color3::new(0.5, 0.5, 0.5)));
@@ -137,7 +330,7 @@ fn make_cover() {
     let material3 = Materials::Metal(MatMetal::new(Color3::new(0.7, 0.6, 0.5), 0.0));
     world.add(Hittables::Sphere(Sphere::new(1.0, 4.0, 1.0, 0.0, material3)));
 
-    let camera = CameraBuilder::new()
+    let camera = options.camera(CameraBuilder::new()
         .aspect_ratio(16.0 / 9.0)
         .image_width(1200)
         .samples_per_pixel(500)
@@ -148,16 +341,16 @@ fn make_cover() {
         .vector_up(Point3::y_u())
         .defocus_angle(0.6)
         .focus_dist(10.0)
+        .filter(FilterFactory::make_mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0)))
         .build();
 
-    let pixels = camera.render(&world);
+    let (pixels, aovs) = options.render(&camera, &world);
 
-    let image = ImageUtil::get_rgb_image(pixels, camera.im_width(), camera.im_height());
-    let _ = image.save("out-cover.png");
+    options.save("out-cover", &pixels, aovs.as_ref(), &camera, Display::DEFAULT);
 }
 
 #[allow(dead_code)]
-fn dev_scene() {
+fn dev_scene(options: &Options) {
     let aspect_ratio = 16.0 / 9.0;
     let im_width: u32 = 400;
 
@@ -190,7 +383,7 @@ fn dev_scene() {
     world.add(ShapeFactory::make_sphere(0.5, 1.0, 0.0, -1.0, mat_right));
     // world.add(ShapeFactory::make_sphere(0.25, -0.25, 1.0, -0.5, mat_light));
 
-    let mut camera = CameraBuilder::new()
+    let mut camera = options.camera(CameraBuilder::new()
         .aspect_ratio(aspect_ratio)
         .image_width(im_width)
         .samples_per_pixel(100)
@@ -200,7 +393,7 @@ fn dev_scene() {
         .look_at(-&Point3::z_u())
         .vector_up(Vec3::y_u())
         .defocus_angle(2.0)
-        .focus_dist(1.0)
+        .focus_dist(1.0))
         .build();
     // camera.set_center(Vec3::y_u());
     // camera.set_lookfrom(Vec3::y_u());
@@ -208,19 +401,40 @@ fn dev_scene() {
     // camera.set_vfov(50.0);
     camera.update();
 
-    let pixels = camera.render(&world);
+    let (pixels, aovs) = options.render(&camera, &world);
 
     let elapsed = start.elapsed();
     println!("Run time: {}", elapsed.as_millis());
 
-    let image = ImageUtil::get_rgb_image(pixels, camera.im_width(), camera.im_height());
-    let _ = image.save("out-dev.png");
+    options.save("out-dev", &pixels, aovs.as_ref(), &camera, Display::DEFAULT);
 }
 
 #[allow(dead_code)]
-fn bench_scene() {
+fn bench_scene(options: &Options) {
     let start = Instant::now();
 
+    let world = bench_world();
+
+        // Fixed workload, so run times compare across changes.
+    let mut camera = options.camera(CameraBuilder::new()
+        .image_width(800)
+        .samples_per_pixel(400)
+        .max_bounces(50)
+        .defocus_angle(2.0)
+        .focus_dist(1.0))
+        .build();
+    camera.update();
+
+    let (pixels, aovs) = options.render(&camera, &world);
+
+    let elapsed = start.elapsed();
+    println!("Run time: {}", elapsed.as_millis());
+
+    options.save("out-bench", &pixels, aovs.as_ref(), &camera, Display::DEFAULT);
+}
+
+#[allow(dead_code)]
+fn bench_world() -> HittableList {
     let mut world = HittableList::new();
 
     let mat_ground = MaterialFactory::make_lambertian(Color3::new(0.7, 0.7, 0.2));
@@ -245,21 +459,215 @@ fn bench_scene() {
     world.add(ShapeFactory::make_sphere(0.5, -1.0, 0.0, -1.0, mat_left));
     world.add(ShapeFactory::make_sphere(0.4, -1.0, 0.0, -1.0, mat_bubble));
     world.add(ShapeFactory::make_sphere(0.5, 1.0, 0.0, -1.0, mat_right));
+    world
+}
+
+#[allow(dead_code)]
+fn adaptive_scene(options: &Options) {
+    let start = Instant::now();
+
+    let world = bench_world();
 
-    let mut camera = CameraBuilder::new()
+        // The bench scene sampled until pixels converge, in Hilbert ordered tiles.
+    let mut camera = options.camera(CameraBuilder::new()
         .image_width(800)
         .samples_per_pixel(400)
+        .adaptive_sampling(AdaptiveSampling::new(32, 2000, 0.02))
+        .tiles(16, TileOrders::Hilbert)
         .max_bounces(50)
         .defocus_angle(2.0)
-        .focus_dist(1.0)
+        .focus_dist(1.0))
         .build();
     camera.update();
 
-    let pixels = camera.render(&world);
+    let (pixels, tiles) = camera.render_tiles(&world, |_, _| {});
 
     let elapsed = start.elapsed();
     println!("Run time: {}", elapsed.as_millis());
+    if let Some(slowest) = tiles.iter().max_by_key(|stats| stats.elapsed) {
+        println!("Slowest tile: ({}, {}), {} ms, {} samples", slowest.tile.x, slowest.tile.y,
+                 slowest.elapsed.as_millis(), slowest.samples);
+    }
 
-    let image = ImageUtil::get_rgb_image(pixels, camera.im_width(), camera.im_height());
-    let _ = image.save("out-bench.png");
-}
\ No newline at end of file
+    options.save("out-adaptive", &pixels, None, &camera, Display::DEFAULT);
+}
+
+#[allow(dead_code)]
+fn outdoor_scene(options: &Options) {
+    let start = Instant::now();
+
+    let mut world = HittableList::new();
+
+    world.set_daylight(SkyPreetham::new(25.0, 40.0, 3.0), 4.0);
+
+    let mat_ground = MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5));
+    let mat_center = MaterialFactory::make_lambertian(Color3::new(0.1, 0.2, 0.5));
+    let mat_left = MaterialFactory::make_dielectric(1.5);
+    let mat_right = MaterialFactory::make_metal(Color3::new(0.8, 0.8, 0.8), 0.1);
+
+    world.add(ShapeFactory::make_sphere(100.0, 0.0, -100.5, -1.0, mat_ground));
+    world.add(ShapeFactory::make_sphere(0.5, 0.0, 0.0, -1.2, mat_center));
+    world.add(ShapeFactory::make_sphere(0.5, -1.0, 0.0, -1.0, mat_left));
+    world.add(ShapeFactory::make_sphere(0.5, 1.0, 0.0, -1.0, mat_right));
+
+    let camera = options.camera(CameraBuilder::new()
+        .image_width(800)
+        .samples_per_pixel(200)
+        .max_bounces(50)
+        .look_from(Point3::new(0.0, 0.5, 1.0))
+        .look_at(Point3::new(0.0, 0.0, -1.0))
+        .vertical_fov(70.0))
+        .build();
+
+    let (pixels, aovs) = options.render(&camera, &world);
+
+    let elapsed = start.elapsed();
+    println!("Run time: {}", elapsed.as_millis());
+
+        // The sun is far brighter than the rest of the frame, so roll it off instead of clipping.
+    options.save("out-outdoor", &pixels, aovs.as_ref(), &camera, Display::new(-1.0, ToneMappers::AcesFilmic));
+}
+
+#[allow(dead_code)]
+fn many_lights_scene(options: &Options) {
+    let start = Instant::now();
+
+    let mut world = HittableList::new();
+    world.set_sky(Skies::Gradient(SkyGradient::new(Color3::zero(), Color3::new(0.01, 0.01, 0.02))));
+
+    let ground_material = MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5));
+    world.add(ShapeFactory::make_sphere(1000.0, 0.0, -1000.0, -1.0, ground_material));
+
+        // A field of small lamps, sampled through the light tree.
+    for a in -20..20 {
+        for b in -20..20 {
+            let center = Point3::new(a as f64 + 0.9 * MathUtil::rand(), 0.1, b as f64 + 0.9 * MathUtil::rand());
+            let material = if MathUtil::rand() < 0.5 {
+                MaterialFactory::make_emitter(Color3::random_ran(0.3, 1.0), 8.0)
+            } else {
+                MaterialFactory::make_lambertian(Color3::random() * Color3::random())
+            };
+            world.add(ShapeFactory::make_sphere(0.1, center.x, center.y, center.z, material));
+        }
+    }
+
+    let material1 = MaterialFactory::make_dielectric(1.5);
+    world.add(ShapeFactory::make_sphere(1.0, 0.0, 1.0, 0.0, material1));
+    let material2 = MaterialFactory::make_lambertian(Color3::new(0.4, 0.2, 0.1));
+    world.add(ShapeFactory::make_sphere(1.0, -4.0, 1.0, 0.0, material2));
+    let material3 = MaterialFactory::make_metal(Color3::new(0.7, 0.6, 0.5), 0.0);
+    world.add(ShapeFactory::make_sphere(1.0, 4.0, 1.0, 0.0, material3));
+
+    let camera = options.camera(CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(100)
+        .max_bounces(20)
+        .vertical_fov(20.0)
+        .look_from(Point3::new(13.0, 2.0, 3.0))
+        .look_at(Point3::zero())
+        .vector_up(Point3::y_u()))
+        .build();
+
+    let (pixels, aovs) = options.render(&camera, &world);
+
+    let elapsed = start.elapsed();
+    println!("Run time: {}", elapsed.as_millis());
+
+    options.save("out-lights", &pixels, aovs.as_ref(), &camera, Display::DEFAULT);
+}
+
+#[allow(dead_code)]
+fn materials_scene(options: &Options, library_path: Option<&String>) {
+        // A small library in the exporters' dialect, for when no file is given.
+    const LIBRARY: &str = "newmtl brushed steel\nKd 0.6 0.6 0.65\nPm 1.0\nPr 0.3\n\
+                           newmtl car paint\nKd 0.6 0.05 0.05\nPr 0.4\nPc 1.0\nPcr 0.05\n\
+                           newmtl green glass\nKd 0.1 0.1 0.1\nTf 0.8 1.0 0.85\nd 0.0\nNi 1.5\nPr 0.0\n\
+                           newmtl velvet\nKd 0.3 0.1 0.4\nPr 0.9\nPs 1.0\n";
+    let library = match library_path {
+        Some(path) => match MtlLibrary::load(path) {
+            Ok(library) => library,
+            Err(error) => {
+                eprintln!("Cannot read {path}: {error}");
+                return;
+            },
+        },
+        None => MtlLibrary::parse(LIBRARY),
+    };
+
+    let start = Instant::now();
+
+    let mut world = HittableList::new();
+
+    let ground_material = MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5));
+    world.add(ShapeFactory::make_sphere(1000.0, 0.0, -1000.0, -1.0, ground_material));
+
+        // One ball per material, in name order.
+    let mut names: Vec<&String> = library.keys().collect();
+    names.sort();
+    let spacing = 1.2;
+    let first = -0.5 * spacing * (names.len() as f64 - 1.0);
+    for (index, name) in names.iter().enumerate() {
+        let x = first + spacing * index as f64;
+        world.add(ShapeFactory::make_sphere(0.5, x, 0.5, 0.0, library[*name].clone()));
+    }
+
+    let camera = options.camera(CameraBuilder::new()
+        .aspect_ratio(16.0 / 9.0)
+        .image_width(800)
+        .samples_per_pixel(200)
+        .max_bounces(20)
+        .vertical_fov(30.0)
+        .look_from(Point3::new(0.0, 2.0, 6.0))
+        .look_at(Point3::new(0.0, 0.4, 0.0))
+        .vector_up(Point3::y_u()))
+        .build();
+
+    let (pixels, aovs) = options.render(&camera, &world);
+
+    let elapsed = start.elapsed();
+    println!("Run time: {}", elapsed.as_millis());
+
+    options.save("out-materials", &pixels, aovs.as_ref(), &camera, Display::DEFAULT);
+}
+
+#[allow(dead_code)]
+fn caustics_scene(options: &Options) {
+    let mut world = HittableList::new();
+    world.set_sky(Skies::Gradient(SkyGradient::new(Color3::zero(), Color3::zero())));
+
+    let mat_ground = MaterialFactory::make_lambertian(Color3::new(0.7, 0.7, 0.7));
+    let mat_light = MaterialFactory::make_emitter(Color3::new(1.0, 0.9, 0.8), 12.0);
+    let mat_glass = MaterialFactory::make_dielectric(1.5);
+
+    world.add(ShapeFactory::make_quad(Point3::new(-5.0, 0.0, -6.0), Vec3::new(0.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 0.0), mat_ground));
+    world.add(ShapeFactory::make_sphere(0.3, 1.5, 3.0, -1.5, mat_light));
+    world.add(ShapeFactory::make_sphere(0.6, 0.0, 0.6, -1.0, mat_glass));
+
+        // Light focused through the glass ball, which path tracing barely finds.
+        // Rendered with both caustic capable integrators to compare.
+    let integrators = [
+        (IntegratorFactory::make_bidirectional(), "out-caustics-bdpt"),
+        (IntegratorFactory::make_photon_mapping(200_000, 0.05), "out-caustics-photons"),
+    ];
+    for (integrator, stem) in integrators {
+        let start = Instant::now();
+
+        let camera = options.camera(CameraBuilder::new()
+            .image_width(800)
+            .samples_per_pixel(64)
+            .max_bounces(12)
+            .look_from(Point3::new(0.0, 1.5, 2.5))
+            .look_at(Point3::new(0.0, 0.3, -1.0))
+            .vertical_fov(50.0))
+            .integrator(integrator)
+            .build();
+
+        let (pixels, aovs) = options.render(&camera, &world);
+
+        let elapsed = start.elapsed();
+        println!("Run time: {}", elapsed.as_millis());
+
+        options.save(stem, &pixels, aovs.as_ref(), &camera, Display::DEFAULT);
+    }
+}
@@ -1,8 +1,16 @@
 
+use std::sync::Arc;
+use std::f64::consts::PI;
+
 use crate::datatypes::Ray;
 use crate::datatypes::Vec3;
 use crate::datatypes::Color3;
+use crate::datatypes::Onb;
 use crate::datatypes::HitRecord;
+use crate::textures::Textures;
+use crate::textures::TextureFactory;
+use crate::utils::MatUtil;
+use crate::utils::TexUtil;
 use crate::utils::MathUtil;
 
 
@@ -12,6 +20,9 @@ pub enum Materials {
     DifuseLamb(MatLambertian),
     Metal(MatMetal),
     Dielectric(MatDielectric),
+    Subsurface(MatSubsurface),
+    Blend(MatBlend),
+    Principled(MatPrincipled),
     Emitter(MatEmitter)
 }
 
@@ -28,6 +39,28 @@ impl MaterialFactory {
     pub fn make_dielectric(ir: f64) -> Materials {
         Materials::Dielectric(MatDielectric::new(ir))
     }
+    pub fn make_thin_film_dielectric(ir: f64, film_thickness: f64, film_ir: f64) -> Materials {
+        Materials::Dielectric(MatDielectric::new(ir).with_film(ThinFilm::new(film_thickness, film_ir)))
+    }
+    pub fn make_thin_film_metal(albedo: Color3, fuzz: f64, film_thickness: f64, film_ir: f64) -> Materials {
+        Materials::Metal(MatMetal::new(albedo, fuzz).with_film(ThinFilm::new(film_thickness, film_ir)))
+    }
+    pub fn make_anisotropic_metal(albedo: Color3, alpha_x: f64, alpha_y: f64, rotation: f64) -> Materials {
+        Materials::Metal(MatMetal::new(albedo, 0.0).with_anisotropy(Anisotropy::new(alpha_x, alpha_y, rotation)))
+    }
+    pub fn make_subsurface(albedo: Color3, mean_free_path: Color3, ir: f64) -> Materials {
+        Materials::Subsurface(MatSubsurface::new(albedo, mean_free_path, ir))
+    }
+    pub fn make_blend(first: Materials, second: Materials, weight: f64) -> Materials {
+        let weight = TextureFactory::make_solid(Color3::new(weight, weight, weight));
+        Materials::Blend(MatBlend::new(first, second, weight))
+    }
+    pub fn make_textured_blend(first: Materials, second: Materials, weight: Textures) -> Materials {
+        Materials::Blend(MatBlend::new(first, second, weight))
+    }
+    pub fn make_principled(builder: PrincipledBuilder) -> Materials {
+        Materials::Principled(builder.build())
+    }
     pub fn make_emitter(color: Color3, intensity: f64) -> Materials {
         Materials::Emitter(MatEmitter::new(color, intensity))
     }
@@ -36,6 +69,32 @@ impl MaterialFactory {
 
 pub trait Material {
     fn scatter(&self, ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)>;
+    /// BSDF value for light arriving from `direction` and leaving back along `ray`.
+    /// Materials that only scatter into discrete directions keep the zero default.
+    fn eval(&self, _ray: &Ray, _hit_rec: &HitRecord, _direction: &Vec3) -> Color3 {
+        Color3::zero()
+    }
+    /// Solid angle density of `scatter` picking `direction`.
+    fn pdf(&self, _ray: &Ray, _hit_rec: &HitRecord, _direction: &Vec3) -> f64 {
+        0.0
+    }
+    /// Radiance given off at the hit, used when the surface is sampled as a light.
+    fn emitted(&self, _hit_rec: &HitRecord) -> Color3 {
+        Color3::zero()
+    }
+    /// Overall surface color, for albedo feature buffers.
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color3 {
+        Color3::one()
+    }
+    /// Whether lights can't be sampled explicitly at this material.
+    fn is_specular(&self) -> bool {
+        true
+    }
+    /// Whether scattering at the hit is a step of a random walk inside the material,
+    /// which paths count apart from their bounces, see `PathDepth`.
+    fn is_medium_step(&self, _hit_rec: &HitRecord) -> bool {
+        false
+    }
 }
 
 #[derive(Clone)]
@@ -49,50 +108,151 @@ impl MatLambertian {
     pub const GRAY: MatLambertian = MatLambertian { albedo: Color3 { x: 0.5, y: 0.5, z: 0.5 } };
 }
 impl Material for MatLambertian {
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color3 {
+        self.albedo.clone()
+    }
     fn scatter(&self, _ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
-        let mut sc_direction = &hit_rec.normal + &Vec3::random_unit();
+        let mut sc_direction = &hit_rec.shading_normal + &Vec3::random_unit();
         if sc_direction.is_near_zero() {
-            sc_direction = hit_rec.normal.clone();
+            sc_direction = hit_rec.shading_normal.clone();
+        }
+        if sc_direction.dot(&hit_rec.normal) <= 0.0 {
+            return None;
         }
         let sc_ray = Ray::new(hit_rec.p.clone(), sc_direction);
         Some((self.albedo.clone(), Some(sc_ray)))
     }
+    fn eval(&self, _ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> Color3 {
+        if direction.dot(&hit_rec.shading_normal) <= 0.0 || direction.dot(&hit_rec.normal) <= 0.0 {
+            return Color3::zero();
+        }
+        &self.albedo / PI
+    }
+    fn pdf(&self, _ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> f64 {
+        f64::max(0.0, direction.unit().dot(&hit_rec.shading_normal)) / PI
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
 }
 
 
 #[derive(Clone)]
 pub struct MatMetal {
     pub albedo: Color3,
-    pub fuzz: f64
+    pub fuzz: f64,
+    pub film: Option<ThinFilm>,
+    pub anisotropy: Option<Anisotropy>
 }
 impl MatMetal {
     pub fn new(albedo: Color3, _fuzz: f64) -> Self {
         let fuzz = if _fuzz < 1.0 { _fuzz } else { 1.0 };
-        MatMetal { albedo, fuzz }
+        MatMetal { albedo, fuzz, film: None, anisotropy: None }
+    }
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+    pub fn with_anisotropy(mut self, anisotropy: Anisotropy) -> Self {
+        self.anisotropy = Some(anisotropy);
+        self
+    }
+    fn fresnel(&self, cos_theta: f64) -> Color3 {
+        match &self.film {
+            Some(film) => film.reflectance_conductor(cos_theta, &self.albedo),
+            None => &self.albedo + &((Color3::one() - self.albedo.clone()) * f64::powi(1.0 - cos_theta, 5)),
+        }
+    }
+    fn scatter_microfacet(&self, anisotropy: &Anisotropy, ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
+        let frame = hit_rec.frame().rotated(MathUtil::degrees_to_radians(anisotropy.rotation));
+        let ggx = Ggx::new(anisotropy.alpha_x, anisotropy.alpha_y);
+        let wo = frame.to_local(&-&ray.direction().unit());
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let m = ggx.sample_vndf(&wo);
+        let wi = (-&wo).reflect(&m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+            // VNDF sampling leaves F * G2 / G1 as the path weight.
+        let weight = self.fresnel(wo.dot(&m)) * (ggx.g2(&wo, &wi) / ggx.g1(&wo));
+        let sc_ray = Ray::new(hit_rec.p.clone(), frame.local(wi.x, wi.y, wi.z));
+        Some((weight, Some(sc_ray)))
+    }
+    fn local_directions(&self, anisotropy: &Anisotropy, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> (Vec3, Vec3) {
+        let frame = hit_rec.frame().rotated(MathUtil::degrees_to_radians(anisotropy.rotation));
+        (frame.to_local(&-&ray.direction().unit()), frame.to_local(&direction.unit()))
     }
 }
 impl Material for MatMetal {
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color3 {
+        self.albedo.clone()
+    }
     fn scatter(&self, ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
-        let mut reflected = ray.direction().reflect(&hit_rec.normal);
+        if let Some(anisotropy) = &self.anisotropy {
+            return self.scatter_microfacet(anisotropy, ray, hit_rec);
+        }
+        let mut reflected = ray.direction().reflect(&hit_rec.shading_normal);
         reflected = reflected.unit() + (self.fuzz * Vec3::random_unit());
         let sc_ray = Ray::new(hit_rec.p.clone(), reflected);
         if sc_ray.direction().dot(&hit_rec.normal) < 0.0 {
             None
         }
         else {
-            Some((self.albedo.clone(), Some(sc_ray)))
+            let attenuation = match &self.film {
+                Some(film) => {
+                    let cos_theta = f64::min(1.0, hit_rec.shading_normal.dot(&-&ray.direction().unit()));
+                    film.reflectance_conductor(cos_theta, &self.albedo)
+                },
+                None => self.albedo.clone(),
+            };
+            Some((attenuation, Some(sc_ray)))
         }
     }
+    fn eval(&self, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> Color3 {
+        let Some(anisotropy) = &self.anisotropy else {
+            return Color3::zero();
+        };
+        let (wo, wi) = self.local_directions(anisotropy, ray, hit_rec, direction);
+        if wo.z <= 0.0 || wi.z <= 0.0 || direction.dot(&hit_rec.normal) <= 0.0 {
+            return Color3::zero();
+        }
+        let ggx = Ggx::new(anisotropy.alpha_x, anisotropy.alpha_y);
+        let h = (&wo + &wi).unit();
+        self.fresnel(wo.dot(&h)) * (ggx.d(&h) * ggx.g2(&wo, &wi) / (4.0 * wo.z * wi.z))
+    }
+    fn pdf(&self, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> f64 {
+        let Some(anisotropy) = &self.anisotropy else {
+            return 0.0;
+        };
+        let (wo, wi) = self.local_directions(anisotropy, ray, hit_rec, direction);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (&wo + &wi).unit();
+        Ggx::new(anisotropy.alpha_x, anisotropy.alpha_y).pdf_vndf(&wo, &h) / (4.0 * wo.dot(&h))
+    }
+    fn is_specular(&self) -> bool {
+            // Fuzzed reflection has no closed form density, so only the
+            // microfacet model takes part in light sampling.
+        self.anisotropy.is_none()
+    }
 }
 
 
 #[derive(Clone)]
 pub struct MatDielectric {
-    pub ir: f64
+    pub ir: f64,
+    pub film: Option<ThinFilm>
 }
 impl MatDielectric {
     pub fn new(ir: f64) -> Self {
-        MatDielectric { ir }
+        MatDielectric { ir, film: None }
+    }
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
     }
     fn reflectance(cos: f64, ir: f64) -> f64 {
         let mut r0 = (1.0 - ir) / (1.0 + ir);
@@ -106,22 +266,735 @@ impl Material for MatDielectric {
         let ri = if hit_rec.is_front_face { 1.0 / self.ir } else { self.ir };
         let unit_dir = ray.direction().unit();
 
-        let cos_theta = f64::min(1.0, hit_rec.normal.dot(&-&unit_dir));
+        let cos_theta = f64::min(1.0, hit_rec.shading_normal.dot(&-&unit_dir));
         let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
 
         let cannot_refract = ri * sin_theta > 1.0;
 
+        if let Some(film) = &self.film {
+            return Some(self.scatter_film(film, &unit_dir, hit_rec, cos_theta, cannot_refract));
+        }
+
         let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > MathUtil::rand() {
-            unit_dir.reflect(&hit_rec.normal)
+            unit_dir.reflect(&hit_rec.shading_normal)
         }
         else {
-            unit_dir.refract(&hit_rec.normal, ri)
+            unit_dir.refract(&hit_rec.shading_normal, ri)
         };
 
         let sc_ray = Ray::new( hit_rec.p.clone(), direction);
         Some((attenuation, Some(sc_ray)))
     }
 }
+impl MatDielectric {
+    fn scatter_film(&self, film: &ThinFilm, unit_dir: &Vec3, hit_rec: &HitRecord,
+                    cos_theta: f64, cannot_refract: bool) -> (Color3, Option<Ray>) {
+            // The film coats the outside of the surface, so the media swap when leaving.
+        let (n_outer, n_inner) = if hit_rec.is_front_face { (1.0, self.ir) } else { (self.ir, 1.0) };
+        let refl = if cannot_refract { Color3::one() } else { film.reflectance(cos_theta, n_outer, n_inner) };
+
+            // Pick a branch using the mean reflectance, then reweight per channel.
+        let p_refl = (refl.x + refl.y + refl.z) / 3.0;
+        if cannot_refract || MathUtil::rand() < p_refl {
+            let sc_ray = Ray::new(hit_rec.p.clone(), unit_dir.reflect(&hit_rec.shading_normal));
+            (refl / p_refl, Some(sc_ray))
+        }
+        else {
+            let trans = Color3::one() - refl;
+            let sc_ray = Ray::new(hit_rec.p.clone(), unit_dir.refract(&hit_rec.shading_normal, n_outer / n_inner));
+            (trans / (1.0 - p_refl), Some(sc_ray))
+        }
+    }
+}
+
+
+/// Translucent solid (skin, wax, marble, milk) rendered with a volumetric random walk.
+/// Light refracts into the closed surface, then scatters isotropically inside it with
+/// per-channel mean free path and single-scattering albedo until it leaves again.
+/// The walk only relies on the next hit being the inside of the same surface, so any
+/// closed hittable works; nested objects inside the volume are not supported.
+/// Every hit from inside is a medium step, so a whole walk costs the path one bounce.
+#[derive(Clone)]
+pub struct MatSubsurface {
+    pub albedo: Color3,
+    pub mean_free_path: Color3,
+    pub ir: f64
+}
+impl MatSubsurface {
+    pub fn new(albedo: Color3, mean_free_path: Color3, ir: f64) -> Self {
+        MatSubsurface { albedo, mean_free_path, ir }
+    }
+    fn extinction(&self) -> Color3 {
+        let sigma = |mfp: f64| 1.0 / f64::max(mfp, 1e-6);
+        Color3::new(sigma(self.mean_free_path.x), sigma(self.mean_free_path.y), sigma(self.mean_free_path.z))
+    }
+    fn transmittance(sigma_t: &Color3, dist: f64) -> Color3 {
+        Color3::new(f64::exp(-sigma_t.x * dist), f64::exp(-sigma_t.y * dist), f64::exp(-sigma_t.z * dist))
+    }
+    fn cross_boundary(&self, unit_dir: &Vec3, hit_rec: &HitRecord) -> Ray {
+        let ri = if hit_rec.is_front_face { 1.0 / self.ir } else { self.ir };
+        let cos_theta = f64::min(1.0, hit_rec.shading_normal.dot(&-unit_dir));
+        let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+        let cannot_refract = ri * sin_theta > 1.0;
+
+        let direction = if cannot_refract || MatDielectric::reflectance(cos_theta, ri) > MathUtil::rand() {
+            unit_dir.reflect(&hit_rec.shading_normal)
+        }
+        else {
+            unit_dir.refract(&hit_rec.shading_normal, ri)
+        };
+        Ray::new(hit_rec.p.clone(), direction)
+    }
+}
+impl Material for MatSubsurface {
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color3 {
+        self.albedo.clone()
+    }
+    fn scatter(&self, ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
+        let unit_dir = ray.direction().unit();
+        if hit_rec.is_front_face {
+            return Some((Color3::one(), Some(self.cross_boundary(&unit_dir, hit_rec))));
+        }
+
+            // The ray travelled inside the medium up to this hit; sample a free flight
+            // distance from a random channel and weight by the mixture pdf.
+        let sigma_t = self.extinction();
+        let dist = hit_rec.t * ray.direction().len();
+        let channel = usize::min((MathUtil::rand() * 3.0) as usize, 2);
+        let flight = -f64::ln(1.0 - MathUtil::rand()) / sigma_t[channel];
+
+        if flight < dist {
+            let tr = Self::transmittance(&sigma_t, flight);
+            let pdf = (sigma_t.x * tr.x + sigma_t.y * tr.y + sigma_t.z * tr.z) / 3.0;
+            let weight = self.albedo.clone() * sigma_t * tr / pdf;
+            let origin = ray.at(flight / ray.direction().len());
+            Some((weight, Some(Ray::new(origin, Vec3::random_unit()))))
+        }
+        else {
+            let tr = Self::transmittance(&sigma_t, dist);
+            let pdf = (tr.x + tr.y + tr.z) / 3.0;
+            Some((tr / pdf, Some(self.cross_boundary(&unit_dir, hit_rec))))
+        }
+    }
+    fn is_medium_step(&self, hit_rec: &HitRecord) -> bool {
+        !hit_rec.is_front_face
+    }
+}
+
+
+/// Stochastic mix of two materials. Each scatter event picks `second` with
+/// probability `weight` (red channel of the weight texture at the hit UV) and
+/// `first` otherwise. Blends can be nested to mix more than two materials.
+#[derive(Clone)]
+pub struct MatBlend {
+    pub first: Arc<Materials>,
+    pub second: Arc<Materials>,
+    pub weight: Textures
+}
+impl MatBlend {
+    pub fn new(first: Materials, second: Materials, weight: Textures) -> Self {
+        MatBlend { first: Arc::new(first), second: Arc::new(second), weight }
+    }
+    pub fn pick(&self, hit_rec: &HitRecord) -> &Materials {
+        if MathUtil::rand() < self.weight_at(hit_rec) { &self.second } else { &self.first }
+    }
+    fn weight_at(&self, hit_rec: &HitRecord) -> f64 {
+        TexUtil::value(&self.weight, hit_rec.u, hit_rec.v, &hit_rec.p).x.clamp(0.0, 1.0)
+    }
+}
+impl Material for MatBlend {
+    fn albedo(&self, hit_rec: &HitRecord) -> Color3 {
+        let weight = self.weight_at(hit_rec);
+        (1.0 - weight) * MatUtil::albedo(&self.first, hit_rec) + weight * MatUtil::albedo(&self.second, hit_rec)
+    }
+    fn scatter(&self, ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
+        MatUtil::scatter(self.pick(hit_rec), ray, hit_rec)
+    }
+    fn eval(&self, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> Color3 {
+        let weight = self.weight_at(hit_rec);
+        (1.0 - weight) * MatUtil::eval(&self.first, ray, hit_rec, direction)
+            + weight * MatUtil::eval(&self.second, ray, hit_rec, direction)
+    }
+    fn pdf(&self, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> f64 {
+        let weight = self.weight_at(hit_rec);
+        (1.0 - weight) * MatUtil::pdf(&self.first, ray, hit_rec, direction)
+            + weight * MatUtil::pdf(&self.second, ray, hit_rec, direction)
+    }
+    fn emitted(&self, hit_rec: &HitRecord) -> Color3 {
+        let weight = self.weight_at(hit_rec);
+        (1.0 - weight) * MatUtil::emitted(&self.first, hit_rec) + weight * MatUtil::emitted(&self.second, hit_rec)
+    }
+    fn is_specular(&self) -> bool {
+        MatUtil::is_specular(&self.first) || MatUtil::is_specular(&self.second)
+    }
+}
+
+
+/// Disney principled BSDF (Burley 2012, with the 2015 transmission extension).
+/// Each scatter event picks one lobe (diffuse with sheen and subsurface, specular,
+/// clearcoat, transmission) with a probability estimated from its weight, and the
+/// path weight uses the pdf of the whole lobe mixture. Built through `PrincipledBuilder`.
+#[derive(Clone)]
+pub struct MatPrincipled {
+    pub base_color: Color3,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    pub transmission: f64,
+    pub ir: f64,
+    pub subsurface: f64
+}
+
+/// Lobe selection probabilities for one shading point.
+struct PrincipledLobes {
+    diffuse: f64,
+    specular: f64,
+    clearcoat: f64,
+    transmission: f64
+}
+
+#[allow(clippy::too_many_arguments)]
+impl MatPrincipled {
+    fn new(base_color: Color3, metallic: f64, roughness: f64, specular: f64, specular_tint: f64, sheen: f64,
+           sheen_tint: f64, clearcoat: f64, clearcoat_gloss: f64, transmission: f64, ir: f64, subsurface: f64) -> Self {
+        MatPrincipled {
+            base_color,
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            specular,
+            specular_tint,
+            sheen,
+            sheen_tint,
+            clearcoat,
+            clearcoat_gloss,
+            transmission: transmission.clamp(0.0, 1.0),
+            ir,
+            subsurface,
+        }
+    }
+    fn luminance(c: &Color3) -> f64 {
+        0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+    }
+    fn schlick_weight(cos: f64) -> f64 {
+        f64::powi((1.0 - cos).clamp(0.0, 1.0), 5)
+    }
+    fn mix(a: &Color3, b: &Color3, t: f64) -> Color3 {
+        (1.0 - t) * a + t * b
+    }
+    fn tint(&self) -> Color3 {
+        let lum = Self::luminance(&self.base_color);
+        if lum > 0.0 { &self.base_color / lum } else { Color3::one() }
+    }
+    fn specular_color(&self) -> Color3 {
+        let dielectric = self.specular * 0.08 * Self::mix(&Color3::one(), &self.tint(), self.specular_tint);
+        Self::mix(&dielectric, &self.base_color, self.metallic)
+    }
+    fn ggx(&self) -> Ggx {
+        let alpha = f64::max(1e-3, self.roughness * self.roughness);
+        Ggx::new(alpha, alpha)
+    }
+    fn clearcoat_alpha(&self) -> f64 {
+        (1.0 - self.clearcoat_gloss) * 0.1 + self.clearcoat_gloss * 0.001
+    }
+    fn relative_ir(&self, front_face: bool) -> f64 {
+        if front_face { self.ir } else { 1.0 / self.ir }
+    }
+    fn lobes(&self, wo: &Vec3, front_face: bool) -> PrincipledLobes {
+        let dielectric = 1.0 - self.metallic;
+        if !front_face && self.transmission > 0.0 {
+                // Inside a transmissive object only the rough dielectric interface applies.
+            return PrincipledLobes { diffuse: 0.0, specular: 0.0, clearcoat: 0.0, transmission: 1.0 };
+        }
+        let fresnel = &self.specular_color() + &((Color3::one() - self.specular_color()) * Self::schlick_weight(wo.z));
+        let diffuse = dielectric * (1.0 - self.transmission) * f64::max(Self::luminance(&self.base_color), 0.01);
+        let specular = (1.0 - dielectric * self.transmission) * f64::max(Self::luminance(&fresnel), 0.05);
+        let clearcoat = 0.25 * self.clearcoat * (0.04 + 0.96 * Self::schlick_weight(wo.z));
+        let transmission = dielectric * self.transmission;
+
+        let total = diffuse + specular + clearcoat + transmission;
+        PrincipledLobes {
+            diffuse: diffuse / total,
+            specular: specular / total,
+            clearcoat: clearcoat / total,
+            transmission: transmission / total,
+        }
+    }
+    fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+        let a2 = alpha * alpha;
+        let t = 1.0 + (a2 - 1.0) * cos_h * cos_h;
+        (a2 - 1.0) / (PI * f64::ln(a2) * t)
+    }
+    fn smith_g1(cos: f64, alpha: f64) -> f64 {
+        let a2 = alpha * alpha;
+        let c2 = cos * cos;
+        1.0 / (cos.abs() + f64::sqrt(a2 + c2 - a2 * c2))
+    }
+
+    /// BSDF value for local directions `wo` (towards the viewer) and `wi`.
+    fn eval_local(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> Color3 {
+        let mut f = Color3::zero();
+        if wo.z <= 0.0 {
+            return f;
+        }
+        let dielectric = 1.0 - self.metallic;
+        let inside = !front_face && self.transmission > 0.0;
+
+        if wi.z > 0.0 && !inside {
+            let h = (wo + wi).unit();
+            let cos_d = wi.dot(&h);
+            let fl = Self::schlick_weight(wi.z);
+            let fv = Self::schlick_weight(wo.z);
+
+                // Diffuse with retro-reflection, blended towards the subsurface approximation.
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+            let fss90 = self.roughness * cos_d * cos_d;
+            let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+            let ss = 1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5);
+            let diffuse = ((1.0 - self.subsurface) * fd + self.subsurface * ss) / PI;
+            let sheen = self.sheen * Self::schlick_weight(cos_d) * Self::mix(&Color3::one(), &self.tint(), self.sheen_tint);
+            f += dielectric * (1.0 - self.transmission) * (diffuse * &self.base_color + sheen);
+
+                // Specular reflection.
+            let ggx = self.ggx();
+            let spec0 = self.specular_color();
+            let fresnel = &spec0 + &((Color3::one() - spec0.clone()) * Self::schlick_weight(cos_d));
+            let spec = ggx.d(&h) * ggx.g2(wo, wi) / (4.0 * wi.z * wo.z);
+            f += (1.0 - dielectric * self.transmission) * spec * fresnel;
+
+                // Clearcoat.
+            if self.clearcoat > 0.0 {
+                let fr = 0.04 + 0.96 * Self::schlick_weight(cos_d);
+                let gr = Self::smith_g1(wo.z, 0.25) * Self::smith_g1(wi.z, 0.25);
+                let dr = Self::gtr1(h.z, self.clearcoat_alpha());
+                f += Color3::one() * (0.25 * self.clearcoat * dr * fr * gr);
+            }
+        }
+
+        let transmission = if inside { 1.0 } else { dielectric * self.transmission };
+        if transmission > 0.0 {
+            f += transmission * self.eval_dielectric(wo, wi, front_face) * &self.base_color.clone();
+        }
+        f
+    }
+    fn eval_dielectric(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64 {
+        let ggx = self.ggx();
+        let eta = self.relative_ir(front_face);
+        let reflect = wi.z > 0.0;
+        let etap = if reflect { 1.0 } else { eta };
+        let mut wm = wi * etap + wo.clone();
+        if wi.z == 0.0 || wm.len_sqr() == 0.0 {
+            return 0.0;
+        }
+        wm = wm.unit();
+        if wm.z < 0.0 {
+            wm.flip();
+        }
+        if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 {
+            return 0.0;
+        }
+        let fresnel = Self::fresnel_dielectric(wo.dot(&wm), eta);
+        if reflect {
+            ggx.d(&wm) * ggx.g2(wo, wi) * fresnel / (4.0 * wi.z * wo.z).abs()
+        }
+        else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            let ft = ggx.d(&wm) * (1.0 - fresnel) * ggx.g2(wo, wi)
+                * (wi.dot(&wm) * wo.dot(&wm) / (wi.z * wo.z * denom * denom)).abs();
+            ft / (etap * etap)
+        }
+    }
+    fn pdf_local(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        let lobes = self.lobes(wo, front_face);
+        let mut pdf = 0.0;
+        if wi.z > 0.0 {
+            let h = (wo + wi).unit();
+            pdf += lobes.diffuse * wi.z / PI;
+            pdf += lobes.specular * self.ggx().pdf_vndf(wo, &h) / (4.0 * wo.dot(&h));
+            if lobes.clearcoat > 0.0 {
+                pdf += lobes.clearcoat * Self::gtr1(h.z, self.clearcoat_alpha()) * h.z / (4.0 * wo.dot(&h));
+            }
+        }
+        if lobes.transmission > 0.0 {
+            pdf += lobes.transmission * self.pdf_dielectric(wo, wi, front_face);
+        }
+        pdf
+    }
+    fn pdf_dielectric(&self, wo: &Vec3, wi: &Vec3, front_face: bool) -> f64 {
+        let ggx = self.ggx();
+        let eta = self.relative_ir(front_face);
+        let reflect = wi.z > 0.0;
+        let etap = if reflect { 1.0 } else { eta };
+        let mut wm = wi * etap + wo.clone();
+        if wi.z == 0.0 || wm.len_sqr() == 0.0 {
+            return 0.0;
+        }
+        wm = wm.unit();
+        if wm.z < 0.0 {
+            wm.flip();
+        }
+        if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 {
+            return 0.0;
+        }
+        let fresnel = Self::fresnel_dielectric(wo.dot(&wm), eta);
+        if reflect {
+            ggx.pdf_vndf(wo, &wm) / (4.0 * wo.dot(&wm).abs()) * fresnel
+        }
+        else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            ggx.pdf_vndf(wo, &wm) * wi.dot(&wm).abs() / (denom * denom) * (1.0 - fresnel)
+        }
+    }
+    fn sample_local(&self, wo: &Vec3, front_face: bool) -> Option<Vec3> {
+        let lobes = self.lobes(wo, front_face);
+        let mut u = MathUtil::rand();
+        if u < lobes.diffuse {
+            return Some(Vec3::random_cosine_direction());
+        }
+        u -= lobes.diffuse;
+        if u < lobes.specular {
+            let m = self.ggx().sample_vndf(wo);
+            return Some((-wo).reflect(&m));
+        }
+        u -= lobes.specular;
+        if u < lobes.clearcoat {
+            let a2 = self.clearcoat_alpha() * self.clearcoat_alpha();
+            let cos_h = f64::sqrt(f64::max(0.0, (1.0 - f64::powf(a2, 1.0 - MathUtil::rand())) / (1.0 - a2)));
+            let sin_h = f64::sqrt(f64::max(0.0, 1.0 - cos_h * cos_h));
+            let phi = 2.0 * PI * MathUtil::rand();
+            let h = Vec3::new(sin_h * f64::cos(phi), sin_h * f64::sin(phi), cos_h);
+            return Some((-wo).reflect(&h));
+        }
+        let m = self.ggx().sample_vndf(wo);
+        let eta = self.relative_ir(front_face);
+        if MathUtil::rand() < Self::fresnel_dielectric(wo.dot(&m), eta) {
+            return Some((-wo).reflect(&m));
+        }
+        Self::refract_local(wo, &m, eta)
+    }
+    fn refract_local(wo: &Vec3, m: &Vec3, eta: f64) -> Option<Vec3> {
+        let cos_i = wo.dot(m);
+        let sin2_t = f64::max(0.0, 1.0 - cos_i * cos_i) / (eta * eta);
+        if sin2_t >= 1.0 {
+            return None;
+        }
+        let cos_t = f64::sqrt(1.0 - sin2_t);
+        Some(-wo / eta + (cos_i / eta - cos_t) * m)
+    }
+    fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+        let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+        let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+        if sin2_t >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = f64::sqrt(1.0 - sin2_t);
+        let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        0.5 * (r_parl * r_parl + r_perp * r_perp)
+    }
+}
+impl Material for MatPrincipled {
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color3 {
+        self.base_color.clone()
+    }
+    fn scatter(&self, ray: &Ray, hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
+        let frame: Onb = hit_rec.frame();
+        let wo = frame.to_local(&-&ray.direction().unit());
+        let wi = self.sample_local(&wo, hit_rec.is_front_face)?;
+        let pdf = self.pdf_local(&wo, &wi, hit_rec.is_front_face);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let direction = frame.local(wi.x, wi.y, wi.z);
+            // Reject directions that end up on the wrong side of the geometric surface.
+        if (direction.dot(&hit_rec.normal) > 0.0) != (wi.z > 0.0) {
+            return None;
+        }
+        let weight = self.eval_local(&wo, &wi, hit_rec.is_front_face) * (wi.z.abs() / pdf);
+        Some((weight, Some(Ray::new(hit_rec.p.clone(), direction))))
+    }
+    fn eval(&self, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> Color3 {
+        let frame = hit_rec.frame();
+        let wo = frame.to_local(&-&ray.direction().unit());
+        let wi = frame.to_local(&direction.unit());
+        if (direction.dot(&hit_rec.normal) > 0.0) != (wi.z > 0.0) {
+            return Color3::zero();
+        }
+        self.eval_local(&wo, &wi, hit_rec.is_front_face)
+    }
+    fn pdf(&self, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> f64 {
+        let frame = hit_rec.frame();
+        let wo = frame.to_local(&-&ray.direction().unit());
+        self.pdf_local(&wo, &frame.to_local(&direction.unit()), hit_rec.is_front_face)
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
+}
+
+pub struct PrincipledBuilder {
+    base_color: Color3,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_gloss: f64,
+    transmission: f64,
+    ir: f64,
+    subsurface: f64,
+}
+
+#[allow(unused)]
+impl PrincipledBuilder {
+    pub fn new(base_color: Color3) -> Self {
+        Self {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ir: 1.5,
+            subsurface: 0.0,
+        }
+    }
+
+    pub fn base_color(mut self, base_color: Color3) -> Self {
+        self.base_color = base_color;
+        self
+    }
+
+    pub fn metallic(mut self, metallic: f64) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn roughness(mut self, roughness: f64) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn specular(mut self, specular: f64) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn specular_tint(mut self, specular_tint: f64) -> Self {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    pub fn sheen(mut self, sheen: f64) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn sheen_tint(mut self, sheen_tint: f64) -> Self {
+        self.sheen_tint = sheen_tint;
+        self
+    }
+
+    pub fn clearcoat(mut self, clearcoat: f64) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn clearcoat_gloss(mut self, clearcoat_gloss: f64) -> Self {
+        self.clearcoat_gloss = clearcoat_gloss;
+        self
+    }
+
+    pub fn transmission(mut self, transmission: f64) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn ir(mut self, ir: f64) -> Self {
+        self.ir = ir;
+        self
+    }
+
+    pub fn subsurface(mut self, subsurface: f64) -> Self {
+        self.subsurface = subsurface;
+        self
+    }
+
+    pub fn build(self) -> MatPrincipled {
+        MatPrincipled::new(
+            self.base_color,
+            self.metallic,
+            self.roughness,
+            self.specular,
+            self.specular_tint,
+            self.sheen,
+            self.sheen_tint,
+            self.clearcoat,
+            self.clearcoat_gloss,
+            self.transmission,
+            self.ir,
+            self.subsurface,
+        )
+    }
+}
+
+
+/// Anisotropic GGX roughness for brushed metals. `alpha_x` runs along the hit
+/// record tangent (direction of increasing u), `alpha_y` along the bitangent, and
+/// `rotation` (degrees) turns the brushing direction around the normal.
+#[derive(Clone)]
+pub struct Anisotropy {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+    pub rotation: f64
+}
+impl Anisotropy {
+    pub fn new(alpha_x: f64, alpha_y: f64, rotation: f64) -> Self {
+        Anisotropy { alpha_x, alpha_y, rotation }
+    }
+}
+
+
+/// Trowbridge-Reitz (GGX) microfacet distribution in the local shading frame (z up).
+pub struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64
+}
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Ggx { alpha_x: f64::max(alpha_x, 1e-4), alpha_y: f64::max(alpha_y, 1e-4) }
+    }
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let ax = self.alpha_x * w.x;
+        let ay = self.alpha_y * w.y;
+        let tan2 = (ax * ax + ay * ay) / (w.z * w.z);
+        0.5 * (-1.0 + f64::sqrt(1.0 + tan2))
+    }
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+    pub fn d(&self, m: &Vec3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let x = m.x / self.alpha_x;
+        let y = m.y / self.alpha_y;
+        let t = x * x + y * y + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+    /// Density of `m` under `sample_vndf(wo)`.
+    pub fn pdf_vndf(&self, wo: &Vec3, m: &Vec3) -> f64 {
+        self.g1(wo) * f64::max(0.0, wo.dot(m)) * self.d(m) / wo.z.abs()
+    }
+    /// Samples a visible microfacet normal for `wo` (Heitz 2018).
+    pub fn sample_vndf(&self, wo: &Vec3) -> Vec3 {
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit();
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / f64::sqrt(lensq) } else { Vec3::x_u() };
+        let t2 = vh.cross(&t1);
+
+        let r = f64::sqrt(MathUtil::rand());
+        let phi = 2.0 * PI * MathUtil::rand();
+        let p1 = r * f64::cos(phi);
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * r * f64::sin(phi);
+        let p3 = f64::sqrt(f64::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+        let nh = p1 * &t1 + p2 * &t2 + p3 * &vh;
+
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, f64::max(1e-6, nh.z)).unit()
+    }
+}
+
+
+/// Thin transparent layer on top of a surface (soap film, oil slick, lens coating).
+/// Reflectance is computed from two-beam Airy interference at a few wavelengths per
+/// color channel, so it varies with viewing angle and film thickness.
+#[derive(Clone)]
+pub struct ThinFilm {
+    pub thickness: f64,     // Film thickness in nanometres
+    pub ir: f64             // Film refractive index
+}
+impl ThinFilm {
+    const WAVELENGTHS: [[f64; 4]; 3] = [
+        [590.0, 620.0, 650.0, 680.0],
+        [500.0, 525.0, 550.0, 575.0],
+        [410.0, 435.0, 460.0, 485.0],
+    ];
+
+    pub fn new(thickness: f64, ir: f64) -> Self {
+        ThinFilm { thickness, ir }
+    }
+    /// Reflectance of the film over a dielectric substrate, light arriving from `n_outer`.
+    pub fn reflectance(&self, cos_i: f64, n_outer: f64, n_inner: f64) -> Color3 {
+        let sin_i = f64::sqrt(f64::max(0.0, 1.0 - cos_i * cos_i));
+        let sin_f = n_outer * sin_i / self.ir;
+        let sin_t = n_outer * sin_i / n_inner;
+        if sin_f >= 1.0 || sin_t >= 1.0 {
+            return Color3::one();
+        }
+        let cos_f = f64::sqrt(1.0 - sin_f * sin_f);
+        let cos_t = f64::sqrt(1.0 - sin_t * sin_t);
+
+        let (r12_s, r12_p) = Self::fresnel_amplitudes(n_outer, self.ir, cos_i, cos_f);
+        let (r23_s, r23_p) = Self::fresnel_amplitudes(self.ir, n_inner, cos_f, cos_t);
+        self.per_channel(cos_f, |_, phase| {
+            0.5 * (Self::airy(r12_s, r23_s, phase) + Self::airy(r12_p, r23_p, phase))
+        })
+    }
+    /// Reflectance of the film over a metal. The bare metal is approximated by a real
+    /// amplitude `-sqrt(albedo)` (half-wave phase shift), and the film/metal interface
+    /// is chosen so that a zero-thickness film reproduces the plain albedo.
+    pub fn reflectance_conductor(&self, cos_i: f64, albedo: &Color3) -> Color3 {
+        let sin_i = f64::sqrt(f64::max(0.0, 1.0 - cos_i * cos_i));
+        let sin_f = sin_i / self.ir;
+        let cos_f = f64::sqrt(1.0 - sin_f * sin_f);
+        let (r12_s, r12_p) = Self::fresnel_amplitudes(1.0, self.ir, cos_i, cos_f);
+
+        self.per_channel(cos_f, |c, phase| {
+            let r13 = -f64::sqrt(albedo[c].clamp(0.0, 1.0));
+            let r23_s = (r13 - r12_s) / (1.0 - r12_s * r13);
+            let r23_p = (r13 - r12_p) / (1.0 - r12_p * r13);
+            0.5 * (Self::airy(r12_s, r23_s, phase) + Self::airy(r12_p, r23_p, phase))
+        })
+    }
+    fn per_channel(&self, cos_f: f64, refl: impl Fn(usize, f64) -> f64) -> Color3 {
+        let mut color = Color3::zero();
+        for (c, wavelengths) in Self::WAVELENGTHS.iter().enumerate() {
+            let sum: f64 = wavelengths.iter().map(|l| refl(c, self.phase(cos_f, *l))).sum();
+            color[c] = sum / wavelengths.len() as f64;
+        }
+        color
+    }
+    fn phase(&self, cos_f: f64, wavelength: f64) -> f64 {
+        4.0 * PI * self.ir * self.thickness * cos_f / wavelength
+    }
+    fn fresnel_amplitudes(n1: f64, n2: f64, cos1: f64, cos2: f64) -> (f64, f64) {
+        let rs = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+        let rp = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+        (rs, rp)
+    }
+    fn airy(r12: f64, r23: f64, phase: f64) -> f64 {
+        let cross = 2.0 * r12 * r23 * f64::cos(phase);
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    }
+}
 
 
 #[derive(Clone)]
@@ -135,8 +1008,14 @@ impl MatEmitter {
     }
 }
 impl Material for MatEmitter {
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color3 {
+        self.color.clone()
+    }
     fn scatter(&self, _ray: &Ray, _hit_rec: &HitRecord) -> Option<(Color3, Option<Ray>)> {
         let attenuation = &self.color * self.intensity;
         Some((attenuation, None))
     }
+    fn emitted(&self, _hit_rec: &HitRecord) -> Color3 {
+        &self.color * self.intensity
+    }
 }
\ No newline at end of file
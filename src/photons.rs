@@ -0,0 +1,232 @@
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+
+use crate::aovs::LightSplit;
+use crate::datatypes::Ray;
+use crate::datatypes::Vec3;
+use crate::datatypes::Point3;
+use crate::datatypes::Color3;
+use crate::datatypes::Interval;
+use crate::datatypes::Hittable;
+use crate::datatypes::HitRecord;
+use crate::datatypes::Aabb;
+use crate::integrators::IntPath;
+use crate::integrators::PathDepth;
+use crate::light_tree::LightPowerTable;
+use crate::materials::Materials;
+use crate::shapes::HittableList;
+use crate::utils::MatUtil;
+use crate::utils::LightUtil;
+use crate::utils::SkyUtil;
+use crate::utils::MathUtil;
+
+
+/// Light packet that reached a diffuse surface through specular bounces only.
+#[derive(Clone)]
+pub struct Photon {
+    pub p: Point3,
+    pub direction: Vec3,    // Unit direction of travel when it landed
+    pub normal: Vec3,       // Geometric normal of the surface it landed on
+    pub power: Color3
+}
+
+/// Kd-tree over photons, stored implicitly: each range's median is its root.
+#[derive(Clone)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>        // Split axis of the node at each index
+}
+#[allow(unused)]
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        let len = photons.len();
+        Self::build(&mut photons, &mut axes, 0, len);
+        PhotonMap { photons, axes }
+    }
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+    /// Calls `visit` on every photon within `sqrt(radius_sqr)` of `p`.
+    pub fn for_each_near<F: FnMut(&Photon)>(&self, p: &Point3, radius_sqr: f64, visit: &mut F) {
+        self.query(p, radius_sqr, 0, self.photons.len(), visit);
+    }
+    fn build(photons: &mut [Photon], axes: &mut [usize], lo: usize, hi: usize) {
+        if hi <= lo {
+            return;
+        }
+        let bounds = photons[lo..hi].iter().fold(Aabb::empty(), |acc, photon| acc.union(&Aabb::new(&photon.p, &photon.p)));
+        let axis = bounds.longest_axis();
+        let mid = (lo + hi) / 2;
+        photons[lo..hi].select_nth_unstable_by(mid - lo, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+        axes[mid] = axis;
+        Self::build(photons, axes, lo, mid);
+        Self::build(photons, axes, mid + 1, hi);
+    }
+    fn query<F: FnMut(&Photon)>(&self, p: &Point3, radius_sqr: f64, lo: usize, hi: usize, visit: &mut F) {
+        if hi <= lo {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        if (&photon.p - p).len_sqr() <= radius_sqr {
+            visit(photon);
+        }
+        let d = p[self.axes[mid]] - photon.p[self.axes[mid]];
+        let (near, far) = if d < 0.0 { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+        self.query(p, radius_sqr, near.0, near.1, visit);
+        if d * d <= radius_sqr {
+            self.query(p, radius_sqr, far.0, far.1, visit);
+        }
+    }
+}
+
+
+/// Progressive photon mapping for caustics (Knaus & Zwicker 2011). Each pass shoots
+/// photons from the lights, keeps the ones that land on a diffuse surface after
+/// specular bounces, and path traces the image with them standing in for caustic
+/// paths. The gather radius shrinks every pass so the average converges.
+pub struct PhotonMapper<'a> {
+    world: &'a HittableList,
+    power: &'a LightPowerTable,
+    max_depth: u32
+}
+#[allow(unused)]
+impl<'a> PhotonMapper<'a> {
+    /// Controls how fast the gather radius shrinks, between 0 and 1.
+    pub const ALPHA: f64 = 2.0 / 3.0;
+
+    pub fn new(world: &'a HittableList, power: &'a LightPowerTable, max_depth: u32) -> Self {
+        PhotonMapper { world, power, max_depth }
+    }
+    /// Squared gather radius of pass `pass` (from 0) when the first one uses `radius`.
+    pub fn radius_sqr(radius: f64, pass: u32) -> f64 {
+        (1..=pass).fold(radius * radius, |r_sqr, i| r_sqr * (i as f64 + Self::ALPHA) / (i as f64 + 1.0))
+    }
+    /// Shoots `count` photons and keeps the caustic ones. Every photon seeds its own
+    /// random numbers from `seed`, so the map is the same on any number of threads.
+    pub fn shoot(&self, count: usize, seed: u64) -> PhotonMap {
+        let photons: Vec<Photon> = (0..count).into_par_iter()
+            .filter_map(|index| {
+                MathUtil::seed(MathUtil::hash(&[seed, index as u64]));
+                self.trace_photon(count)
+            })
+            .collect();
+        PhotonMap::new(photons)
+    }
+    fn trace_photon(&self, count: usize) -> Option<Photon> {
+        let (index, pmf) = self.power.sample()?;
+        let light = &self.world.lights()[index];
+        let position = LightUtil::sample_position(light)?;
+        let (direction, pdf_dir) = LightUtil::sample_direction(light, &position)?;
+        if pdf_dir <= 0.0 {
+            return None;
+        }
+        let cos_theta = position.normal.as_ref().map_or(1.0, |n| n.dot(&direction).abs());
+        let mut power = LightUtil::emission(light, &position, &direction) * (cos_theta / (pmf * position.pdf * pdf_dir * count as f64));
+        let mut ray = Ray::new(position.p, direction);
+
+        let mut depth = PathDepth::new(self.max_depth);
+        while !depth.is_spent() {
+            let hit_rec = self.world.hit(&ray, &Interval::HIT_EVAL)?;
+            let material = MatUtil::resolve(&hit_rec.material, &hit_rec);
+            if MatUtil::is_emissive(material) {
+                return None;
+            }
+            if !MatUtil::is_specular(material) {
+                    // Direct hits are left to light sampling.
+                if depth.bounces == self.max_depth {
+                    return None;
+                }
+                return Some(Photon { p: hit_rec.p.clone(), direction: ray.direction().unit(), normal: hit_rec.normal.clone(), power });
+            }
+            let (attenuation, Some(next_ray)) = MatUtil::scatter(material, &ray, &hit_rec)? else {
+                return None;
+            };
+            power = power * attenuation;
+            ray = next_ray;
+            depth = depth.next(material, &hit_rec);
+        }
+        None
+    }
+    /// Radiance along the camera `ray`, with caustics read from `map`.
+    pub fn li(&self, ray: &Ray, map: &PhotonMap, radius_sqr: f64) -> Color3 {
+        self.li_split(ray, map, radius_sqr).0.total()
+    }
+    /// `li` split by bounces, along with the first hit of `ray`. Gathered caustics
+    /// took at least two bounces.
+    pub fn li_split(&self, ray: &Ray, map: &PhotonMap, radius_sqr: f64) -> (LightSplit, Option<HitRecord>) {
+        let hit = self.world.hit(ray, &Interval::HIT_EVAL);
+        (self.shade(ray, hit.as_ref(), PathDepth::new(self.max_depth), map, radius_sqr, None, false, false), hit)
+    }
+        // Path tracer that drops light reaching an emitter through specular bounces
+        // after a diffuse one, since the photons already carry it.
+    #[allow(clippy::too_many_arguments)]
+    fn trace(&self, ray: &Ray, depth: PathDepth, map: &PhotonMap, radius_sqr: f64, bsdf_pdf: Option<f64>,
+             after_diffuse: bool, caustic: bool) -> LightSplit {
+        if depth.is_spent() {
+            return LightSplit::zero();
+        }
+        let hit = self.world.hit(ray, &Interval::HIT_EVAL);
+        self.shade(ray, hit.as_ref(), depth, map, radius_sqr, bsdf_pdf, after_diffuse, caustic)
+    }
+        // `trace` once `ray` is known to first hit `hit`.
+    #[allow(clippy::too_many_arguments)]
+    fn shade(&self, ray: &Ray, hit: Option<&HitRecord>, depth: PathDepth, map: &PhotonMap, radius_sqr: f64,
+             bsdf_pdf: Option<f64>, after_diffuse: bool, caustic: bool) -> LightSplit {
+        if depth.is_spent() {
+            return LightSplit::zero();
+        }
+
+        let Some(hr) = hit else {
+            let mut sky = SkyUtil::radiance(&self.world.sky, ray.direction());
+            if bsdf_pdf.is_none() {
+                for light in self.world.lights() {
+                    sky += LightUtil::le(light, ray.direction());
+                }
+            }
+            return LightSplit { emission: sky, ..LightSplit::zero() };
+        };
+
+        let material = MatUtil::resolve(&hr.material, hr);
+        let specular = MatUtil::is_specular(material);
+        let direct = IntPath::sample_light(ray, hr, material, self.world);
+        let gathered = if specular { Color3::zero() } else { self.gather(ray, hr, material, map, radius_sqr) };
+
+        match MatUtil::scatter(material, ray, hr) {
+            Some((att, Some(sc_ray))) => {
+                let pdf = if specular { None } else { Some(MatUtil::pdf(material, ray, hr, sc_ray.direction())) };
+                let caustic = specular && (caustic || after_diffuse);
+                let next = self.trace(&sc_ray, depth.next(material, hr), map, radius_sqr, pdf, after_diffuse || !specular, caustic);
+                LightSplit {
+                    emission: Color3::zero(),
+                    direct: direct + att.clone() * next.emission,
+                    indirect: gathered + att * (next.direct + next.indirect),
+                }
+            },
+            Some((att, None)) => {
+                let photon_light = self.world.light_index(hr).is_some_and(|index| self.power.pmf(index) > 0.0);
+                if caustic && photon_light {
+                    return LightSplit::zero();
+                }
+                LightSplit { emission: att * IntPath::emitter_weight(ray, hr, self.world, bsdf_pdf), ..LightSplit::zero() }
+            },
+            None => LightSplit { direct, indirect: gathered, ..LightSplit::zero() },
+        }
+    }
+        // Density estimate of the caustic radiance leaving towards the camera.
+    fn gather(&self, ray: &Ray, hit_rec: &HitRecord, material: &Materials, map: &PhotonMap, radius_sqr: f64) -> Color3 {
+        let mut flux = Color3::zero();
+        map.for_each_near(&hit_rec.p, radius_sqr, &mut |photon| {
+            if photon.normal.dot(&hit_rec.normal) <= 0.0 {
+                return;
+            }
+            flux += MatUtil::eval(material, ray, hit_rec, &-&photon.direction) * photon.power.clone();
+        });
+        flux / (PI * radius_sqr)
+    }
+}
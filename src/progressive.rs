@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+
+/// When `Camera::render_progressive` stops adding passes; whichever limit is reached
+/// first. Without any limit the camera's `samples_per_pixel` is used.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RenderBudget {
+    pub samples: Option<u32>,       // Samples per pixel
+    pub time: Option<Duration>,     // Wall-clock time, checked after each pass
+    pub noise: Option<f64>,         // Relative RMS error of the image, see `PixelMoments`
+    pub pass_samples: u32           // Samples per pixel added by each pass
+}
+#[allow(unused)]
+impl RenderBudget {
+    pub fn new() -> Self {
+        RenderBudget { samples: None, time: None, noise: None, pass_samples: 1 }
+    }
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = Some(samples);
+        self
+    }
+    pub fn with_time(mut self, time: Duration) -> Self {
+        self.time = Some(time);
+        self
+    }
+    pub fn with_noise(mut self, noise: f64) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+    pub fn with_pass_samples(mut self, pass_samples: u32) -> Self {
+        self.pass_samples = u32::max(pass_samples, 1);
+        self
+    }
+    /// The budget, limited to `samples` when nothing else limits it.
+    pub fn or_samples(mut self, samples: u32) -> Self {
+        if self.samples.is_none() && self.time.is_none() && self.noise.is_none() {
+            self.samples = Some(samples);
+        }
+        self
+    }
+    pub fn is_spent(&self, pass: &PassInfo) -> bool {
+        self.samples.is_some_and(|samples| pass.samples >= samples)
+            || self.time.is_some_and(|time| pass.elapsed >= time)
+            || self.noise.is_some_and(|noise| pass.noise.is_some_and(|estimate| estimate <= noise))
+    }
+}
+
+
+/// State of a progressive render after a pass, handed to the pass callback.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PassInfo {
+    pub pass: u32,                  // Passes done
+    pub samples: u32,               // Samples per pixel so far, on average when sampling adaptively
+    pub elapsed: Duration,
+    pub noise: Option<f64>          // Relative RMS error, known once every pixel has two samples
+}
+
+
+/// Which pixels get more samples when the camera samples adaptively: every pixel
+/// takes `min_samples`, then pixels whose relative error is above `threshold` take
+/// `min_samples` more each pass, up to `max_samples`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64          // Standard error of the mean over the mean luminance
+}
+#[allow(unused)]
+impl AdaptiveSampling {
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f64) -> Self {
+        let min_samples = u32::max(min_samples, 2);
+        AdaptiveSampling { min_samples, max_samples: u32::max(max_samples, min_samples), threshold }
+    }
+    /// Samples to add to a pixel that has `moments` so far, 0 once it has converged.
+    pub fn samples_for(&self, moments: &Moments) -> u32 {
+        if moments.count < self.min_samples {
+            return self.min_samples - moments.count;
+        }
+        let converged = moments.relative_error().is_some_and(|error| error <= self.threshold);
+        if converged { 0 } else { u32::min(self.min_samples, self.max_samples - moments.count) }
+    }
+}
+
+
+/// Running luminance sums of the samples of one pixel.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Moments {
+    sum: f64,
+    sum_sqr: f64,
+    pub count: u32
+}
+#[allow(unused)]
+impl Moments {
+        // Dark pixels are judged against this much luminance, or they'd never converge.
+    const MIN_MEAN: f64 = 1e-2;
+
+    pub fn add(&mut self, luminance: f64) {
+        self.sum += luminance;
+        self.sum_sqr += luminance * luminance;
+        self.count += 1;
+    }
+    pub fn merge(&mut self, other: &Moments) {
+        self.sum += other.sum;
+        self.sum_sqr += other.sum_sqr;
+        self.count += other.count;
+    }
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum / self.count as f64 }
+    }
+    /// Squared standard error of the mean, known from the second sample on.
+    pub fn error_sqr(&self) -> Option<f64> {
+        if self.count < 2 {
+            return None;
+        }
+        let n = self.count as f64;
+        let variance = f64::max(self.sum_sqr / n - self.mean() * self.mean(), 0.0) * n / (n - 1.0);
+        Some(variance / n)
+    }
+    pub fn relative_error(&self) -> Option<f64> {
+        Some(self.error_sqr()?.sqrt() / f64::max(self.mean(), Self::MIN_MEAN))
+    }
+}
+
+
+/// `Moments` of every pixel of the image.
+pub struct PixelMoments {
+    pixels: Vec<Moments>
+}
+#[allow(unused)]
+impl PixelMoments {
+    pub fn new(count: usize) -> Self {
+        PixelMoments { pixels: vec![Moments::default(); count] }
+    }
+    pub fn add(&mut self, index: usize, luminance: f64) {
+        self.pixels[index].add(luminance);
+    }
+    pub fn merge(&mut self, index: usize, moments: &Moments) {
+        self.pixels[index].merge(moments);
+    }
+    pub fn get(&self, index: usize) -> &Moments {
+        &self.pixels[index]
+    }
+    pub fn mean(&self, index: usize) -> f64 {
+        self.pixels[index].mean()
+    }
+    pub fn error_sqr(&self, index: usize) -> Option<f64> {
+        self.pixels[index].error_sqr()
+    }
+    /// Samples taken over the whole image.
+    pub fn samples(&self) -> u64 {
+        self.pixels.iter().map(|moments| moments.count as u64).sum()
+    }
+    /// RMS standard error over the image relative to its mean luminance.
+    pub fn relative_error(&self) -> Option<f64> {
+        let count = self.pixels.len() as f64;
+        let error_sqr = self.pixels.iter().map(|moments| moments.error_sqr()).sum::<Option<f64>>()? / count;
+        let mean = self.pixels.iter().map(|moments| moments.mean()).sum::<f64>() / count;
+        if mean <= 0.0 {
+            return Some(if error_sqr > 0.0 { f64::INFINITY } else { 0.0 });
+        }
+        Some(error_sqr.sqrt() / mean)
+    }
+}
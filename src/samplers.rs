@@ -0,0 +1,298 @@
+use std::sync::OnceLock;
+
+
+/// Where the random numbers of camera samples come from. `MathUtil::rand` draws one
+/// dimension per call from the sampler of the sample being traced on its thread.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Samplers {
+    Independent(SmpIndependent),
+    Stratified(SmpStratified),
+    Halton(SmpHalton),
+    Sobol(SmpSobol),
+    BlueNoise(SmpBlueNoise)
+}
+
+
+pub struct SamplerFactory;
+#[allow(unused)]
+impl SamplerFactory {
+    pub fn make_independent() -> Samplers {
+        Samplers::Independent(SmpIndependent)
+    }
+    pub fn make_stratified() -> Samplers {
+        Samplers::Stratified(SmpStratified)
+    }
+    pub fn make_halton() -> Samplers {
+        Samplers::Halton(SmpHalton)
+    }
+    pub fn make_sobol() -> Samplers {
+        Samplers::Sobol(SmpSobol)
+    }
+    pub fn make_blue_noise() -> Samplers {
+        Samplers::BlueNoise(SmpBlueNoise)
+    }
+    /// Sampler picked by name on the command line.
+    pub fn from_name(name: &str) -> Option<Samplers> {
+        match name {
+            "independent" => Some(Self::make_independent()),
+            "stratified" => Some(Self::make_stratified()),
+            "halton" => Some(Self::make_halton()),
+            "sobol" => Some(Self::make_sobol()),
+            "blue-noise" => Some(Self::make_blue_noise()),
+            _ => None,
+        }
+    }
+}
+
+
+/// One dimension of one camera sample.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SamplePoint {
+    pub seed: u32,              // Render seed, picks one of many equally good sequences
+    pub pixel: (u32, u32),
+    pub index: u32,             // Samples the pixel took before this one
+    pub batch: (u32, u32),      // Position in, and size of, the samples the pixel takes together
+    pub dimension: u32
+}
+
+
+pub trait Sampler {
+    /// Value in [0, 1) for `point`, the same every time it's asked for.
+    fn sample(&self, point: &SamplePoint) -> f64;
+}
+
+
+/// Uncorrelated values, as `fastrand` gives.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SmpIndependent;
+impl Sampler for SmpIndependent {
+    fn sample(&self, _point: &SamplePoint) -> f64 {
+        fastrand::f64()
+    }
+}
+
+
+/// Jittered strata, one per sample of a batch. Every dimension shuffles its strata
+/// on its own (Kensler 2013), so pairs of dimensions form a Latin hypercube.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SmpStratified;
+impl Sampler for SmpStratified {
+    fn sample(&self, point: &SamplePoint) -> f64 {
+        let (position, size) = point.batch;
+        let seed = hash(&[point.seed, point.pixel.0, point.pixel.1, point.index - position, point.dimension]);
+        let stratum = permute(position, size, seed);
+        let jitter = to_unit(hash(&[point.seed, point.pixel.0, point.pixel.1, point.index, point.dimension, 1]));
+        (stratum as f64 + jitter) / size as f64
+    }
+}
+
+
+/// Radical inverses in the first `PRIMES` bases, randomly rotated per pixel
+/// (Cranley-Patterson). Further dimensions are independent.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SmpHalton;
+impl SmpHalton {
+    const PRIMES: [u32; 32] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+                               59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131];
+
+    fn radical_inverse(base: u32, mut index: u32) -> f64 {
+        let inverse_base = 1.0 / base as f64;
+        let (mut digits, mut scale) = (0.0, inverse_base);
+        while index > 0 {
+            digits += (index % base) as f64 * scale;
+            index /= base;
+            scale *= inverse_base;
+        }
+        digits
+    }
+}
+impl Sampler for SmpHalton {
+    fn sample(&self, point: &SamplePoint) -> f64 {
+        let rotation = to_unit(hash(&[point.seed, point.pixel.0, point.pixel.1, point.dimension]));
+        match Self::PRIMES.get(point.dimension as usize) {
+            Some(base) => (Self::radical_inverse(*base, point.index) + rotation).fract(),
+            None => to_unit(hash(&[point.seed, point.pixel.0, point.pixel.1, point.index, point.dimension])),
+        }
+    }
+}
+
+
+/// 4D Sobol points with hash-based Owen scrambling and shuffling (Burley 2020).
+/// Further dimensions are padded with independently scrambled copies.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SmpSobol;
+impl SmpSobol {
+        // Joe-Kuo polynomials of the first dimensions, as (degree, coefficients, initial numbers).
+    const POLYNOMIALS: [(usize, u32, [u32; 3]); 3] = [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+    const DIRECTIONS: [[u32; 32]; 4] = Self::directions();
+
+    const fn directions() -> [[u32; 32]; 4] {
+        let mut directions = [[0; 32]; 4];
+        let mut bit = 0;
+        while bit < 32 {
+            directions[0][bit] = 1 << (31 - bit);
+            bit += 1;
+        }
+        let mut dimension = 1;
+        while dimension < 4 {
+            let (degree, coefficients, initial) = Self::POLYNOMIALS[dimension - 1];
+            let mut m = [0u32; 32];
+            let mut k = 0;
+            while k < 32 {
+                if k < degree {
+                    m[k] = initial[k];
+                }
+                else {
+                    let mut value = m[k - degree] ^ (m[k - degree] << degree);
+                    let mut j = 1;
+                    while j < degree {
+                        if (coefficients >> (degree - 1 - j)) & 1 == 1 {
+                            value ^= m[k - j] << j;
+                        }
+                        j += 1;
+                    }
+                    m[k] = value;
+                }
+                directions[dimension][k] = m[k] << (31 - k);
+                k += 1;
+            }
+            dimension += 1;
+        }
+        directions
+    }
+    fn sobol(index: u32, dimension: usize) -> u32 {
+        let (mut value, mut index, mut bit) = (0, index, 0);
+        while index != 0 {
+            if index & 1 == 1 {
+                value ^= Self::DIRECTIONS[dimension][bit];
+            }
+            index >>= 1;
+            bit += 1;
+        }
+        value
+    }
+    /// Owen-scrambled point `index` in `dimension` of the sequence picked by `seed`.
+    fn scrambled(index: u32, dimension: u32, seed: u32) -> f64 {
+        let seed = hash(&[seed, dimension / 4]);
+        let shuffled = nested_uniform_scramble(index, seed);
+        let value = Self::sobol(shuffled, (dimension % 4) as usize);
+        nested_uniform_scramble(value, hash(&[seed, dimension % 4])) as f64 / 4294967296.0
+    }
+}
+impl Sampler for SmpSobol {
+    fn sample(&self, point: &SamplePoint) -> f64 {
+        Self::scrambled(point.index, point.dimension, hash(&[point.seed, point.pixel.0, point.pixel.1]))
+    }
+}
+
+
+/// One scrambled Sobol sequence shared by all pixels, rotated per pixel by a blue
+/// noise mask (Georgiev and Fajardo 2016). Neighbouring pixels then err in
+/// different directions, which reads as fine grain rather than blotches.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SmpBlueNoise;
+impl SmpBlueNoise {
+    const SIZE: usize = 32;
+
+        // Ranks from void-and-cluster (Ulichney 1993): every next rank goes to the cell
+        // with the least Gaussian energy from the cells ranked before it.
+    fn mask() -> &'static [f64] {
+        static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+        MASK.get_or_init(|| {
+            let (size, count) = (Self::SIZE, Self::SIZE * Self::SIZE);
+            let wrap = |d: usize| usize::min(d, size - d) as f64;
+            let kernel: Vec<f64> = (0..count).map(|d| {
+                let (dx, dy) = (wrap(d % size), wrap(d / size));
+                f64::exp(-(dx * dx + dy * dy) / (2.0 * 1.5 * 1.5))
+            }).collect();
+
+                // A little noise breaks the ties between empty cells.
+            let mut energy: Vec<f64> = (0..count).map(|cell| 1e-9 * to_unit(hash(&[cell as u32]))).collect();
+            let mut mask = vec![-1.0; count];
+            for rank in 0..count {
+                let cell = (0..count).filter(|cell| mask[*cell] < 0.0)
+                    .min_by(|a, b| energy[*a].total_cmp(&energy[*b])).unwrap();
+                mask[cell] = rank as f64 / count as f64;
+                let (x, y) = (cell % size, cell / size);
+                for (other, e) in energy.iter_mut().enumerate() {
+                    let (dx, dy) = ((other % size + size - x) % size, (other / size + size - y) % size);
+                    *e += kernel[dy * size + dx];
+                }
+            }
+            mask
+        })
+    }
+}
+impl Sampler for SmpBlueNoise {
+    fn sample(&self, point: &SamplePoint) -> f64 {
+            // Each dimension reads the mask at its own offset, so dimensions stay uncorrelated.
+        let offset = hash(&[point.seed, point.dimension]) as usize;
+        let x = (point.pixel.0 as usize + offset) % Self::SIZE;
+        let y = (point.pixel.1 as usize + (offset >> 16)) % Self::SIZE;
+        (SmpSobol::scrambled(point.index, point.dimension, point.seed) + Self::mask()[y * Self::SIZE + x]).fract()
+    }
+}
+
+
+    // Integer hash of `values` (lowbias32 by Chris Wellons, chained).
+fn hash(values: &[u32]) -> u32 {
+    let mut h = 0x9e3779b9u32;
+    for value in values {
+        h ^= value.wrapping_add(0x7f4a7c15).wrapping_add(h << 6).wrapping_add(h >> 2);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846ca68b);
+        h ^= h >> 16;
+    }
+    h
+}
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / 4294967296.0
+}
+
+    // Position of `index` in a random permutation of 0..size picked by `seed` (Kensler 2013).
+fn permute(index: u32, size: u32, seed: u32) -> u32 {
+    let mut w = size.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = index;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < size {
+            return (i + seed) % size;
+        }
+    }
+}
+
+    // Owen scrambling of all 32 bits at once (Burley 2020, with Laine and Karras' hash).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x.reverse_bits()
+}
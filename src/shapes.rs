@@ -1,17 +1,37 @@
 
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
 use crate::datatypes::Ray;
+use crate::datatypes::Aabb;
+use crate::datatypes::Onb;
+use crate::datatypes::Vec3;
 use crate::datatypes::Point3;
 use crate::datatypes::Interval;
 use crate::datatypes::Hittable;
 use crate::datatypes::HitRecord;
+use crate::datatypes::Sampleable;
+use crate::datatypes::SurfaceSample;
+use crate::lights::Lights;
+use crate::light_tree::LightTree;
+use crate::lights::LightArea;
 use crate::materials::Materials;
+use crate::sky::Skies;
+use crate::sky::SkyFactory;
+use crate::sky::SkyPreetham;
+use crate::textures::AlphaMask;
+use crate::textures::NormalMaps;
 use crate::utils::HitUtil;
+use crate::utils::MatUtil;
+use crate::utils::MathUtil;
 
 #[allow(unused)]
 #[derive(Clone)]
 pub enum Hittables {
     Sphere(Sphere),
-    HittableList(HittableList)
+    Quad(Quad),
+    Triangle(Triangle),
+    HittableList(Box<HittableList>)     // Boxed, lists carry their lights, light tree and sky
 }
 
 
@@ -21,8 +41,14 @@ impl ShapeFactory {
     pub fn make_sphere(radius: f64, x: f64, y: f64, z: f64, material: Materials) -> Hittables {
         Hittables::Sphere(Sphere::new(radius, x, y, z, material))
     }
+    pub fn make_quad(corner: Point3, u: Vec3, v: Vec3, material: Materials) -> Hittables {
+        Hittables::Quad(Quad::new(corner, u, v, material))
+    }
+    pub fn make_triangle(a: Point3, b: Point3, c: Point3, material: Materials) -> Hittables {
+        Hittables::Triangle(Triangle::new(a, b, c, material))
+    }
     pub fn make_hittable_list() -> Hittables {
-        Hittables::HittableList(HittableList::new())
+        Hittables::HittableList(Box::new(HittableList::new()))
     }
 }
 
@@ -31,16 +57,29 @@ impl ShapeFactory {
 pub struct Sphere {
     pub radius: f64,
     pub center: Point3,
-    pub material: Materials
+    pub material: Materials,
+    pub normal_map: Option<NormalMaps>,
+    pub alpha_mask: Option<AlphaMask>
 }
+#[allow(unused)]
 impl Sphere {
     pub fn new(radius: f64, x: f64, y: f64, z: f64, material: Materials) -> Sphere {
         Sphere {
             radius,
             center: Point3::new(x, y, z),
-            material
+            material,
+            normal_map: None,
+            alpha_mask: None
         }
     }
+    pub fn with_normal_map(mut self, normal_map: NormalMaps) -> Self {
+        self.normal_map = Some(normal_map);
+        self
+    }
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
 }
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_i: &Interval) -> Option<HitRecord> {
@@ -56,51 +95,379 @@ impl Hittable for Sphere {
 
         let sqrtd = f64::sqrt(discriminant);
 
-        let mut root = (h - sqrtd) / a;
-        if !t_i.contains(root) {
-            root = (h + sqrtd) / a;
-            if !t_i.surrounds(root) {
-                return None;
+        let near = (h - sqrtd) / a;
+        let far = (h + sqrtd) / a;
+        if t_i.contains(near) {
+            if let Some(hit_rec) = self.hit_at(ray, near) {
+                return Some(hit_rec);
             }
         }
-
+        if t_i.surrounds(far) {
+            return self.hit_at(ray, far);
+        }
+        None
+    }
+}
+impl Sphere {
+    fn hit_at(&self, ray: &Ray, root: f64) -> Option<HitRecord> {
         let p = ray.at(root);
-        let mut hit_rec = HitRecord::new(p.clone(), (&p - &self.center) / self.radius, root, self.material.clone());
         let outward_normal = (&p - &self.center) / self.radius;
-            // TODO: consider doing on init.
-        hit_rec.set_face_normal(ray, &outward_normal);
+        let (u, v) = Self::get_uv(&outward_normal);
+        let tangent = Self::get_tangent(&outward_normal);
+        SurfaceHit { p, outward_normal, tangent, u, v }
+            .record(ray, root, &self.material, &self.normal_map, &self.alpha_mask)
+    }
+    fn get_uv(outward_normal: &Vec3) -> (f64, f64) {
+            // u: angle around the Y axis from X=-1, v: angle from Y=-1 to Y=+1.
+        let theta = f64::acos(-outward_normal.y);
+        let phi = f64::atan2(-outward_normal.z, outward_normal.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+    fn get_tangent(outward_normal: &Vec3) -> Vec3 {
+            // Direction of increasing u, i.e. dp/du of the parametrization above.
+        Vec3::new(outward_normal.z, 0.0, -outward_normal.x)
+    }
+    fn sampled_record(&self, origin: &Point3, p: Point3) -> HitRecord {
+        let outward_normal = (&p - &self.center) / self.radius;
+        let (u, v) = Self::get_uv(&outward_normal);
+        let tangent = Self::get_tangent(&outward_normal);
+        SurfaceHit { p, outward_normal, tangent, u, v }
+            .sampled_record(origin, &self.material)
+    }
+}
+impl Sampleable for Sphere {
+    fn sample(&self, origin: &Point3) -> Option<SurfaceSample> {
+        let to_center = &self.center - origin;
+        let dist_center = to_center.len();
+        if dist_center <= self.radius {
+                // Inside the sphere every point is visible, so sample by area.
+            let p = &self.center + &(self.radius * Vec3::random_unit());
+            let hit_rec = self.sampled_record(origin, p);
+            let pdf = self.pdf(origin, &hit_rec);
+            return Some(SurfaceSample { hit_rec, pdf });
+        }
+
+            // Uniform direction in the cone subtended by the sphere (pbrt-v3).
+        let sin_max = self.radius / dist_center;
+        let cos_max = f64::sqrt(f64::max(0.0, 1.0 - sin_max * sin_max));
+        let cos_theta = 1.0 - MathUtil::rand() * (1.0 - cos_max);
+        let sin_theta_sqr = f64::max(0.0, 1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * MathUtil::rand();
+
+            // Find the sphere point along the sampled direction.
+        let ds = dist_center * cos_theta
+            - f64::sqrt(f64::max(0.0, self.radius * self.radius - dist_center * dist_center * sin_theta_sqr));
+        let cos_alpha = (dist_center * dist_center + self.radius * self.radius - ds * ds) / (2.0 * dist_center * self.radius);
+        let sin_alpha = f64::sqrt(f64::max(0.0, 1.0 - cos_alpha * cos_alpha));
+        let frame = Onb::from_normal(&(to_center / dist_center));
+        let normal = -&frame.local(sin_alpha * f64::cos(phi), sin_alpha * f64::sin(phi), cos_alpha);
+
+        let hit_rec = self.sampled_record(origin, &self.center + &(self.radius * &normal));
+        Some(SurfaceSample { hit_rec, pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)) })
+    }
+    fn pdf(&self, origin: &Point3, hit_rec: &HitRecord) -> f64 {
+        let dist_center = (&self.center - origin).len();
+        if dist_center <= self.radius {
+            return SurfaceSample::area_to_solid_angle(1.0 / self.area(), origin, &hit_rec.p, &hit_rec.normal);
+        }
+        let sin_max = self.radius / dist_center;
+        let cos_max = f64::sqrt(f64::max(0.0, 1.0 - sin_max * sin_max));
+        1.0 / (2.0 * PI * (1.0 - cos_max))
+    }
+    fn sample_area(&self) -> HitRecord {
+        let normal = Vec3::random_unit();
+        let p = &self.center + &(self.radius * &normal);
+        self.sampled_record(&(&p + &normal), p)
+    }
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+    fn bounds(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(&(&self.center - &r), &(&self.center + &r))
+    }
+}
+
 
+/// Parallelogram spanned by `u` and `v` from `corner`. UVs run along the edges.
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Materials,
+    pub normal_map: Option<NormalMaps>,
+    pub alpha_mask: Option<AlphaMask>,
+    normal: Vec3,
+    w: Vec3,
+    area: f64
+}
+#[allow(unused)]
+impl Quad {
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, material: Materials) -> Quad {
+        let n = u.cross(&v);
+        let w = &n / n.len_sqr();
+        Quad { corner, normal: n.unit(), w, area: n.len(), u, v, material, normal_map: None, alpha_mask: None }
+    }
+    pub fn with_normal_map(mut self, normal_map: NormalMaps) -> Self {
+        self.normal_map = Some(normal_map);
+        self
+    }
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
+}
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_i: &Interval) -> Option<HitRecord> {
+        let denom = self.normal.dot(ray.direction());
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = self.normal.dot(&(&self.corner - ray.origin())) / denom;
+        if !t_i.contains(t) {
+            return None;
+        }
+        let p = ray.at(t);
+        let planar = &p - &self.corner;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        SurfaceHit { p, outward_normal: self.normal.clone(), tangent: self.u.clone(), u: alpha, v: beta }
+            .record(ray, t, &self.material, &self.normal_map, &self.alpha_mask)
+    }
+}
+impl Sampleable for Quad {
+    fn sample(&self, origin: &Point3) -> Option<SurfaceSample> {
+        let (a, b) = (MathUtil::rand(), MathUtil::rand());
+        let p = &self.corner + &(a * &self.u) + b * &self.v;
+        let pdf = SurfaceSample::area_to_solid_angle(1.0 / self.area, origin, &p, &self.normal);
+        let hit_rec = SurfaceHit { p, outward_normal: self.normal.clone(), tangent: self.u.clone(), u: a, v: b }
+            .sampled_record(origin, &self.material);
+        Some(SurfaceSample { hit_rec, pdf })
+    }
+    fn pdf(&self, origin: &Point3, hit_rec: &HitRecord) -> f64 {
+        SurfaceSample::area_to_solid_angle(1.0 / self.area, origin, &hit_rec.p, &self.normal)
+    }
+    fn sample_area(&self) -> HitRecord {
+        let (a, b) = (MathUtil::rand(), MathUtil::rand());
+        let p = &self.corner + &(a * &self.u) + b * &self.v;
+        let outside = &p + &self.normal;
+        SurfaceHit { p, outward_normal: self.normal.clone(), tangent: self.u.clone(), u: a, v: b }
+            .sampled_record(&outside, &self.material)
+    }
+    fn area(&self) -> f64 {
+        self.area
+    }
+    fn bounds(&self) -> Aabb {
+        let far = &self.corner + &(&self.u + &self.v);
+        Aabb::new(&self.corner, &far).union(&Aabb::new(&(&self.corner + &self.u), &(&self.corner + &self.v)))
+    }
+}
+
+
+/// Triangle with vertices `a`, `b`, `c`. UVs are the barycentric weights of `b` and `c`.
+#[derive(Clone)]
+pub struct Triangle {
+    pub a: Point3,
+    pub b: Point3,
+    pub c: Point3,
+    pub material: Materials,
+    pub normal_map: Option<NormalMaps>,
+    pub alpha_mask: Option<AlphaMask>,
+    normal: Vec3,
+    area: f64
+}
+#[allow(unused)]
+impl Triangle {
+    pub fn new(a: Point3, b: Point3, c: Point3, material: Materials) -> Triangle {
+        let n = (&b - &a).cross(&(&c - &a));
+        Triangle { normal: n.unit(), area: 0.5 * n.len(), a, b, c, material, normal_map: None, alpha_mask: None }
+    }
+    pub fn with_normal_map(mut self, normal_map: NormalMaps) -> Self {
+        self.normal_map = Some(normal_map);
+        self
+    }
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
+    fn random_point(&self) -> SurfaceHit {
+            // Uniform barycentrics via the square root warp.
+        let su = f64::sqrt(MathUtil::rand());
+        let b2 = su * MathUtil::rand();
+        let b1 = su - b2;
+        let p = (1.0 - b1 - b2) * &self.a + b1 * &self.b + b2 * &self.c;
+        SurfaceHit { p, outward_normal: self.normal.clone(), tangent: &self.b - &self.a, u: b1, v: b2 }
+    }
+}
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_i: &Interval) -> Option<HitRecord> {
+            // Moller-Trumbore.
+        let e1 = &self.b - &self.a;
+        let e2 = &self.c - &self.a;
+        let pvec = ray.direction().cross(&e2);
+        let det = e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = ray.origin() - &self.a;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&e1);
+        let b2 = ray.direction().dot(&qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qvec) * inv_det;
+        if !t_i.contains(t) {
+            return None;
+        }
+        SurfaceHit { p: ray.at(t), outward_normal: self.normal.clone(), tangent: e1, u: b1, v: b2 }
+            .record(ray, t, &self.material, &self.normal_map, &self.alpha_mask)
+    }
+}
+impl Sampleable for Triangle {
+    fn sample(&self, origin: &Point3) -> Option<SurfaceSample> {
+        let hit_rec = self.random_point().sampled_record(origin, &self.material);
+        let pdf = SurfaceSample::area_to_solid_angle(1.0 / self.area, origin, &hit_rec.p, &self.normal);
+        Some(SurfaceSample { hit_rec, pdf })
+    }
+    fn pdf(&self, origin: &Point3, hit_rec: &HitRecord) -> f64 {
+        SurfaceSample::area_to_solid_angle(1.0 / self.area, origin, &hit_rec.p, &self.normal)
+    }
+    fn sample_area(&self) -> HitRecord {
+        let point = self.random_point();
+        let outside = &point.p + &self.normal;
+        point.sampled_record(&outside, &self.material)
+    }
+    fn area(&self) -> f64 {
+        self.area
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(&self.a, &self.b).union(&Aabb::new(&self.c, &self.c))
+    }
+}
+
+
+/// Local surface data shared by all shapes when building a hit record.
+struct SurfaceHit {
+    p: Point3,
+    outward_normal: Vec3,
+    tangent: Vec3,
+    u: f64,
+    v: f64
+}
+impl SurfaceHit {
+    fn record(self, ray: &Ray, t: f64, material: &Materials, normal_map: &Option<NormalMaps>,
+              alpha_mask: &Option<AlphaMask>) -> Option<HitRecord> {
+        if let Some(alpha_mask) = alpha_mask {
+            if alpha_mask.is_cut_out(self.u, self.v, &self.p) {
+                return None;
+            }
+        }
+        let mut hit_rec = HitRecord::new(self.p, self.outward_normal.clone(), t, material.clone());
+        hit_rec.set_uv(self.u, self.v);
+            // TODO: consider doing on init.
+        hit_rec.set_face_normal(ray, &self.outward_normal);
+        hit_rec.set_tangent(&self.tangent);
+        if let Some(normal_map) = normal_map {
+            normal_map.apply(ray, &mut hit_rec);
+        }
         Some(hit_rec)
     }
+    fn sampled_record(self, origin: &Point3, material: &Materials) -> HitRecord {
+        let ray = Ray::new(origin.clone(), &self.p - origin);
+        let mut hit_rec = HitRecord::new(self.p, self.outward_normal.clone(), 1.0, material.clone());
+        hit_rec.set_uv(self.u, self.v);
+        hit_rec.set_face_normal(&ray, &self.outward_normal);
+        hit_rec.set_tangent(&self.tangent);
+        hit_rec
+    }
 }
 
 
+/// Objects of the scene plus what lights them. Shapes with an emissive material
+/// are also registered as area lights when added. The light tree is built on
+/// first use and dropped whenever objects or lights are added.
 #[derive(Clone)]
 pub struct HittableList {
-    pub objects: Vec<Hittables>
+    objects: Vec<Hittables>,
+    lights: Vec<Lights>,
+    pub sky: Skies,
+    light_ids: Vec<Option<usize>>,    // Area light index of each object
+    light_tree: OnceLock<LightTree>
 }
+#[allow(unused)]
 impl HittableList {
     pub fn new() -> Self {
-        Self { objects: Vec::new() }
+        Self { objects: Vec::new(), lights: Vec::new(), sky: SkyFactory::make_gradient(), light_ids: Vec::new(),
+               light_tree: OnceLock::new() }
+    }
+    pub fn set_sky(&mut self, sky: Skies) {
+        self.sky = sky;
+    }
+    /// Daylight `sky` with its sun as a light of `sun_irradiance`, so the two match.
+    pub fn set_daylight(&mut self, sky: SkyPreetham, sun_irradiance: f64) {
+        self.add_light(sky.sun(sun_irradiance));
+        self.sky = Skies::Preetham(sky);
     }
     pub fn add(&mut self, object: Hittables) {
+        let is_emissive = HitUtil::material(&object).is_some_and(MatUtil::is_emissive);
+        if is_emissive {
+            self.light_ids.push(Some(self.lights.len()));
+            self.add_light(Lights::Area(LightArea::new(object.clone())));
+        }
+        else {
+            self.light_ids.push(None);
+        }
         self.objects.push(object);
     }
+    pub fn add_light(&mut self, light: Lights) {
+        self.lights.push(light);
+        self.light_tree = OnceLock::new();
+    }
+    /// Objects, indexed by `HitRecord::object_id`. Only `add` changes them, so the area
+    /// lights made from them stay in step.
+    pub fn objects(&self) -> &[Hittables] {
+        &self.objects
+    }
+    /// Lights, indexed as by the light tree. Only `add` and `add_light` change them,
+    /// so the tree is rebuilt after.
+    pub fn lights(&self) -> &[Lights] {
+        &self.lights
+    }
+    /// Index in `lights` of the area light made from the object in `hit_rec`, if it is one.
+    pub fn light_index(&self, hit_rec: &HitRecord) -> Option<usize> {
+        self.light_ids.get(hit_rec.object_id).copied().flatten()
+    }
+    pub fn light_tree(&self) -> &LightTree {
+        self.light_tree.get_or_init(|| LightTree::new(&self.lights))
+    }
 }
 impl Hittable for HittableList {
     fn hit(&self, ray: &crate::datatypes::Ray, t_i: &Interval) -> Option<HitRecord> {
         let mut closest_so_far = t_i.max;
         let mut rec: Option<HitRecord> = None;
+        let mut tests = 0;
 
-        for object in &self.objects {
-            if let Some(hr) = HitUtil::hit(object, ray, t_i) {
+        for (id, object) in self.objects.iter().enumerate() {
+            tests += !matches!(object, Hittables::HittableList(_)) as u64;
+            if let Some(mut hr) = HitUtil::hit(object, ray, t_i) {
                 if hr.t < closest_so_far {
                     closest_so_far = hr.t;
+                    hr.object_id = id;
                     rec = Some(hr);
                 }
             }
         }
 
+        HitUtil::count_intersection_tests(tests);
         rec
     }
 }
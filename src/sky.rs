@@ -0,0 +1,160 @@
+use std::f64::consts::PI;
+
+use crate::datatypes::Vec3;
+use crate::datatypes::Color3;
+use crate::lights::Lights;
+use crate::lights::LightDirectional;
+use crate::utils::MathUtil;
+
+
+/// Background radiance for rays that leave the scene.
+#[derive(Clone)]
+pub enum Skies {
+    Gradient(SkyGradient),
+    Preetham(SkyPreetham)
+}
+
+
+pub struct SkyFactory;
+#[allow(unused)]
+impl SkyFactory {
+    pub fn make_gradient() -> Skies {
+        Skies::Gradient(SkyGradient::new(Color3::one(), Color3::new(0.5, 0.7, 1.0)))
+    }
+    pub fn make_preetham(sun_elevation: f64, sun_azimuth: f64, turbidity: f64) -> Skies {
+        Skies::Preetham(SkyPreetham::new(sun_elevation, sun_azimuth, turbidity))
+    }
+}
+
+
+pub trait Sky {
+    fn radiance(&self, direction: &Vec3) -> Color3;
+}
+
+
+/// Linear blend from `horizon` to `zenith` along the Y axis.
+#[derive(Clone)]
+pub struct SkyGradient {
+    pub horizon: Color3,
+    pub zenith: Color3
+}
+impl SkyGradient {
+    pub fn new(horizon: Color3, zenith: Color3) -> Self {
+        SkyGradient { horizon, zenith }
+    }
+}
+impl Sky for SkyGradient {
+    fn radiance(&self, direction: &Vec3) -> Color3 {
+        let unit_dir = direction.unit();
+        let a = 0.5 * (unit_dir.y + 1.0);
+        (1.0 - a) * &self.horizon + (a * &self.zenith)
+    }
+}
+
+
+/// Analytic daylight sky (Preetham, Shirley and Smits 1999). The sun sits at
+/// `sun_elevation` degrees above the horizon and `sun_azimuth` degrees from -Z
+/// towards +X; `turbidity` goes from 2 (very clear) to about 10 (hazy). The sun
+/// itself is not part of the sky radiance, add `sun()` to the scene lights.
+#[derive(Clone)]
+pub struct SkyPreetham {
+    pub sun_direction: Vec3,
+    pub turbidity: f64,
+    pub intensity: f64,     // Scale from kcd/m^2 to scene radiance
+    theta_sun: f64,
+    zenith: [f64; 3],       // Zenith Y, x, y
+    perez: [[f64; 5]; 3]    // Perez coefficients for Y, x, y
+}
+#[allow(unused)]
+impl SkyPreetham {
+    const SUN_ANGULAR_RADIUS: f64 = 0.265;
+
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64) -> Self {
+        let elevation = MathUtil::degrees_to_radians(sun_elevation);
+        let azimuth = MathUtil::degrees_to_radians(sun_azimuth);
+        let sun_direction = Vec3::new(
+            f64::cos(elevation) * f64::sin(azimuth),
+            f64::sin(elevation),
+            -f64::cos(elevation) * f64::cos(azimuth),
+        );
+        let theta_sun = f64::acos(sun_direction.y.clamp(-1.0, 1.0)).min(PI / 2.0);
+        let t = turbidity;
+        let (th, th2, th3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_lum = f64::max(0.0, (4.0453 * t - 4.9710) * f64::tan(chi) - 0.2155 * t + 2.4192);
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        SkyPreetham {
+            sun_direction,
+            turbidity,
+            intensity: 0.05,
+            theta_sun,
+            zenith: [zenith_lum, zenith_x, zenith_y],
+            perez,
+        }
+    }
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+    /// Solar disk matching this sky, as a soft directional light. The color is the
+    /// extraterrestrial sun attenuated by Rayleigh and aerosol scattering along the
+    /// air mass for the current elevation; `irradiance` scales it to scene units.
+    pub fn sun(&self, irradiance: f64) -> Lights {
+        let zenith_deg = self.theta_sun.to_degrees();
+        let air_mass = 1.0 / (f64::cos(self.theta_sun) + 0.15 * f64::powf(93.885 - zenith_deg, -1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda_um: f64| {
+            let rayleigh = f64::exp(-0.008735 * f64::powf(lambda_um, -4.08) * air_mass);
+            let aerosol = f64::exp(-beta * f64::powf(lambda_um, -1.3) * air_mass);
+            rayleigh * aerosol
+        };
+        let color = Color3::new(transmittance(0.65), transmittance(0.55), transmittance(0.45));
+        Lights::Directional(LightDirectional::new(-&self.sun_direction, color, irradiance)
+            .with_angular_radius(Self::SUN_ANGULAR_RADIUS))
+    }
+    fn perez(coeffs: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = *coeffs;
+        (1.0 + a * f64::exp(b / cos_theta)) * (1.0 + c * f64::exp(d * gamma) + e * f64::cos(gamma) * f64::cos(gamma))
+    }
+}
+impl Sky for SkyPreetham {
+    fn radiance(&self, direction: &Vec3) -> Color3 {
+            // Below the horizon the sky is clamped to its horizon value.
+        let mut dir = direction.unit();
+        dir.y = f64::max(dir.y, 0.01);
+        let dir = dir.unit();
+        let gamma = f64::acos(dir.dot(&self.sun_direction).clamp(-1.0, 1.0));
+
+        let mut values = [0.0; 3];
+        for (k, value) in values.iter_mut().enumerate() {
+            let num = Self::perez(&self.perez[k], dir.y, gamma);
+            let den = Self::perez(&self.perez[k], 1.0, self.theta_sun);
+            *value = self.zenith[k] * num / den;
+        }
+        let [lum, x, y] = values;
+        if y <= 0.0 {
+            return Color3::zero();
+        }
+
+            // xyY -> XYZ -> linear sRGB.
+        let cap_x = x / y * lum;
+        let cap_z = (1.0 - x - y) / y * lum;
+        let r = 3.2406 * cap_x - 1.5372 * lum - 0.4986 * cap_z;
+        let g = -0.9689 * cap_x + 1.8758 * lum + 0.0415 * cap_z;
+        let b = 0.0557 * cap_x - 0.2040 * lum + 1.0570 * cap_z;
+        Color3::new(f64::max(r, 0.0), f64::max(g, 0.0), f64::max(b, 0.0)) * self.intensity
+    }
+}
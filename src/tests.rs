@@ -124,9 +124,9 @@ mod color_util_tests {
     fn test_get_pixel() {
         let color = Color3::new(0.5, 0.7, 0.9);
         let pixel = ColorUtil::get_pixel(&color);
-        assert!((pixel[0] as f64 - 181.0).abs() < 2.0);
-        assert!((pixel[1] as f64 - 214.0).abs() < 2.0);
-        assert!((pixel[2] as f64 - 242.0).abs() < 2.0);
+        assert!((pixel[0] as f64 - 188.0).abs() < 2.0);
+        assert!((pixel[1] as f64 - 218.0).abs() < 2.0);
+        assert!((pixel[2] as f64 - 244.0).abs() < 2.0);
     }
 }
 
@@ -207,11 +207,14 @@ mod camera_tests {
     use crate::datatypes::{Color3, Point3, Ray, Vec3};
     use crate::materials::{MatLambertian, Materials};
     use crate::shapes::{HittableList, Hittables, Sphere};
+    use crate::sky::SkyFactory;
+    use crate::utils::MathUtil;
 
     #[test]
     fn test_camera_ray_color_empty_world() {
         let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
-        let world = HittableList::new();
+        let mut world = HittableList::new();
+        world.set_sky(SkyFactory::make_gradient());
         let color = Camera::ray_color(&ray, 10, &world);
         assert_eq!(color, Color3::new(0.75, 0.85, 1.0)); // Background color
     }
@@ -220,10 +223,1325 @@ mod camera_tests {
     fn test_camera_ray_color_with_sphere() {
         let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
         let mut world = HittableList::new();
+        world.set_sky(SkyFactory::make_gradient());
         world.add(Hittables::Sphere(Sphere::new(0.5, 0.0, 0.0, -1.0, Materials::DifuseLamb(MatLambertian::GRAY))));
-        let color = Camera::ray_color(&ray, 10, &world);
-        assert!((color.x - 0.42).abs() < 0.02);
-        assert!((color.y - 0.45).abs() < 0.02);
-        assert!((color.z - 0.5).abs() < 0.02);
+            // Mean of many seeded samples, a single one is anywhere between sky and black.
+        MathUtil::seed(1);
+        let samples = 20000;
+        let color = (0..samples).fold(Color3::zero(), |sum, _| sum + Camera::ray_color(&ray, 10, &world)) / samples as f64;
+            // Bounces off the convex sphere only see the sky, which is linear in the
+            // direction's y: white at -y, (0.5, 0.7, 1.0) at +y. Its cosine weighted
+            // mean over the hemisphere around the normal +z is its value at y = 0, the
+            // mean of both ends, times the albedo of 0.5.
+        let horizon = Color3::one();
+        let zenith = Color3::new(0.5, 0.7, 1.0);
+        let expected = 0.5 * &(0.5 * &(horizon + zenith));
+        assert!((&color - &expected).len() < 0.01);
+    }
+}
+#[cfg(test)]
+mod thin_film_tests {
+    use crate::datatypes::Color3;
+    use crate::materials::ThinFilm;
+
+    #[test]
+    fn test_zero_thickness_matches_fresnel() {
+        let film = ThinFilm::new(0.0, 1.33);
+        let refl = film.reflectance(1.0, 1.0, 1.5);
+        let r0 = ((1.0 - 1.5) / (1.0 + 1.5_f64)).powi(2);
+        assert!((refl.x - r0).abs() < 1e-9);
+        assert!((refl.y - r0).abs() < 1e-9);
+        assert!((refl.z - r0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reflectance_depends_on_wavelength() {
+        let film = ThinFilm::new(300.0, 1.33);
+        let refl = film.reflectance(1.0, 1.0, 1.0);
+        assert!((refl.x - refl.y).abs() > 0.01 || (refl.y - refl.z).abs() > 0.01);
+        assert!(refl.x >= 0.0 && refl.x <= 1.0);
+    }
+
+    #[test]
+    fn test_conductor_without_film_effect() {
+        let film = ThinFilm::new(0.0, 1.5);
+        let albedo = Color3::new(0.9, 0.5, 0.1);
+        let refl = film.reflectance_conductor(1.0, &albedo);
+        assert!((refl.x - 0.9).abs() < 1e-9);
+        assert!((refl.z - 0.1).abs() < 1e-9);
+    }
+}
+
+#[cfg(test)]
+mod subsurface_tests {
+    use crate::camera::Camera;
+    use crate::datatypes::{Color3, Hittable, Interval, Point3, Ray, Vec3};
+    use crate::materials::{MatSubsurface, Material, MaterialFactory};
+    use crate::shapes::{HittableList, ShapeFactory, Sphere};
+    use crate::sky::{Skies, SkyGradient};
+    use crate::utils::MathUtil;
+
+    fn inside_hit(mfp: f64) -> (Color3, Ray) {
+        let material = MatSubsurface::new(Color3::one(), Color3::new(mfp, mfp, mfp), 1.0);
+        let sphere = Sphere::new(1.0, 0.0, 0.0, 0.0, MaterialFactory::make_subsurface(Color3::one(), Color3::one(), 1.0));
+        let ray = Ray::new(Point3::zero(), Vec3::x_u());
+        let hit_rec = sphere.hit(&ray, &Interval::HIT_EVAL).unwrap();
+        assert!(!hit_rec.is_front_face);
+        let (att, sc_ray) = material.scatter(&ray, &hit_rec).unwrap();
+        (att, sc_ray.unwrap())
+    }
+
+    #[test]
+    fn test_long_mean_free_path_passes_through() {
+        let (att, sc_ray) = inside_hit(1e9);
+        assert!((att.x - 1.0).abs() < 1e-6);
+        assert!((sc_ray.origin().x - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_short_mean_free_path_scatters_inside() {
+        let (att, sc_ray) = inside_hit(1e-3);
+        assert!(sc_ray.origin().len() < 1.0);
+        assert!((att.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_walk_does_not_use_up_bounces() {
+            // White furnace: a lossless, index matched volume under a white sky returns
+            // all light, however many steps its walks take.
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::one(), Color3::one())));
+        world.add(ShapeFactory::make_sphere(1.0, 0.0, 0.0, -3.0, MaterialFactory::make_subsurface(Color3::one(), Color3::one() * 0.2, 1.0)));
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        MathUtil::seed(3);
+        let samples = 2000;
+        let color = (0..samples).fold(Color3::zero(), |sum, _| sum + Camera::ray_color(&ray, 3, &world)) / samples as f64;
+        assert!((color.x - 1.0).abs() < 0.02);
+    }
+}
+
+#[cfg(test)]
+mod tangent_frame_tests {
+    use crate::datatypes::{Hittable, Interval, Point3, Ray, Vec3};
+    use crate::materials::{Material, MaterialFactory, MatMetal, Anisotropy};
+    use crate::shapes::Sphere;
+
+    #[test]
+    fn test_sphere_tangent_frame_is_orthonormal() {
+        let sphere = Sphere::new(1.0, 0.0, 0.0, 0.0, MaterialFactory::make_lambertian(Vec3::one()));
+        let ray = Ray::new(Point3::new(3.0, 0.5, 2.0), Vec3::new(-3.0, -0.5, -2.0));
+        let hit_rec = sphere.hit(&ray, &Interval::HIT_EVAL).unwrap();
+        assert!(hit_rec.tangent.dot(&hit_rec.normal).abs() < 1e-9);
+        assert!(hit_rec.bitangent.dot(&hit_rec.normal).abs() < 1e-9);
+        assert!((hit_rec.tangent.len() - 1.0).abs() < 1e-9);
+        assert!((hit_rec.tangent.cross(&hit_rec.bitangent).dot(&hit_rec.normal) - 1.0).abs() < 1e-9);
+        assert!(hit_rec.u >= 0.0 && hit_rec.u <= 1.0);
+        assert!(hit_rec.v >= 0.0 && hit_rec.v <= 1.0);
+    }
+
+    #[test]
+    fn test_anisotropic_metal_reflects_above_surface() {
+        let sphere = Sphere::new(1.0, 0.0, 0.0, 0.0, MaterialFactory::make_lambertian(Vec3::one()));
+        let material = MatMetal::new(Vec3::one(), 0.0).with_anisotropy(Anisotropy::new(0.05, 0.5, 30.0));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 3.0), Vec3::new(0.1, 0.2, -1.0));
+        let hit_rec = sphere.hit(&ray, &Interval::HIT_EVAL).unwrap();
+        for _ in 0..100 {
+            if let Some((att, Some(sc_ray))) = material.scatter(&ray, &hit_rec) {
+                assert!(sc_ray.direction().dot(&hit_rec.normal) > 0.0);
+                assert!(att.x > 0.0 && att.x <= 1.0 + 1e-9);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod normal_map_tests {
+    use crate::datatypes::{Color3, Hittable, Interval, Point3, Ray, Vec3};
+    use crate::materials::MaterialFactory;
+    use crate::shapes::Sphere;
+    use crate::textures::TextureFactory;
+    use crate::utils::{MathUtil, MatUtil};
+
+    fn hit_mapped(map_color: Color3) -> (Vec3, Vec3) {
+        let normal_map = TextureFactory::make_normal_map(TextureFactory::make_solid(map_color));
+        let sphere = Sphere::new(1.0, 0.0, 0.0, 0.0, MaterialFactory::make_lambertian(Color3::one()))
+            .with_normal_map(normal_map);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit_rec = sphere.hit(&ray, &Interval::HIT_EVAL).unwrap();
+        (hit_rec.normal, hit_rec.shading_normal)
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_normal() {
+        let (normal, shading_normal) = hit_mapped(Color3::new(0.5, 0.5, 1.0));
+        assert!((&normal - &shading_normal).len() < 1e-9);
+    }
+
+    #[test]
+    fn test_tilted_normal_map_only_changes_shading_normal() {
+        let (normal, shading_normal) = hit_mapped(Color3::new(0.9, 0.5, 0.8));
+        assert_eq!(normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(shading_normal.dot(&normal) < 0.99);
+        assert!((shading_normal.len() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_constant_bump_map_keeps_normal() {
+        let bump_map = TextureFactory::make_bump_map(TextureFactory::make_solid(Color3::one()), 1.0);
+        let sphere = Sphere::new(1.0, 0.0, 0.0, 0.0, MaterialFactory::make_lambertian(Color3::one()))
+            .with_normal_map(bump_map);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit_rec = sphere.hit(&ray, &Interval::HIT_EVAL).unwrap();
+        assert!((&hit_rec.normal - &hit_rec.shading_normal).len() < 1e-9);
+    }
+
+    #[test]
+    fn test_tilted_map_on_dielectric_stays_visible() {
+            // Nearly flat in tangent space and pointing inwards, seen head on and at grazing angles.
+        MathUtil::seed(4);
+        let normal_map = TextureFactory::make_normal_map(TextureFactory::make_solid(Color3::new(1.0, 0.5, 0.05)));
+        let sphere = Sphere::new(1.0, 0.0, 0.0, 0.0, MaterialFactory::make_dielectric(1.5)).with_normal_map(normal_map);
+        for height in [0.0, 0.5, 0.9, 0.99] {
+            let ray = Ray::new(Point3::new(0.0, height, 3.0), Vec3::new(0.0, 0.0, -1.0));
+            let hit_rec = sphere.hit(&ray, &Interval::HIT_EVAL).unwrap();
+            let wo = -ray.direction();
+            assert!(hit_rec.shading_normal.dot(&wo) > 0.0, "height {height}");
+            let reflected = (-&wo).reflect(&hit_rec.shading_normal);
+            assert!(reflected.dot(&hit_rec.normal) > 0.0, "height {height}");
+            for _ in 0..100 {
+                let (_, scattered) = MatUtil::scatter(&hit_rec.material, &ray, &hit_rec).unwrap();
+                let direction = scattered.unwrap().direction().clone();
+                assert!(direction.len().is_finite() && direction.len() > 0.5, "height {height}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod alpha_mask_tests {
+    use image::{Rgba, Rgba32FImage};
+
+    use crate::datatypes::{Color3, Hittable, Interval, Point3, Ray, Vec3};
+    use crate::materials::MaterialFactory;
+    use crate::shapes::{HittableList, Hittables, Sphere};
+    use crate::textures::{TexImage, Textures, TextureFactory};
+
+    fn alpha_texture(alpha: f32) -> Textures {
+        Textures::Image(TexImage::new(Rgba32FImage::from_pixel(1, 1, Rgba([1.0, 1.0, 1.0, alpha]))))
+    }
+
+    fn world_with_mask(alpha: f32) -> HittableList {
+        let mut world = HittableList::new();
+        let leaf = Sphere::new(0.5, 0.0, 0.0, -1.0, MaterialFactory::make_lambertian(Color3::one()))
+            .with_alpha_mask(TextureFactory::make_cutout(alpha_texture(alpha), 0.5));
+        world.add(Hittables::Sphere(leaf));
+        world.add(Hittables::Sphere(Sphere::new(0.5, 0.0, 0.0, -5.0, MaterialFactory::make_lambertian(Color3::one()))));
+        world
+    }
+
+    #[test]
+    fn test_opaque_mask_is_hit() {
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let hit_rec = world_with_mask(1.0).hit(&ray, &Interval::HIT_EVAL).unwrap();
+        assert!((hit_rec.t - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cut_out_surface_is_skipped() {
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let hit_rec = world_with_mask(0.2).hit(&ray, &Interval::HIT_EVAL).unwrap();
+        assert!((hit_rec.t - 4.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stochastic_mask_hits_by_alpha() {
+        let sphere = Sphere::new(0.5, 0.0, 0.0, -1.0, MaterialFactory::make_lambertian(Color3::one()))
+            .with_alpha_mask(TextureFactory::make_stochastic_cutout(alpha_texture(0.5)));
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let near_hits = (0..2000).filter(|_| {
+            matches!(sphere.hit(&ray, &Interval::HIT_EVAL), Some(hr) if hr.t < 1.0)
+        }).count();
+        assert!(near_hits > 850 && near_hits < 1150);
+    }
+}
+
+#[cfg(test)]
+mod blend_tests {
+    use crate::datatypes::{Color3, HitRecord, Point3, Ray, Vec3};
+    use crate::materials::{Materials, MatLambertian, MaterialFactory};
+    use crate::utils::MatUtil;
+
+    fn mean_emission(material: &Materials, samples: u32) -> Color3 {
+        let hit_rec = HitRecord::new(Point3::zero(), Vec3::z_u(), 1.0, Materials::DifuseLamb(MatLambertian::GRAY));
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut sum = Color3::zero();
+        for _ in 0..samples {
+            let (att, _) = MatUtil::scatter(material, &ray, &hit_rec).unwrap();
+            sum += att;
+        }
+        sum / samples as f64
+    }
+
+    #[test]
+    fn test_blend_weight() {
+        let red = MaterialFactory::make_emitter(Color3::new(1.0, 0.0, 0.0), 1.0);
+        let blue = MaterialFactory::make_emitter(Color3::new(0.0, 0.0, 1.0), 1.0);
+        let mean = mean_emission(&MaterialFactory::make_blend(red, blue, 0.25), 20000);
+        assert!((mean.x - 0.75).abs() < 0.03);
+        assert!((mean.z - 0.25).abs() < 0.03);
+    }
+
+    #[test]
+    fn test_nested_blend() {
+        let red = MaterialFactory::make_emitter(Color3::new(1.0, 0.0, 0.0), 1.0);
+        let green = MaterialFactory::make_emitter(Color3::new(0.0, 1.0, 0.0), 1.0);
+        let blue = MaterialFactory::make_emitter(Color3::new(0.0, 0.0, 1.0), 1.0);
+        let inner = MaterialFactory::make_blend(red, green, 0.5);
+        let mean = mean_emission(&MaterialFactory::make_blend(inner, blue, 0.5), 20000);
+        assert!((mean.x - 0.25).abs() < 0.03);
+        assert!((mean.y - 0.25).abs() < 0.03);
+        assert!((mean.z - 0.5).abs() < 0.03);
+    }
+}
+
+#[cfg(test)]
+mod principled_tests {
+    use crate::datatypes::{Color3, HitRecord, Point3, Ray, Vec3};
+    use crate::materials::{Material, MatLambertian, Materials, PrincipledBuilder};
+
+    fn front_hit() -> (Ray, HitRecord) {
+        let mut hit_rec = HitRecord::new(Point3::zero(), Vec3::z_u(), 1.0, Materials::DifuseLamb(MatLambertian::GRAY));
+        let ray = Ray::new(Point3::new(-0.5, 0.0, 1.0), Vec3::new(0.5, 0.0, -1.0));
+        hit_rec.set_face_normal(&ray, &Vec3::z_u());
+        (ray, hit_rec)
+    }
+
+    #[test]
+    fn test_rough_white_metal_conserves_energy() {
+        let material = PrincipledBuilder::new(Color3::one()).metallic(1.0).roughness(0.4).build();
+        let (ray, hit_rec) = front_hit();
+        let samples = 20000;
+        let mut sum = 0.0;
+        for _ in 0..samples {
+            if let Some((att, Some(sc_ray))) = material.scatter(&ray, &hit_rec) {
+                assert!(sc_ray.direction().z > 0.0);
+                sum += att.x;
+            }
+        }
+        let albedo = sum / samples as f64;
+        assert!(albedo > 0.8 && albedo < 1.01, "albedo {albedo}");
+    }
+
+    #[test]
+    fn test_diffuse_base_color() {
+        let material = PrincipledBuilder::new(Color3::new(0.8, 0.2, 0.1)).specular(0.0).roughness(1.0).build();
+        let (ray, hit_rec) = front_hit();
+        let samples = 20000;
+        let mut sum = Color3::zero();
+        for _ in 0..samples {
+            if let Some((att, Some(_))) = material.scatter(&ray, &hit_rec) {
+                sum += att;
+            }
+        }
+        let albedo = sum / samples as f64;
+        assert!(albedo.x > albedo.y && albedo.y > albedo.z);
+        assert!(albedo.x > 0.5 && albedo.x < 1.1, "albedo {albedo}");
+    }
+
+    #[test]
+    fn test_transmission_refracts() {
+        let material = PrincipledBuilder::new(Color3::one()).transmission(1.0).roughness(0.1).build();
+        let (ray, hit_rec) = front_hit();
+        let transmitted = (0..1000).filter(|_| {
+            matches!(material.scatter(&ray, &hit_rec), Some((_, Some(sc_ray))) if sc_ray.direction().z < 0.0)
+        }).count();
+        assert!(transmitted > 800);
+    }
+}
+
+#[cfg(test)]
+mod assets_tests {
+    use crate::assets::MtlLibrary;
+    use crate::materials::Materials;
+
+    #[test]
+    fn test_mtl_maps_to_principled() {
+        let library = MtlLibrary::parse("# exported\n\
+            newmtl brushed steel\nKd 0.5 0.6 0.7\nPm 1.0\nPr 0.3\nmap_Kd steel.png\nillum 2\n\
+            newmtl car paint\nKd 0.8\nPc 1.0\nPcr 0.2\nNi 1.45\n");
+        assert_eq!(library.len(), 2);
+        let Some(Materials::Principled(steel)) = library.get("brushed steel") else {
+            panic!("brushed steel is missing");
+        };
+        assert_eq!((steel.base_color.z, steel.metallic, steel.roughness), (0.7, 1.0, 0.3));
+        let Some(Materials::Principled(paint)) = library.get("car paint") else {
+            panic!("car paint is missing");
+        };
+        assert_eq!((paint.base_color.y, paint.clearcoat, paint.ir), (0.8, 1.0, 1.45));
+        assert!((paint.clearcoat_gloss - 0.8).abs() < 1e-12);
+        assert_eq!(paint.metallic, 0.0);
+    }
+
+    #[test]
+    fn test_mtl_maps_transmission() {
+        let library = MtlLibrary::parse("newmtl glass
+Kd 0.1 0.1 0.1
+Ks 0.9 0.9 0.9
+Tf 0.9 1.0 0.9
+d 0.0
+Ni 1.5
+            newmtl tinted
+Tr 0.25
+            newmtl opaque
+Kd 0.2 0.3 0.4
+Tf 1 1 1
+d 1.0
+");
+        let Some(Materials::Principled(glass)) = library.get("glass") else {
+            panic!("glass is missing");
+        };
+        assert_eq!((glass.transmission, glass.base_color.y, glass.specular), (1.0, 1.0, 0.5));
+        let Some(Materials::Principled(tinted)) = library.get("tinted") else {
+            panic!("tinted is missing");
+        };
+        assert_eq!(tinted.transmission, 0.25);
+        let Some(Materials::Principled(opaque)) = library.get("opaque") else {
+            panic!("opaque is missing");
+        };
+        assert_eq!((opaque.transmission, opaque.base_color.y), (0.0, 0.3));
+    }
+}
+
+#[cfg(test)]
+mod light_tests {
+    use std::f64::consts::PI;
+
+    use crate::camera::Camera;
+    use crate::datatypes::{Color3, Point3, Ray, Vec3};
+    use crate::lights::{Light, LightFactory, LightSpot};
+    use crate::materials::{MatLambertian, Materials};
+    use crate::shapes::{HittableList, Hittables, Sphere};
+
+    fn lit_world(light_position: Point3) -> HittableList {
+        let mut world = HittableList::new();
+        world.add(Hittables::Sphere(Sphere::new(0.5, 0.0, 0.0, -1.0, Materials::DifuseLamb(MatLambertian::GRAY))));
+        world.add_light(LightFactory::make_point(light_position, Color3::one(), 9.0));
+        world
+    }
+
+    #[test]
+    fn test_point_light_direct_lighting() {
+        let world = lit_world(Point3::new(0.0, 0.0, 1.0));
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let color = Camera::ray_color(&ray, 1, &world);
+        let expected = 0.5 / PI * 9.0 / (1.5 * 1.5);
+        assert!((color.x - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_point_light_shadowed() {
+        let world = lit_world(Point3::new(0.0, 0.0, -3.0));
+        let ray = Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let color = Camera::ray_color(&ray, 1, &world);
+        assert_eq!(color, Color3::zero());
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let spot = LightSpot::new(Point3::zero(), Vec3::new(0.0, -1.0, 0.0), Color3::one(), 1.0, 60.0, 10.0);
+        let center = spot.sample_li(&Point3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((center.radiance.x - 1.0).abs() < 1e-9);
+        let edge = spot.sample_li(&Point3::new(f64::tan(0.45), -1.0, 0.0)).unwrap();
+        assert!(edge.radiance.x > 0.0 && edge.radiance.x < center.radiance.x);
+        assert!(spot.sample_li(&Point3::new(1.0, -1.0, 0.0)).is_none());
+    }
+}
+
+#[cfg(test)]
+mod sky_tests {
+    use crate::datatypes::Vec3;
+    use crate::lights::{Light, LightDirectional, Lights};
+    use crate::shapes::HittableList;
+    use crate::sky::{Skies, Sky, SkyPreetham};
+    use crate::datatypes::Color3;
+
+    #[test]
+    fn test_preetham_zenith_is_blue() {
+        let sky = SkyPreetham::new(30.0, 0.0, 3.0);
+        let zenith = sky.radiance(&Vec3::y_u());
+        assert!(zenith.z > zenith.x);
+        assert!(zenith.x > 0.0);
+    }
+
+    #[test]
+    fn test_preetham_brighter_with_higher_sun() {
+        let low = SkyPreetham::new(5.0, 0.0, 3.0).radiance(&Vec3::y_u());
+        let high = SkyPreetham::new(60.0, 0.0, 3.0).radiance(&Vec3::y_u());
+        assert!(high.y > low.y);
+    }
+
+    #[test]
+    fn test_sun_disk_light() {
+        let sun = LightDirectional::new(Vec3::new(0.0, -1.0, 0.0), Color3::one(), 2.0).with_angular_radius(1.0);
+        assert!(sun.le(&Vec3::y_u()).x > 0.0);
+        assert_eq!(sun.le(&Vec3::new(0.1, 1.0, 0.0)), Color3::zero());
+        for _ in 0..100 {
+            let sample = sun.sample_li(&Vec3::zero()).unwrap();
+            assert!(sample.direction.y > f64::cos(1.0_f64.to_radians()) - 1e-9);
+            assert!((sample.radiance.x / sample.pdf - 2.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_daylight_comes_with_its_sun() {
+        let mut world = HittableList::new();
+        assert!(matches!(world.sky, Skies::Gradient(_)) && world.lights().is_empty());
+        world.set_daylight(SkyPreetham::new(30.0, 0.0, 3.0), 4.0);
+        assert!(matches!(world.sky, Skies::Preetham(_)));
+        assert!(matches!(world.lights(), [Lights::Directional(_)]));
+    }
+}
+
+#[cfg(test)]
+mod area_light_tests {
+    use std::f64::consts::PI;
+
+    use crate::camera::Camera;
+    use crate::datatypes::{Color3, Point3, Ray, Sampleable, Vec3};
+    use crate::materials::MaterialFactory;
+    use crate::shapes::{HittableList, Quad, ShapeFactory, Sphere};
+    use crate::utils::LightUtil;
+
+    #[test]
+    fn test_sphere_cone_sampling() {
+        let sphere = Sphere::new(1.0, 0.0, 0.0, -4.0, MaterialFactory::make_emitter(Color3::one(), 1.0));
+        let origin = Point3::zero();
+        let solid_angle = 2.0 * PI * (1.0 - f64::sqrt(1.0 - 1.0 / 16.0));
+        for _ in 0..100 {
+            let sample = sphere.sample(&origin).unwrap();
+            let p = &sample.hit_rec.p;
+            assert!(((p - &sphere.center).len() - 1.0).abs() < 1e-9);
+            assert!(sample.hit_rec.normal.dot(&(&origin - p)) >= 0.0);
+            assert!((sample.pdf * solid_angle - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_quad_sampling_solid_angle() {
+            // Unit square centered on the z axis, one unit away.
+        let quad = Quad::new(Point3::new(-0.5, -0.5, -1.0), Vec3::x_u(), Vec3::y_u(), MaterialFactory::make_emitter(Color3::one(), 1.0));
+        let n = 200_000;
+        let estimate = (0..n).map(|_| 1.0 / quad.sample(&Point3::zero()).unwrap().pdf).sum::<f64>() / n as f64;
+        let expected = 4.0 * f64::asin(0.25 / 1.25);
+        assert!((estimate - expected).abs() < 0.01 * expected);
+    }
+
+    #[test]
+    fn test_emissive_shapes_become_lights() {
+        let mut world = HittableList::new();
+        world.add(ShapeFactory::make_quad(Point3::new(-0.5, -0.5, -1.0), Vec3::x_u(), Vec3::y_u(),
+                                          MaterialFactory::make_emitter(Color3::one(), 3.0)));
+        world.add(ShapeFactory::make_triangle(Point3::new(0.0, 0.0, -2.0), Point3::new(1.0, 0.0, -2.0), Point3::new(0.0, 1.0, -2.0),
+                                              MaterialFactory::make_lambertian(Color3::one())));
+        assert_eq!(world.lights().len(), 1);
+            // Seen directly the emitter keeps its full radiance.
+        let color = Camera::ray_color(&Ray::new(Point3::zero(), Vec3::new(0.0, 0.0, -1.0)), 5, &world);
+        assert!((color.x - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_area_light_power_counts_both_sides() {
+        let mut world = HittableList::new();
+        world.add(ShapeFactory::make_quad(Point3::new(0.0, 0.0, -1.0), 2.0 * Vec3::x_u(), Vec3::y_u(),
+                                          MaterialFactory::make_emitter(Color3::one(), 3.0)));
+            // 3 radiance over 2 square units, π per side.
+        let power = LightUtil::power(&world.lights()[0]);
+        assert!((power - 2.0 * PI * 2.0 * 3.0).abs() < 1e-9);
+        assert_eq!(power, LightUtil::power(&world.lights()[0]));
+    }
+}
+
+#[cfg(test)]
+mod light_tree_tests {
+    use crate::datatypes::{Color3, Point3, Vec3};
+    use crate::light_tree::LightTree;
+    use crate::lights::{LightFactory, Lights};
+
+    fn row_of_lights(n: usize) -> Vec<Lights> {
+        (0..n).map(|i| LightFactory::make_point(Point3::new(i as f64, 0.0, 0.0), Color3::one(), 1.0)).collect()
+    }
+
+    #[test]
+    fn test_pmf_sums_to_one() {
+        let mut lights = row_of_lights(37);
+        lights.push(LightFactory::make_directional(Vec3::new(0.0, -1.0, 0.0), Color3::one(), 1.0));
+        let tree = LightTree::new(&lights);
+        let p = Point3::new(3.3, 1.0, 0.5);
+        let total: f64 = (0..lights.len()).map(|i| tree.pmf(&p, i)).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!((tree.pmf(&p, 37) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_matches_pmf() {
+        let tree = LightTree::new(&row_of_lights(8));
+        let p = Point3::new(1.2, 0.5, 0.0);
+        for _ in 0..100 {
+            let (index, pmf) = tree.sample(&p).unwrap();
+            assert!((pmf - tree.pmf(&p, index)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_prefers_nearby_lights() {
+        let tree = LightTree::new(&row_of_lights(64));
+        let p = Point3::new(0.0, 0.5, 0.0);
+        assert!(tree.pmf(&p, 0) > 10.0 * tree.pmf(&p, 63));
+    }
+}
+
+#[cfg(test)]
+mod bdpt_tests {
+    use crate::bdpt::SplatBuffer;
+    use crate::camera::CameraBuilder;
+    use crate::datatypes::{Color3, Point3, Vec3};
+    use crate::integrators::IntegratorFactory;
+    use crate::materials::MaterialFactory;
+    use crate::shapes::{HittableList, ShapeFactory};
+    use crate::sky::{Skies, SkyGradient};
+
+    #[test]
+    fn test_camera_raster_round_trip() {
+        let camera = CameraBuilder::new().image_width(40).look_from(Point3::new(1.0, 2.0, 3.0)).defocus_angle(3.0).focus_dist(2.0).build();
+        for (i, j) in [(0, 0), (17, 11), (39, 21)] {
+            let (ray, _) = camera.get_ray(i, j);
+            let p = ray.origin() + &(5.0 * ray.direction());
+            assert_eq!(camera.raster(ray.origin(), &p), Some((i, j)));
+        }
+    }
+
+    #[test]
+    fn test_bdpt_matches_path_tracer() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::zero(), Color3::zero())));
+        world.add(ShapeFactory::make_quad(Point3::new(-5.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 0.0),
+                                          MaterialFactory::make_lambertian(Color3::new(0.7, 0.7, 0.7))));
+        world.add(ShapeFactory::make_quad(Point3::new(-0.5, 2.0, -1.5), Vec3::x_u(), Vec3::new(0.0, 0.0, 1.0),
+                                          MaterialFactory::make_emitter(Color3::one(), 4.0)));
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, 0.5, -1.0, MaterialFactory::make_lambertian(Color3::new(0.8, 0.3, 0.3))));
+
+        let render = |integrator, samples| {
+            let camera = CameraBuilder::new().image_width(8).samples_per_pixel(samples).max_bounces(4)
+                .look_from(Point3::new(0.0, 1.0, 2.0)).look_at(Point3::new(0.0, 0.3, -1.0))
+                .integrator(integrator).build();
+            let pixels = camera.render(&world);
+            pixels.iter().map(|c| c.x + c.y + c.z).sum::<f64>() / pixels.len() as f64
+        };
+        let path = render(IntegratorFactory::make_path(), 8000);
+        let bdpt = render(IntegratorFactory::make_bidirectional(), 4000);
+        assert!((path - bdpt).abs() < 0.1 * path);
+    }
+
+    #[test]
+    fn test_huge_splats_saturate() {
+        let splats = SplatBuffer::new(1, 1);
+        splats.add(0, 0, 1, &Color3::new(1e12, 0.25, 0.0));
+        splats.add(0, 0, 1, &Color3::new(1e12, 0.25, 0.0));
+        let total = splats.get(0);
+        assert!(total.x > 5e11);
+        assert_eq!(total.y, 0.5);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod photon_tests {
+    use crate::datatypes::{Color3, Point3, Vec3};
+    use crate::materials::MaterialFactory;
+    use crate::light_tree::LightPowerTable;
+    use crate::photons::{Photon, PhotonMap, PhotonMapper};
+    use crate::shapes::{HittableList, ShapeFactory};
+
+    #[test]
+    fn test_kd_tree_matches_brute_force() {
+        let photons: Vec<Photon> = (0..500).map(|_| Photon {
+            p: Point3::random_ran(-1.0, 1.0), direction: Vec3::y_u(), normal: Vec3::y_u(), power: Color3::one()
+        }).collect();
+        let map = PhotonMap::new(photons.clone());
+        let center = Point3::new(0.2, -0.1, 0.3);
+        let mut found = 0;
+        map.for_each_near(&center, 0.25, &mut |_| found += 1);
+        let expected = photons.iter().filter(|photon| (&photon.p - &center).len_sqr() <= 0.25).count();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_radius_shrinks() {
+        assert_eq!(PhotonMapper::radius_sqr(0.5, 0), 0.25);
+        let radii: Vec<f64> = (0..10).map(|pass| PhotonMapper::radius_sqr(0.5, pass)).collect();
+        assert!(radii.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+
+    #[test]
+    fn test_only_caustic_photons_are_kept() {
+        let mut world = HittableList::new();
+        world.add(ShapeFactory::make_quad(Point3::new(-5.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 0.0),
+                                          MaterialFactory::make_lambertian(Color3::one())));
+        world.add(ShapeFactory::make_sphere(0.2, 0.0, 3.0, 0.0, MaterialFactory::make_emitter(Color3::one(), 1.0)));
+        let power = LightPowerTable::new(world.lights());
+        let mapper = PhotonMapper::new(&world, &power, 8);
+        assert!(mapper.shoot(1000, 0).is_empty());
+
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, 1.0, 0.0, MaterialFactory::make_dielectric(1.5)));
+        let power = LightPowerTable::new(world.lights());
+        let mapper = PhotonMapper::new(&world, &power, 8);
+        let map = mapper.shoot(2000, 0);
+        assert!(!map.is_empty());
+        let mut on_ground = 0;
+        map.for_each_near(&Point3::zero(), 1e6, &mut |photon| if photon.p.y.abs() < 1e-9 { on_ground += 1 });
+        assert_eq!(on_ground, map.len());
+    }
+}
+
+#[cfg(test)]
+mod integrator_tests {
+    use std::f64::consts::PI;
+
+    use crate::camera::CameraBuilder;
+    use crate::datatypes::{Color3, Point3, Vec3};
+    use crate::integrators::{DebugModes, IntegratorFactory, Integrators};
+    use crate::lights::LightFactory;
+    use crate::materials::MaterialFactory;
+    use crate::shapes::{HittableList, ShapeFactory};
+    use crate::sky::{Skies, SkyGradient};
+
+    #[test]
+    fn test_integrator_from_name() {
+        assert!(matches!(IntegratorFactory::from_name("path"), Some(Integrators::Path(_))));
+        assert!(matches!(IntegratorFactory::from_name("whitted"), Some(Integrators::Whitted(_))));
+        assert!(matches!(IntegratorFactory::from_name("bdpt"), Some(Integrators::Bidirectional(_))));
+        assert!(matches!(IntegratorFactory::from_name("photons"), Some(Integrators::PhotonMapping(_))));
+        assert!(IntegratorFactory::from_name("raymarch").is_none());
+    }
+
+    #[test]
+    fn test_whitted_direct_light_only() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::zero(), Color3::zero())));
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, 0.0, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0),
+                                          MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5))));
+        world.add_light(LightFactory::make_point(Point3::new(0.0, 2.0, 0.0), Color3::one(), 4.0));
+
+        let camera = CameraBuilder::new().image_width(1).aspect_ratio(1.0).samples_per_pixel(1).vertical_fov(1.0)
+            .look_from(Point3::new(0.0, 1.0, 0.0)).look_at(Point3::zero()).vector_up(Vec3::z_u())
+            .integrator(IntegratorFactory::make_whitted()).build();
+        let pixels = camera.render(&world);
+        let expected = 0.5 / PI * 4.0 / (2.0 * 2.0);
+        assert!((pixels[0].x - expected).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn test_whitted_follows_mirrors() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::one(), Color3::one())));
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, 0.0, -1.0, MaterialFactory::make_metal(Color3::new(0.8, 0.8, 0.8), 0.0)));
+
+        let camera = CameraBuilder::new().image_width(1).aspect_ratio(1.0).samples_per_pixel(1).vertical_fov(1.0)
+            .integrator(IntegratorFactory::make_whitted()).build();
+        let pixels = camera.render(&world);
+        assert!((pixels[0].x - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ambient_occlusion_under_ceiling() {
+        let mut world = HittableList::new();
+        let gray = MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5));
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, 0.0, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0), gray.clone()));
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, 0.5, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0), gray));
+
+            // Directions with cos > h / radius reach the ceiling, which cosine sampling picks 1 - (h / radius)^2 of the time.
+        let camera = CameraBuilder::new().image_width(1).aspect_ratio(1.0).samples_per_pixel(20000).vertical_fov(1.0)
+            .look_from(Point3::new(0.0, 0.25, 0.0)).look_at(Point3::zero()).vector_up(Vec3::z_u())
+            .integrator(IntegratorFactory::make_ambient_occlusion(1.0)).build();
+        let pixels = camera.render(&world);
+        assert!((pixels[0].x - 0.25).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_debug_views_of_first_hit() {
+        let mut world = HittableList::new();
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, 0.0, -1.5, MaterialFactory::make_metal(Color3::one(), 0.0)));
+        for i in 0..9 {
+            world.add(ShapeFactory::make_sphere(0.1, i as f64, 10.0, 0.0, MaterialFactory::make_dielectric(1.5)));
+        }
+        let render = |mode| {
+            let camera = CameraBuilder::new().image_width(1).aspect_ratio(1.0).samples_per_pixel(1).vertical_fov(1.0)
+                .integrator(IntegratorFactory::make_debug(mode)).build();
+            camera.render(&world)[0].clone()
+        };
+        let normal = render(DebugModes::Normal);
+        assert!((normal.z - 1.0).abs() < 1e-3 && (normal.x - 0.5).abs() < 1e-2);
+        let depth = render(DebugModes::Depth { far: 2.0 });
+        assert!((depth.x - 0.5).abs() < 1e-3);
+        assert!(render(DebugModes::MaterialId) != Color3::zero());
+            // Every one of the ten spheres is tested once, which is the middle of the ramp.
+        let cost = render(DebugModes::IntersectionCost { max_tests: 20.0 });
+        assert!((cost.y - 1.0).abs() < 1e-9);
+    }
+}
+
+#[cfg(test)]
+mod aov_tests {
+    use std::f64::consts::PI;
+
+    use crate::aovs::AovKinds;
+    use crate::camera::CameraBuilder;
+    use crate::datatypes::{Color3, Point3, Vec3};
+    use crate::integrators::{DebugModes, IntegratorFactory};
+    use crate::lights::LightFactory;
+    use crate::materials::MaterialFactory;
+    use crate::shapes::{HittableList, ShapeFactory};
+    use crate::sky::{Skies, SkyGradient};
+
+    #[test]
+    fn test_first_hit_buffers_and_direct_light() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::zero(), Color3::zero())));
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, 0.0, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0),
+                                          MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5))));
+        world.add_light(LightFactory::make_point(Point3::new(0.0, 2.0, 0.0), Color3::one(), 4.0));
+
+        let camera = CameraBuilder::new().image_width(1).aspect_ratio(1.0).samples_per_pixel(4).vertical_fov(1.0)
+            .look_from(Point3::new(0.0, 1.0, 0.0)).look_at(Point3::zero()).vector_up(Vec3::z_u()).build();
+        let (pixels, aovs) = camera.render_aovs(&world);
+
+        assert!((aovs.layer(AovKinds::Albedo)[0].x - 0.5).abs() < 1e-9);
+        assert!((aovs.layer(AovKinds::Normal)[0].y - 1.0).abs() < 1e-9);
+        assert!((aovs.layer(AovKinds::Depth)[0].x - 1.0).abs() < 1e-3);
+        assert!(aovs.layer(AovKinds::Position)[0].y.abs() < 1e-9);
+        assert_eq!(aovs.layer(AovKinds::ObjectId)[0].x, 1.0);
+        assert_eq!(aovs.layer(AovKinds::MaterialId)[0].x, 1.0);
+        let expected = 0.5 / PI * 4.0 / (2.0 * 2.0);
+        assert!((aovs.layer(AovKinds::Direct)[0].x - expected).abs() < 1e-3 * expected);
+        assert_eq!(aovs.layer(AovKinds::Emission)[0], Color3::zero());
+        assert_eq!(aovs.layer(AovKinds::Indirect)[0], Color3::zero());
+        assert!((pixels[0].x - expected).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn test_background_is_emission_without_ids() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::one(), Color3::one())));
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, -10.0, 0.0, MaterialFactory::make_lambertian(Color3::one())));
+
+        let camera = CameraBuilder::new().image_width(2).samples_per_pixel(2).build();
+        let (pixels, aovs) = camera.render_aovs(&world);
+        for (index, pixel) in pixels.iter().enumerate() {
+            assert_eq!(aovs.layer(AovKinds::Emission)[index], pixel.clone());
+            assert_eq!(aovs.layer(AovKinds::ObjectId)[index], Color3::zero());
+            assert_eq!(aovs.layer(AovKinds::Depth)[index], Color3::zero());
+        }
+        assert_eq!(aovs.preview(AovKinds::MaterialId)[0], Color3::zero());
+    }
+
+    #[test]
+    fn test_light_split_adds_up_to_beauty() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::zero(), Color3::new(0.2, 0.2, 0.2))));
+        world.add(ShapeFactory::make_quad(Point3::new(-5.0, -0.5, -5.0), Vec3::new(0.0, 0.0, 10.0), Vec3::new(10.0, 0.0, 0.0),
+                                          MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5))));
+        world.add(ShapeFactory::make_sphere(0.4, -0.5, 0.0, -1.5, MaterialFactory::make_metal(Color3::new(0.8, 0.8, 0.8), 0.0)));
+        world.add(ShapeFactory::make_sphere(0.3, 0.5, 0.0, -1.5, MaterialFactory::make_emitter(Color3::one(), 4.0)));
+        world.add_light(LightFactory::make_point(Point3::new(0.0, 2.0, -1.0), Color3::one(), 4.0));
+
+        for integrator in [IntegratorFactory::make_path(), IntegratorFactory::make_whitted(),
+                           IntegratorFactory::make_bidirectional(), IntegratorFactory::make_photon_mapping(2000, 0.1)] {
+            let camera = CameraBuilder::new().image_width(8).aspect_ratio(1.0).samples_per_pixel(4).max_bounces(4)
+                .integrator(integrator).build();
+            let (pixels, aovs) = camera.render_aovs(&world);
+            let mut lit = [false; 3];
+            for (index, pixel) in pixels.iter().enumerate() {
+                let layers = [AovKinds::Emission, AovKinds::Direct, AovKinds::Indirect].map(|kind| aovs.layer(kind)[index].clone());
+                let sum = &(&layers[0] + &layers[1]) + &layers[2];
+                assert!((&sum - pixel).len() < 1e-6 * (1.0 + pixel.len()));
+                for (lit, layer) in lit.iter_mut().zip(&layers) {
+                    *lit |= !layer.is_near_zero();
+                }
+            }
+            assert!(lit[0] && lit[1]);
+        }
+    }
+
+    #[test]
+    fn test_every_integrator_reports_the_first_hit() {
+        let mut world = HittableList::new();
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, 0.0, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0),
+                                          MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5))));
+        world.add_light(LightFactory::make_point(Point3::new(0.0, 2.0, 0.0), Color3::one(), 4.0));
+
+        for integrator in [IntegratorFactory::make_path(), IntegratorFactory::make_whitted(),
+                           IntegratorFactory::make_bidirectional(), IntegratorFactory::make_photon_mapping(2000, 0.1),
+                           IntegratorFactory::make_ambient_occlusion(1.0), IntegratorFactory::make_debug(DebugModes::Normal)] {
+            let camera = CameraBuilder::new().image_width(1).aspect_ratio(1.0).samples_per_pixel(4).vertical_fov(1.0).focus_dist(4.0)
+                .look_from(Point3::new(0.0, 2.0, 0.0)).look_at(Point3::zero()).vector_up(Vec3::z_u())
+                .integrator(integrator).build();
+            let (_, aovs) = camera.render_aovs(&world);
+            assert!((aovs.layer(AovKinds::Depth)[0].x - 2.0).abs() < 1e-3);
+            assert!((aovs.layer(AovKinds::Normal)[0].y - 1.0).abs() < 1e-9);
+            assert_eq!(aovs.layer(AovKinds::ObjectId)[0].x, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod hdr_output_tests {
+    use exr::prelude::*;
+
+    use crate::datatypes::Color3;
+    use crate::utils::{ExrPrecisions, ImageUtil};
+
+    fn gradient() -> Vec<Color3> {
+        (0..12).map(|i| Color3::new(i as f64 * 10.0, 0.25, -1.5)).collect()
+    }
+
+    #[test]
+    fn test_exr_layers_round_trip() {
+        let path = std::env::temp_dir().join("raytracing-rust-layers.exr");
+        let beauty = gradient();
+        let normal: Vec<Color3> = beauty.iter().map(|c| c * 0.5).collect();
+        ImageUtil::save_exr(path.to_str().unwrap(), &[("beauty", &beauty), ("normal", &normal)], 4, 3, ExrPrecisions::Float).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .from_file(&path).unwrap();
+        assert_eq!(image.layer_data.len(), 2);
+        let normal_layer = image.layer_data.iter().find(|layer| layer.attributes.layer_name == Some(Text::from("normal"))).unwrap();
+        let red = normal_layer.channel_data.list.iter().find(|channel| channel.name.eq("R")).unwrap();
+        let values: Vec<f32> = red.sample_data.values_as_f32().collect();
+        assert_eq!(values[11], 55.0);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_exr_half_precision() {
+        let path = std::env::temp_dir().join("raytracing-rust-half.exr");
+        ImageUtil::save_exr(path.to_str().unwrap(), &[("beauty", &gradient())], 4, 3, ExrPrecisions::Half).unwrap();
+
+        let image = read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer().all_attributes()
+            .from_file(&path).unwrap();
+        let channel = &image.layer_data.channel_data.list[0];
+        assert!(matches!(channel.sample_data, FlatSamples::F16(_)));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_hdr_keeps_range() {
+        let path = std::env::temp_dir().join("raytracing-rust.hdr");
+            // RGBE shares one exponent per pixel, so small channels lose precision.
+        let pixels = vec![Color3::new(40.0, 1.0, 0.0); 6];
+        ImageUtil::save_hdr(path.to_str().unwrap(), &pixels, 3, 2).unwrap();
+
+        let image = image::open(&path).unwrap().into_rgb32f();
+        let pixel = image.get_pixel(2, 1);
+        assert!((pixel[0] - 40.0).abs() < 0.5 && (pixel[1] - 1.0).abs() < 0.2);
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tonemap_tests {
+    use crate::datatypes::Color3;
+    use crate::tonemap::{Display, ToneMappers};
+    use crate::utils::ColorUtil;
+
+    #[test]
+    fn test_srgb_transfer() {
+        assert_eq!(ColorUtil::linear_to_srgb(0.0), 0.0);
+        assert!((ColorUtil::linear_to_srgb(1.0) - 1.0).abs() < 1e-12);
+        assert!((ColorUtil::linear_to_srgb(0.002) - 0.02584).abs() < 1e-9);
+        assert!((ColorUtil::linear_to_srgb(0.18) - 0.4614).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_operators_stay_in_range_and_increase() {
+        let operators = [ToneMappers::Clamp, ToneMappers::Reinhard, ToneMappers::ReinhardExtended { white: 4.0 },
+                         ToneMappers::AcesFilmic, ToneMappers::Hable, ToneMappers::AgX];
+        for operator in operators {
+            let mut last = -1.0;
+            for stop in -8..12 {
+                let value = operator.apply(&(Color3::one() * f64::powi(2.0, stop))).y;
+                assert!((0.0..=1.0).contains(&value), "{operator:?}");
+                assert!(value >= last, "{operator:?}");
+                last = value;
+            }
+        }
+        assert!((ToneMappers::ReinhardExtended { white: 4.0 }.apply(&(Color3::one() * 4.0)).x - 1.0).abs() < 1e-12);
+        assert!((ToneMappers::Reinhard.apply(&Color3::one()).x - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_exposure_in_stops() {
+        let color = Color3::new(0.1, 0.2, 0.05);
+        let brighter = Display::new(1.0, ToneMappers::Clamp).encode(&color);
+        let doubled = Display::DEFAULT.encode(&(&color * 2.0));
+        assert!((brighter.y - doubled.y).abs() < 1e-12);
+        assert_eq!(ColorUtil::get_display_pixel(&(Color3::one() * 100.0), &Display::DEFAULT), image::Rgb([255, 255, 255]));
+    }
+}
+
+#[cfg(test)]
+mod denoise_tests {
+    use crate::datatypes::{Color3, Vec3};
+    use crate::denoise::Denoiser;
+    use crate::utils::MathUtil;
+
+    fn variance(values: &[f64]) -> f64 {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+    }
+
+    #[test]
+    fn test_denoise_smooths_and_keeps_albedo_edge() {
+        let (width, height) = (32, 16);
+        let albedo: Vec<Color3> = (0..width * height).map(|index| {
+            if index % width < width / 2 { Color3::one() * 0.2 } else { Color3::one() * 0.8 }
+        }).collect();
+        let normal = vec![Vec3::y_u(); (width * height) as usize];
+        MathUtil::seed(7);
+        let noisy: Vec<Color3> = albedo.iter().map(|a| a * (0.5 + 0.4 * (MathUtil::rand() - 0.5))).collect();
+
+        let denoised = Denoiser::new().denoise(&noisy, &albedo, &normal, width, height);
+        let left = |image: &[Color3]| image.iter().enumerate().filter(|(index, _)| *index as u32 % width < width / 2)
+            .map(|(_, c)| c.x).collect::<Vec<f64>>();
+        let right = |image: &[Color3]| image.iter().enumerate().filter(|(index, _)| *index as u32 % width >= width / 2)
+            .map(|(_, c)| c.x).collect::<Vec<f64>>();
+
+        assert!(variance(&left(&denoised)) < 0.1 * variance(&left(&noisy)));
+        assert!(variance(&right(&denoised)) < 0.1 * variance(&right(&noisy)));
+        let mean = |values: Vec<f64>| values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean(left(&denoised)) - mean(left(&noisy))).abs() < 0.005);
+        assert!((mean(right(&denoised)) - mean(right(&noisy))).abs() < 0.005);
+    }
+
+    #[test]
+    fn test_denoise_keeps_normal_edges() {
+        let (width, height) = (16, 16);
+        let albedo = vec![Color3::one(); (width * height) as usize];
+        let normal: Vec<Vec3> = (0..width * height).map(|index| if index / width < height / 2 { Vec3::y_u() } else { Vec3::x_u() }).collect();
+        let image: Vec<Color3> = normal.iter().map(|n| if n.y > 0.0 { Color3::one() } else { Color3::zero() }).collect();
+
+        let denoised = Denoiser::new().with_sigmas(10.0, 0.3, 0.1).denoise(&image, &albedo, &normal, width, height);
+        assert!(denoised.iter().zip(&image).all(|(d, i)| (d.x - i.x).abs() < 1e-3));
+    }
+}
+
+#[cfg(test)]
+mod progressive_tests {
+    use crate::camera::CameraBuilder;
+    use crate::datatypes::Color3;
+    use crate::datatypes::{Point3, Vec3};
+    use crate::materials::MaterialFactory;
+    use crate::progressive::{AdaptiveSampling, Moments, PixelMoments, RenderBudget};
+    use crate::shapes::ShapeFactory;
+    use crate::shapes::HittableList;
+    use crate::sky::{Skies, SkyGradient};
+
+    fn flat_sky() -> HittableList {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::one(), Color3::one())));
+        world
+    }
+
+    #[test]
+    fn test_sample_budget_calls_back_every_pass() {
+        let camera = CameraBuilder::new().image_width(4).aspect_ratio(1.0).samples_per_pixel(100).build();
+        let budget = RenderBudget::new().with_samples(5).with_pass_samples(2);
+        let mut passes = Vec::new();
+        let pixels = camera.render_progressive(&flat_sky(), &budget, |image, info| {
+            assert_eq!(image.len(), 16);
+            passes.push((info.pass, info.samples));
+        });
+        assert_eq!(passes, vec![(1, 2), (2, 4), (3, 5)]);
+        assert!(pixels.iter().all(|c| (c.x - 1.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_noise_budget_stops_when_converged() {
+        let camera = CameraBuilder::new().image_width(4).aspect_ratio(1.0).build();
+        let mut passes = 0;
+        camera.render_progressive(&flat_sky(), &RenderBudget::new().with_noise(0.01), |_, info| passes = info.pass);
+        assert_eq!(passes, 2);
+    }
+
+    #[test]
+    fn test_pixel_moments_error() {
+        let mut moments = PixelMoments::new(1);
+        moments.add(0, 1.0);
+        assert_eq!(moments.error_sqr(0), None);
+        moments.add(0, 3.0);
+        assert!((moments.mean(0) - 2.0).abs() < 1e-12);
+        assert!((moments.error_sqr(0).unwrap() - 1.0).abs() < 1e-12);
+        assert!((moments.relative_error().unwrap() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_adaptive_samples_for() {
+        let adaptive = AdaptiveSampling::new(4, 10, 0.1);
+        let mut moments = Moments::default();
+        assert_eq!(adaptive.samples_for(&moments), 4);
+        (0..4).for_each(|_| moments.add(1.0));
+        assert_eq!(adaptive.samples_for(&moments), 0);
+        (0..4).for_each(|sample| moments.add(sample as f64));
+        assert_eq!(adaptive.samples_for(&moments), 2);
+    }
+
+    #[test]
+    fn test_adaptive_sampling_spends_samples_on_noise() {
+            // The sky converges right away, while the floor and the sphere on it are lit
+            // through a partly blocked hemisphere.
+        let mut world = flat_sky();
+        let gray = MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5));
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, -0.5, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0), gray.clone()));
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, 0.0, -3.0, gray));
+        let camera = CameraBuilder::new().image_width(8).aspect_ratio(1.0)
+            .adaptive_sampling(AdaptiveSampling::new(8, 64, 0.01)).build();
+        let mut last = None;
+        camera.render_progressive(&world, &RenderBudget::new().with_samples(64), |_, info| last = Some(*info));
+        let info = last.unwrap();
+        assert!(info.samples > 8 && info.samples < 64);
+    }
+}
+
+#[cfg(test)]
+mod sampler_tests {
+    use crate::datatypes::Vec3;
+    use crate::samplers::{SamplePoint, SamplerFactory, Samplers};
+    use crate::utils::{MathUtil, SamplerUtil};
+
+    fn point(index: u32, batch: (u32, u32), dimension: u32) -> SamplePoint {
+        SamplePoint { seed: 0, pixel: (3, 5), index, batch, dimension }
+    }
+
+        // Whether `values` has exactly one value in each of `values.len()` equal intervals.
+    fn one_per_stratum(values: &[f64]) -> bool {
+        let mut strata: Vec<usize> = values.iter().map(|v| (v * values.len() as f64) as usize).collect();
+        strata.sort();
+        strata.iter().enumerate().all(|(index, stratum)| index == *stratum)
+    }
+
+    #[test]
+    fn test_samplers_stratify_each_dimension() {
+        let samplers = [SamplerFactory::make_stratified(), SamplerFactory::make_sobol()];
+        for sampler in samplers {
+            for dimension in 0..8 {
+                let values: Vec<f64> = (0..16).map(|index| SamplerUtil::sample(&sampler, &point(index, (index, 16), dimension))).collect();
+                assert!(one_per_stratum(&values), "{sampler:?} dimension {dimension}");
+            }
+        }
+            // The first two Sobol dimensions also stratify the unit square.
+        let sobol = SamplerFactory::make_sobol();
+        let cells: Vec<f64> = (0..16).map(|index| {
+            let x = SamplerUtil::sample(&sobol, &point(index, (index, 16), 0));
+            let y = SamplerUtil::sample(&sobol, &point(index, (index, 16), 1));
+            ((y * 4.0).floor() * 4.0 + (x * 4.0).floor()) / 16.0
+        }).collect();
+        assert!(one_per_stratum(&cells));
+    }
+
+    #[test]
+    fn test_halton_and_blue_noise_spread_values() {
+        let halton = SamplerFactory::make_halton();
+        let values: Vec<f64> = (0..9).map(|index| SamplerUtil::sample(&halton, &point(index, (index, 9), 1))).collect();
+        assert!(one_per_stratum(&values));
+
+            // One sample of every pixel of a mask tile covers [0, 1) evenly.
+        let blue_noise = SamplerFactory::make_blue_noise();
+        let values: Vec<f64> = (0..32 * 32).map(|pixel| {
+            SamplerUtil::sample(&blue_noise, &SamplePoint { seed: 0, pixel: (pixel % 32, pixel / 32), index: 0, batch: (0, 1), dimension: 2 })
+        }).collect();
+        assert!(one_per_stratum(&values));
+    }
+
+    #[test]
+    fn test_rand_draws_sample_dimensions() {
+        let sampler: Samplers = SamplerFactory::make_sobol();
+        SamplerUtil::start_sample(&sampler, 0, (3, 5), 7, (0, 1));
+        let drawn = [MathUtil::rand(), MathUtil::rand(), MathUtil::rand()];
+        SamplerUtil::end_sample();
+        for (dimension, value) in drawn.iter().enumerate() {
+            assert_eq!(*value, SamplerUtil::sample(&sampler, &point(7, (0, 1), dimension as u32)));
+        }
+        assert_eq!(SamplerUtil::next(), None);
+    }
+
+    #[test]
+    fn test_random_unit_takes_two_dimensions() {
+        let sampler: Samplers = SamplerFactory::make_sobol();
+        for index in 0..64 {
+            SamplerUtil::start_sample(&sampler, 0, (3, 5), index, (0, 1));
+            let direction = Vec3::random_unit();
+            let next = MathUtil::rand();
+            SamplerUtil::end_sample();
+            assert!((direction.len() - 1.0).abs() < 1e-9);
+            assert_eq!(next, SamplerUtil::sample(&sampler, &point(index, (0, 1), 2)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod seed_tests {
+    use crate::camera::{Camera, CameraBuilder};
+    use crate::datatypes::{Color3, Point3, Vec3};
+    use crate::integrators::{IntegratorFactory, Integrators};
+    use crate::lights::LightFactory;
+    use crate::materials::MaterialFactory;
+    use crate::samplers::{SamplerFactory, Samplers};
+    use crate::shapes::{HittableList, ShapeFactory};
+
+    fn world() -> HittableList {
+        let mut world = HittableList::new();
+        let gray = MaterialFactory::make_lambertian(Color3::new(0.5, 0.5, 0.5));
+        world.add(ShapeFactory::make_quad(Point3::new(-50.0, -0.5, -50.0), Vec3::new(0.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 0.0), gray));
+        world.add(ShapeFactory::make_sphere(0.5, 0.0, 0.0, -1.5, MaterialFactory::make_dielectric(1.5)));
+        world.add(ShapeFactory::make_sphere(0.2, -1.0, 0.5, -2.0, MaterialFactory::make_emitter(Color3::one(), 4.0)));
+        world.add_light(LightFactory::make_point(Point3::new(1.0, 2.0, -1.0), Color3::one(), 4.0));
+        world
+    }
+
+    fn camera(integrator: Integrators, sampler: Samplers, seed: u64) -> Camera {
+        CameraBuilder::new().image_width(12).aspect_ratio(1.0).samples_per_pixel(4).max_bounces(6)
+            .integrator(integrator).sampler(sampler).seed(seed).build()
+    }
+
+        // A fresh world per render, so its light tree is built anew every time.
+    fn render_on(threads: usize, camera: &Camera) -> Vec<Color3> {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        pool.install(|| camera.render(&world()))
+    }
+
+    #[test]
+    fn test_same_seed_same_image_on_any_thread_count() {
+        let cameras = [
+            camera(IntegratorFactory::make_path(), SamplerFactory::make_independent(), 3),
+            camera(IntegratorFactory::make_bidirectional(), SamplerFactory::make_sobol(), 3),
+            camera(IntegratorFactory::make_photon_mapping(2000, 0.1), SamplerFactory::make_stratified(), 3),
+        ];
+        for camera in cameras {
+            assert!(render_on(1, &camera) == render_on(4, &camera));
+        }
+    }
+
+    #[test]
+    fn test_light_tree_build_does_not_shift_samples() {
+        let camera = camera(IntegratorFactory::make_path(), SamplerFactory::make_sobol(), 3);
+        let world = world();
+        world.light_tree();
+        assert!(render_on(2, &camera) == camera.render(&world));
+    }
+
+    #[test]
+    fn test_seed_changes_image() {
+        let first = render_on(2, &camera(IntegratorFactory::make_path(), SamplerFactory::make_halton(), 1));
+        let second = render_on(2, &camera(IntegratorFactory::make_path(), SamplerFactory::make_halton(), 2));
+        assert!(first != second);
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use crate::camera::CameraBuilder;
+    use crate::datatypes::Color3;
+    use crate::filters::{FilterFactory, FilterSampler};
+    use crate::shapes::HittableList;
+    use crate::sky::{Skies, SkyGradient};
+    use crate::utils::{FilterUtil, MathUtil};
+
+    #[test]
+    fn test_filter_weights_average_to_one() {
+        MathUtil::seed(5);
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter = FilterFactory::from_name(name).unwrap();
+            let sampler = FilterSampler::new(filter);
+            let radius = FilterUtil::radius(&filter);
+            let samples = 100_000;
+            let mut weights = 0.0;
+            for _ in 0..samples {
+                let (x, y, weight) = sampler.sample();
+                assert!(x.abs() <= radius && y.abs() <= radius);
+                weights += weight;
+            }
+            assert!((weights / samples as f64 - 1.0).abs() < 0.02, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_negative_lobes_give_negative_weights() {
+        MathUtil::seed(6);
+        let sampler = FilterSampler::new(FilterFactory::make_box(0.5));
+        assert!((0..1000).all(|_| (sampler.sample().2 - 1.0).abs() < 1e-9));
+        let sampler = FilterSampler::new(FilterFactory::make_lanczos(3.0));
+        assert!((0..1000).any(|_| sampler.sample().2 < 0.0));
+    }
+
+    #[test]
+    fn test_degenerate_filters_act_as_delta() {
+        for filter in [FilterFactory::make_box(0.0), FilterFactory::make_tent(-1.0), FilterFactory::make_gaussian(1.5, 0.0)] {
+            let sampler = FilterSampler::new(filter);
+            assert!((0..100).all(|_| sampler.sample() == (0.0, 0.0, 1.0)), "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn test_filtered_flat_image_stays_flat() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::one(), Color3::one())));
+        let camera = CameraBuilder::new().image_width(4).aspect_ratio(1.0).samples_per_pixel(256)
+            .filter(FilterFactory::make_gaussian(1.5, 0.5)).build();
+        assert!(camera.render(&world).iter().all(|c| (c.x - 1.0).abs() < 0.02));
+    }
+}
+
+#[cfg(test)]
+mod tile_tests {
+    use std::sync::Mutex;
+
+    use crate::camera::CameraBuilder;
+    use crate::datatypes::Color3;
+    use crate::shapes::HittableList;
+    use crate::sky::{Skies, SkyGradient};
+    use crate::tiles::TileOrders;
+
+    #[test]
+    fn test_tile_orders_cover_image_once() {
+        for order in [TileOrders::Scanline, TileOrders::Spiral, TileOrders::Hilbert] {
+            let tiles = order.tiles(37, 21, 8);
+            assert_eq!(tiles.len(), 5 * 3);
+            let mut covered = vec![0; 37 * 21];
+            for tile in &tiles {
+                tile.pixels().for_each(|(i, j)| covered[(j * 37 + i) as usize] += 1);
+            }
+            assert!(covered.iter().all(|c| *c == 1), "{order:?}");
+        }
+            // Spirals start in the middle, Hilbert steps only go to neighbouring tiles.
+        let spiral = TileOrders::Spiral.tiles(40, 40, 8);
+        assert_eq!((spiral[0].x, spiral[0].y), (16, 16));
+        let hilbert = TileOrders::Hilbert.tiles(64, 64, 8);
+        assert!(hilbert.windows(2).all(|pair| pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y) == 8));
+    }
+
+    #[test]
+    fn test_render_tiles_reports_every_tile() {
+        let mut world = HittableList::new();
+        world.set_sky(Skies::Gradient(SkyGradient::new(Color3::new(0.2, 0.2, 0.2), Color3::one())));
+        let camera = CameraBuilder::new().image_width(20).aspect_ratio(2.0).samples_per_pixel(3)
+            .tiles(8, TileOrders::Hilbert).build();
+        let finished = Mutex::new(Vec::new());
+        let (pixels, stats) = camera.render_tiles(&world, |stats, pixels| {
+            assert_eq!(pixels.len(), (stats.tile.width * stats.tile.height) as usize);
+            finished.lock().unwrap().push(stats.tile.index);
+        });
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort();
+        assert_eq!(finished, (0..6).collect::<Vec<usize>>());
+        assert_eq!(stats.iter().map(|stats| stats.samples).sum::<u64>(), 20 * 10 * 3);
+        assert!(pixels == camera.render(&world));
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use image::ImageResult;
+use image::Rgba32FImage;
+
+use crate::datatypes::Vec3;
+use crate::datatypes::Point3;
+use crate::datatypes::Color3;
+use crate::datatypes::HitRecord;
+use crate::datatypes::Ray;
+use crate::utils::TexUtil;
+use crate::utils::MathUtil;
+
+
+#[derive(Clone)]
+pub enum Textures {
+    Solid(TexSolid),
+    Image(TexImage)
+}
+
+
+pub struct TextureFactory;
+#[allow(unused)]
+impl TextureFactory {
+    pub fn make_solid(color: Color3) -> Textures {
+        Textures::Solid(TexSolid::new(color))
+    }
+    pub fn make_image(path: &str) -> ImageResult<Textures> {
+        Ok(Textures::Image(TexImage::load(path)?))
+    }
+    pub fn make_normal_map(texture: Textures) -> NormalMaps {
+        NormalMaps::Normal(NormalMap::new(texture))
+    }
+    pub fn make_bump_map(texture: Textures, scale: f64) -> NormalMaps {
+        NormalMaps::Bump(BumpMap::new(texture, scale))
+    }
+    pub fn make_cutout(texture: Textures, threshold: f64) -> AlphaMask {
+        AlphaMask::new(texture, threshold)
+    }
+    pub fn make_stochastic_cutout(texture: Textures) -> AlphaMask {
+        AlphaMask::stochastic(texture)
+    }
+}
+
+
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3;
+    fn alpha(&self, _u: f64, _v: f64, _p: &Point3) -> f64 {
+        1.0
+    }
+}
+
+#[derive(Clone)]
+pub struct TexSolid {
+    pub color: Color3
+}
+impl TexSolid {
+    pub fn new(color: Color3) -> Self {
+        TexSolid { color }
+    }
+}
+impl Texture for TexSolid {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color3 {
+        self.color.clone()
+    }
+}
+
+
+/// Bilinearly filtered image, wrapped in both directions. Values are returned as
+/// stored, without any color space decoding, so it can hold data maps too.
+#[derive(Clone)]
+pub struct TexImage {
+    pub image: Arc<Rgba32FImage>
+}
+impl TexImage {
+    pub fn new(image: Rgba32FImage) -> Self {
+        TexImage { image: Arc::new(image) }
+    }
+    pub fn load(path: &str) -> ImageResult<Self> {
+        Ok(Self::new(image::open(path)?.to_rgba32f()))
+    }
+    fn sample(&self, u: f64, v: f64) -> [f64; 4] {
+        let (width, height) = self.image.dimensions();
+        if width == 0 || height == 0 {
+            return [0.0, 1.0, 1.0, 1.0];
+        }
+            // Flip v so that v = 1 is the top row of the image.
+        let x = u.rem_euclid(1.0) * width as f64 - 0.5;
+        let y = (1.0 - v.rem_euclid(1.0)) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |i: f64, j: f64| {
+            let i = (i as i64).rem_euclid(width as i64) as u32;
+            let j = (j as i64).rem_euclid(height as i64) as u32;
+            self.image.get_pixel(i, j).0
+        };
+        let (c00, c10) = (texel(x0, y0), texel(x0 + 1.0, y0));
+        let (c01, c11) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+
+        let mut out = [0.0; 4];
+        for (k, value) in out.iter_mut().enumerate() {
+            let top = (1.0 - fx) * c00[k] as f64 + fx * c10[k] as f64;
+            let bottom = (1.0 - fx) * c01[k] as f64 + fx * c11[k] as f64;
+            *value = (1.0 - fy) * top + fy * bottom;
+        }
+        out
+    }
+}
+impl Texture for TexImage {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color3 {
+        let [r, g, b, _] = self.sample(u, v);
+        Color3::new(r, g, b)
+    }
+    fn alpha(&self, u: f64, v: f64, _p: &Point3) -> f64 {
+        self.sample(u, v)[3]
+    }
+}
+
+
+/// Perturbs the shading normal of a hit. The geometric normal is left untouched.
+#[derive(Clone)]
+pub enum NormalMaps {
+    Normal(NormalMap),
+    Bump(BumpMap)
+}
+impl NormalMaps {
+    /// Sets the shading normal of `hit_rec`, found by `ray`.
+    pub fn apply(&self, ray: &Ray, hit_rec: &mut HitRecord) {
+        let shading_normal = match self {
+            NormalMaps::Normal(map) => map.perturb(hit_rec),
+            NormalMaps::Bump(map) => map.perturb(hit_rec),
+        };
+        hit_rec.set_shading_normal(&shading_normal, &-ray.direction());
+    }
+}
+
+/// Tangent-space normal map: RGB in [0, 1] encodes a normal in [-1, 1] along
+/// (tangent, bitangent, normal).
+#[derive(Clone)]
+pub struct NormalMap {
+    pub texture: Textures
+}
+impl NormalMap {
+    pub fn new(texture: Textures) -> Self {
+        NormalMap { texture }
+    }
+    fn perturb(&self, hit_rec: &HitRecord) -> Vec3 {
+        let c = TexUtil::value(&self.texture, hit_rec.u, hit_rec.v, &hit_rec.p);
+        hit_rec.frame().local(2.0 * c.x - 1.0, 2.0 * c.y - 1.0, 2.0 * c.z - 1.0).unit()
+    }
+}
+
+/// Height map; the red channel is the height, scaled by `scale`.
+#[derive(Clone)]
+pub struct BumpMap {
+    pub texture: Textures,
+    pub scale: f64
+}
+impl BumpMap {
+    const DELTA: f64 = 1e-3;
+
+    pub fn new(texture: Textures, scale: f64) -> Self {
+        BumpMap { texture, scale }
+    }
+    fn perturb(&self, hit_rec: &HitRecord) -> Vec3 {
+        let height = |u: f64, v: f64| TexUtil::value(&self.texture, u, v, &hit_rec.p).x;
+        let h = height(hit_rec.u, hit_rec.v);
+        let dh_du = (height(hit_rec.u + Self::DELTA, hit_rec.v) - h) / Self::DELTA;
+        let dh_dv = (height(hit_rec.u, hit_rec.v + Self::DELTA) - h) / Self::DELTA;
+        let frame = hit_rec.frame();
+        frame.local(-self.scale * dh_du, -self.scale * dh_dv, 1.0).unit()
+    }
+}
+
+
+/// Opacity mask read from a texture's alpha channel. Cut out points are skipped by
+/// the shape's intersection test, so the ray carries on as if nothing was there.
+/// With `stochastic` set, partial alpha is treated as a probability of being hit.
+#[derive(Clone)]
+pub struct AlphaMask {
+    pub texture: Textures,
+    pub threshold: f64,
+    pub stochastic: bool
+}
+impl AlphaMask {
+    pub fn new(texture: Textures, threshold: f64) -> Self {
+        AlphaMask { texture, threshold, stochastic: false }
+    }
+    pub fn stochastic(texture: Textures) -> Self {
+        AlphaMask { texture, threshold: 0.0, stochastic: true }
+    }
+    pub fn is_cut_out(&self, u: f64, v: f64, p: &Point3) -> bool {
+        let alpha = TexUtil::alpha(&self.texture, u, v, p);
+        if self.stochastic {
+            alpha < 1.0 && MathUtil::rand() >= alpha
+        }
+        else {
+            alpha < self.threshold
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+
+/// Order in which `Camera` hands tiles out to its threads.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TileOrders {
+    Scanline,       // Rows from the top, left to right
+    Spiral,         // Outwards from the center tile, where the subject usually is
+    Hilbert         // Along a Hilbert curve, so consecutive tiles are always neighbours
+}
+#[allow(unused)]
+impl TileOrders {
+    /// Order picked by name on the command line.
+    pub fn from_name(name: &str) -> Option<TileOrders> {
+        match name {
+            "scanline" => Some(TileOrders::Scanline),
+            "spiral" => Some(TileOrders::Spiral),
+            "hilbert" => Some(TileOrders::Hilbert),
+            _ => None,
+        }
+    }
+    /// Tiles of at most `size` by `size` pixels covering a `width` by `height` image,
+    /// in this order.
+    pub fn tiles(&self, width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = u32::max(size, 1);
+        let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+        let mut cells: Vec<(u32, u32)> = (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row))).collect();
+        match self {
+            TileOrders::Scanline => {},
+            TileOrders::Spiral => cells = Self::spiral(columns, rows),
+            TileOrders::Hilbert => {
+                let side = u32::max(columns, rows).next_power_of_two();
+                cells.sort_by_key(|(column, row)| Self::hilbert_index(side, *column, *row));
+            },
+        }
+        cells.iter().enumerate().map(|(index, (column, row))| {
+            let (x, y) = (column * size, row * size);
+            Tile { index, x, y, width: u32::min(size, width - x), height: u32::min(size, height - y) }
+        }).collect()
+    }
+        // Walks a square spiral around the center cell, keeping the cells inside the grid.
+    fn spiral(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+        let count = (columns * rows) as usize;
+        let mut cells = Vec::with_capacity(count);
+        let (mut x, mut y) = (((columns - 1) / 2) as i64, ((rows - 1) / 2) as i64);
+        let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+        let (mut direction, mut run) = (0, 1);
+        cells.push((x as u32, y as u32));
+        while cells.len() < count {
+            for _ in 0..2 {
+                let (dx, dy) = directions[direction % 4];
+                for _ in 0..run {
+                    (x, y) = (x + dx, y + dy);
+                    if x >= 0 && y >= 0 && x < columns as i64 && y < rows as i64 {
+                        cells.push((x as u32, y as u32));
+                    }
+                }
+                direction += 1;
+            }
+            run += 1;
+        }
+        cells
+    }
+        // Distance of cell (x, y) along the Hilbert curve filling a `side` by `side` grid.
+    fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+        let mut index = 0;
+        let mut s = side / 2;
+        while s > 0 {
+            let (rx, ry) = ((x & s > 0) as u32, (y & s > 0) as u32);
+            index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+            if ry == 0 {
+                if rx == 1 {
+                    (x, y) = (side - 1 - x, side - 1 - y);
+                }
+                (x, y) = (y, x);
+            }
+            s /= 2;
+        }
+        index
+    }
+}
+
+
+/// Rectangle of pixels rendered by one thread at a time.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile {
+    pub index: usize,       // Position in the tile order
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+impl Tile {
+    /// Pixels of the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y..self.y + self.height).flat_map(move |j| (self.x..self.x + self.width).map(move |i| (i, j)))
+    }
+}
+
+
+/// What one pass over a tile cost.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TileStats {
+    pub tile: Tile,
+    pub pass: u32,                  // Pass the tile was rendered in, from 1
+    pub samples: u64,               // Camera samples taken in the tile
+    pub elapsed: Duration,
+    pub intersection_tests: u64     // Primitive tests, see `HitUtil::intersection_tests`
+}
@@ -0,0 +1,107 @@
+use crate::datatypes::Color3;
+use crate::utils::ColorUtil;
+
+
+/// Curves that squeeze scene radiance into the [0, 1] display range.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ToneMappers {
+    Clamp,                              // Clips at 1
+    Reinhard,                           // L / (1 + L) on luminance
+    ReinhardExtended { white: f64 },    // Reinhard with luminance `white` mapped to 1
+    AcesFilmic,                         // Narkowicz 2015 fit of the ACES reference and output transforms
+    Hable,                              // Uncharted 2 filmic curve
+    AgX                                 // Sobotka's AgX base look, with the polynomial fit of its sigmoid
+}
+#[allow(unused)]
+impl ToneMappers {
+    /// Operator picked by name on the command line.
+    pub fn from_name(name: &str) -> Option<ToneMappers> {
+        match name {
+            "clamp" => Some(ToneMappers::Clamp),
+            "reinhard" => Some(ToneMappers::Reinhard),
+            "reinhard-extended" => Some(ToneMappers::ReinhardExtended { white: 4.0 }),
+            "aces" => Some(ToneMappers::AcesFilmic),
+            "hable" => Some(ToneMappers::Hable),
+            "agx" => Some(ToneMappers::AgX),
+            _ => None,
+        }
+    }
+    /// Maps linear `color` to linear display values in [0, 1].
+    pub fn apply(&self, color: &Color3) -> Color3 {
+        let mapped = match self {
+            ToneMappers::Clamp => color.clone(),
+            ToneMappers::Reinhard => Self::scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMappers::ReinhardExtended { white } => {
+                Self::scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            },
+            ToneMappers::AcesFilmic => Self::per_channel(color, |x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)),
+            ToneMappers::Hable => {
+                    // Exposure bias of 2 and linear white point of 11.2, as in the original talk.
+                let white_scale = 1.0 / Self::hable(11.2);
+                Self::per_channel(color, |x| Self::hable(2.0 * x) * white_scale)
+            },
+            ToneMappers::AgX => Self::agx(color),
+        };
+        Self::per_channel(&mapped, |x| x.clamp(0.0, 1.0))
+    }
+    fn per_channel(color: &Color3, curve: impl Fn(f64) -> f64) -> Color3 {
+        Color3::new(curve(color.x), curve(color.y), curve(color.z))
+    }
+        // Keeps the hue by scaling all channels with the mapped luminance.
+    fn scale_luminance(color: &Color3, curve: impl Fn(f64) -> f64) -> Color3 {
+        let luminance = ColorUtil::luminance(color);
+        if luminance <= 0.0 {
+            return Color3::zero();
+        }
+        color * (curve(luminance) / luminance)
+    }
+    fn hable(x: f64) -> f64 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    }
+        // Inset into the AgX working space, log encode between -12.47 and +4.03 stops
+        // around middle gray, apply the sigmoid, then outset and undo the 2.2 display gamma.
+    fn agx(color: &Color3) -> Color3 {
+        const MIN_EV: f64 = -12.47393;
+        const MAX_EV: f64 = 4.026069;
+        let inset = Color3::new(
+            0.842479062253094 * color.x + 0.0784335999999992 * color.y + 0.0792237451477643 * color.z,
+            0.0423282422610123 * color.x + 0.878468636469772 * color.y + 0.0791661274605434 * color.z,
+            0.0423756549057051 * color.x + 0.0784336 * color.y + 0.879142973793104 * color.z,
+        );
+        let curved = Self::per_channel(&inset, |x| {
+            let x = ((f64::max(x, 1e-10).log2().clamp(MIN_EV, MAX_EV)) - MIN_EV) / (MAX_EV - MIN_EV);
+            let (x2, x4) = (x * x, x * x * x * x);
+            15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+        });
+        let outset = Color3::new(
+            1.19687900512017 * curved.x - 0.0980208811401368 * curved.y - 0.0990297440797205 * curved.z,
+            -0.0528968517574562 * curved.x + 1.15190312990417 * curved.y - 0.0989611768448433 * curved.z,
+            -0.0529716355144438 * curved.x - 0.0980434501171241 * curved.y + 1.15107367264116 * curved.z,
+        );
+        Self::per_channel(&outset, |x| f64::max(x, 0.0).powf(2.2))
+    }
+}
+
+
+/// How linear renders turn into display pixels: scaled by `exposure` stops, tone
+/// mapped, then sRGB encoded.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Display {
+    pub exposure: f64,
+    pub tone_mapper: ToneMappers
+}
+#[allow(unused)]
+impl Display {
+    pub const DEFAULT: Display = Display { exposure: 0.0, tone_mapper: ToneMappers::Clamp };
+
+    pub fn new(exposure: f64, tone_mapper: ToneMappers) -> Self {
+        Display { exposure, tone_mapper }
+    }
+    /// sRGB encoded display value of linear `color`, in [0, 1].
+    pub fn encode(&self, color: &Color3) -> Color3 {
+        let exposed = color * f64::powf(2.0, self.exposure);
+        let mapped = self.tone_mapper.apply(&exposed);
+        ToneMappers::per_channel(&mapped, ColorUtil::linear_to_srgb)
+    }
+}
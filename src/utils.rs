@@ -1,16 +1,57 @@
 
+use std::cell::Cell;
 use std::f64::consts::PI;
 
 use image::RgbImage;
+use image::Rgb32FImage;
+use image::ImageFormat;
+use image::ImageResult;
+use exr::prelude::f16;
+use exr::prelude::SmallVec;
+use exr::prelude::Image;
+use exr::prelude::Layer;
+use exr::prelude::Encoding;
+use exr::prelude::AnyChannel;
+use exr::prelude::AnyChannels;
+use exr::prelude::FlatSamples;
+use exr::prelude::IntegerBounds;
+use exr::prelude::LayerAttributes;
+use exr::prelude::ImageAttributes;
+use exr::prelude::WritableImage;
 
 use crate::datatypes::Ray;
+use crate::datatypes::Aabb;
+use crate::datatypes::Vec3;
+use crate::datatypes::Point3;
 use crate::datatypes::Color3;
 use crate::datatypes::Hittable;
 use crate::datatypes::Interval;
 use crate::datatypes::HitRecord;
+use crate::datatypes::Sampleable;
+use crate::datatypes::SurfaceSample;
 use crate::shapes::Hittables;
 use crate::materials::Material;
 use crate::materials::Materials;
+use crate::lights::Light;
+use crate::lights::Lights;
+use crate::lights::LightSample;
+use crate::lights::LightPosition;
+use crate::aovs::LightSplit;
+use crate::camera::Camera;
+use crate::filters::Filter;
+use crate::filters::Filters;
+use crate::samplers::Sampler;
+use crate::samplers::Samplers;
+use crate::samplers::SamplePoint;
+use crate::bdpt::SplatBuffer;
+use crate::integrators::Integrator;
+use crate::integrators::Integrators;
+use crate::shapes::HittableList;
+use crate::sky::Sky;
+use crate::sky::Skies;
+use crate::textures::Texture;
+use crate::tonemap::Display;
+use crate::textures::Textures;
 
 
 pub struct ColorUtil;
@@ -25,27 +66,69 @@ impl ColorUtil {
         format!("{ir} {ig} {ib}\n")
     }
 
+    pub fn luminance(color: &Color3) -> f64 {
+        0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+    }
+    /// Flat color for an id, 0 is black. Ids are spread over the hue circle by the
+    /// golden ratio so neighbours differ.
+    pub fn id_color(id: usize) -> Color3 {
+        if id == 0 {
+            return Color3::zero();
+        }
+        let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+        let x = 1.0 - (hue % 2.0 - 1.0).abs();
+        match hue as u32 {
+            0 => Color3::new(1.0, x, 0.0),
+            1 => Color3::new(x, 1.0, 0.0),
+            2 => Color3::new(0.0, 1.0, x),
+            3 => Color3::new(0.0, x, 1.0),
+            4 => Color3::new(x, 0.0, 1.0),
+            _ => Color3::new(1.0, 0.0, x),
+        }
+    }
+    /// sRGB transfer function (IEC 61966-2-1) for a linear value in [0, 1].
+    pub fn linear_to_srgb(linear: f64) -> f64 {
+        if linear <= 0.0031308 {
+            12.92 * f64::max(linear, 0.0)
+        }
+        else {
+            1.055 * linear.powf(1.0 / 2.4) - 0.055
+        }
+    }
     pub fn get_pixel(color: &Color3) -> image::Rgb<u8> {
-        let gr = MathUtil::linear_to_gamma(color.x);
-        let gg = MathUtil::linear_to_gamma(color.y);
-        let gb = MathUtil::linear_to_gamma(color.z);
-        let ir = (256.0 * ColorUtil::INTENSITY.clamp(gr)) as u8;
-        let ig = (256.0 * ColorUtil::INTENSITY.clamp(gg)) as u8;
-        let ib = (256.0 * ColorUtil::INTENSITY.clamp(gb)) as u8;
+        Self::get_display_pixel(color, &Display::DEFAULT)
+    }
+    pub fn get_display_pixel(color: &Color3, display: &Display) -> image::Rgb<u8> {
+        let encoded = display.encode(color);
+        let ir = (256.0 * ColorUtil::INTENSITY.clamp(encoded.x)) as u8;
+        let ig = (256.0 * ColorUtil::INTENSITY.clamp(encoded.y)) as u8;
+        let ib = (256.0 * ColorUtil::INTENSITY.clamp(encoded.z)) as u8;
         image::Rgb([ir, ig, ib])
     }
 }
 
 
+/// Sample type of OpenEXR channels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[allow(unused)]
+pub enum ExrPrecisions {
+    Half,
+    Float
+}
+
 pub struct ImageUtil;
 #[allow(unused)]
 impl ImageUtil {
     pub fn get_rgb_image(pixels: Vec<Color3>, width: u32, height: u32) -> RgbImage {
+        Self::get_display_image(&pixels, width, height, &Display::DEFAULT)
+    }
+
+    pub fn get_display_image(pixels: &[Color3], width: u32, height: u32, display: &Display) -> RgbImage {
         let mut image: RgbImage = RgbImage::new(width, height);
         for j in 0..height {
             for i in 0..width {
                 let pixel = &pixels[(j * width + i) as usize];
-                image.put_pixel(i, j, ColorUtil::get_pixel(pixel));
+                image.put_pixel(i, j, ColorUtil::get_display_pixel(pixel, display));
             }
         }
 
@@ -68,17 +151,111 @@ impl ImageUtil {
         content
     }
 
+    /// Linear floating point image, for Radiance `.hdr` files.
+    pub fn get_rgb32f_image(pixels: &[Color3], width: u32, height: u32) -> Rgb32FImage {
+        Rgb32FImage::from_fn(width, height, |i, j| {
+            let pixel = &pixels[(j * width + i) as usize];
+            image::Rgb([pixel.x as f32, pixel.y as f32, pixel.z as f32])
+        })
+    }
+
+    pub fn save_hdr(path: &str, pixels: &[Color3], width: u32, height: u32) -> ImageResult<()> {
+        Self::get_rgb32f_image(pixels, width, height).save_with_format(path, ImageFormat::Hdr)
+    }
+
+    /// Writes linear `layers`, each a name and its pixels, as parts of one OpenEXR file.
+    pub fn save_exr(path: &str, layers: &[(&str, &[Color3])], width: u32, height: u32,
+                    precision: ExrPrecisions) -> exr::error::UnitResult {
+        let size = (width as usize, height as usize);
+        let layers: Vec<Layer<AnyChannels<FlatSamples>>> = layers.iter().map(|(name, pixels)| {
+            let channel = |channel: &str, value: fn(&Color3) -> f64| {
+                let samples = match precision {
+                    ExrPrecisions::Half => FlatSamples::F16(pixels.iter().map(|c| f16::from_f64(value(c))).collect()),
+                    ExrPrecisions::Float => FlatSamples::F32(pixels.iter().map(|c| value(c) as f32).collect()),
+                };
+                AnyChannel::new(channel, samples)
+            };
+            let channels = SmallVec::from_vec(vec![channel("R", |c| c.x), channel("G", |c| c.y), channel("B", |c| c.z)]);
+            Layer::new(size, LayerAttributes::named(*name), Encoding::FAST_LOSSLESS, AnyChannels::sort(channels))
+        }).collect();
+
+        let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+        Image::from_layers(attributes, layers).write().to_file(path)
+    }
 }
 
 
+thread_local! {
+    static INTERSECTION_TESTS: Cell<u64> = const { Cell::new(0) };
+}
+
 pub struct HitUtil;
 impl HitUtil {
+    /// Primitive intersection tests run on this thread so far.
+    pub fn intersection_tests() -> u64 {
+        INTERSECTION_TESTS.with(|tests| tests.get())
+    }
+    /// Adds `count` tests to `intersection_tests`. Lists report theirs once per
+    /// ray rather than per primitive, which keeps the counter off the hot loop.
+    pub fn count_intersection_tests(count: u64) {
+        INTERSECTION_TESTS.with(|tests| tests.set(tests.get() + count));
+    }
     pub fn hit(hittable: &Hittables, ray: &crate::datatypes::Ray, t_i: &Interval) -> Option<HitRecord> {
         match hittable {
             Hittables::Sphere(sphere) => sphere.hit(ray, t_i),
+            Hittables::Quad(quad) => quad.hit(ray, t_i),
+            Hittables::Triangle(triangle) => triangle.hit(ray, t_i),
             Hittables::HittableList(list) => list.hit(ray, t_i),
         }
     }
+    pub fn sample(hittable: &Hittables, origin: &Point3) -> Option<SurfaceSample> {
+        match hittable {
+            Hittables::Sphere(sphere) => sphere.sample(origin),
+            Hittables::Quad(quad) => quad.sample(origin),
+            Hittables::Triangle(triangle) => triangle.sample(origin),
+            Hittables::HittableList(_) => None,
+        }
+    }
+    pub fn pdf(hittable: &Hittables, origin: &Point3, hit_rec: &HitRecord) -> f64 {
+        match hittable {
+            Hittables::Sphere(sphere) => sphere.pdf(origin, hit_rec),
+            Hittables::Quad(quad) => quad.pdf(origin, hit_rec),
+            Hittables::Triangle(triangle) => triangle.pdf(origin, hit_rec),
+            Hittables::HittableList(_) => 0.0,
+        }
+    }
+    pub fn sample_area(hittable: &Hittables) -> Option<HitRecord> {
+        match hittable {
+            Hittables::Sphere(sphere) => Some(sphere.sample_area()),
+            Hittables::Quad(quad) => Some(quad.sample_area()),
+            Hittables::Triangle(triangle) => Some(triangle.sample_area()),
+            Hittables::HittableList(_) => None,
+        }
+    }
+    pub fn area(hittable: &Hittables) -> f64 {
+        match hittable {
+            Hittables::Sphere(sphere) => sphere.area(),
+            Hittables::Quad(quad) => quad.area(),
+            Hittables::Triangle(triangle) => triangle.area(),
+            Hittables::HittableList(_) => 0.0,
+        }
+    }
+    pub fn bounds(hittable: &Hittables) -> Aabb {
+        match hittable {
+            Hittables::Sphere(sphere) => sphere.bounds(),
+            Hittables::Quad(quad) => quad.bounds(),
+            Hittables::Triangle(triangle) => triangle.bounds(),
+            Hittables::HittableList(_) => Aabb::empty(),
+        }
+    }
+    pub fn material(hittable: &Hittables) -> Option<&Materials> {
+        match hittable {
+            Hittables::Sphere(sphere) => Some(&sphere.material),
+            Hittables::Quad(quad) => Some(&quad.material),
+            Hittables::Triangle(triangle) => Some(&triangle.material),
+            Hittables::HittableList(_) => None,
+        }
+    }
 }
 
 
@@ -89,9 +266,337 @@ impl MatUtil {
             Materials::DifuseLamb(mat) => mat.scatter(ray, hit_rec),
             Materials::Metal(mat) => mat.scatter(ray, hit_rec),
             Materials::Dielectric(mat) => mat.scatter(ray, hit_rec),
+            Materials::Subsurface(mat) => mat.scatter(ray, hit_rec),
+            Materials::Blend(mat) => mat.scatter(ray, hit_rec),
+            Materials::Principled(mat) => mat.scatter(ray, hit_rec),
             Materials::Emitter(mat) => mat.scatter(ray, hit_rec),
         }
     }
+    pub fn eval(material: &Materials, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> Color3 {
+        match material {
+            Materials::DifuseLamb(mat) => mat.eval(ray, hit_rec, direction),
+            Materials::Metal(mat) => mat.eval(ray, hit_rec, direction),
+            Materials::Dielectric(mat) => mat.eval(ray, hit_rec, direction),
+            Materials::Subsurface(mat) => mat.eval(ray, hit_rec, direction),
+            Materials::Blend(mat) => mat.eval(ray, hit_rec, direction),
+            Materials::Principled(mat) => mat.eval(ray, hit_rec, direction),
+            Materials::Emitter(mat) => mat.eval(ray, hit_rec, direction),
+        }
+    }
+    pub fn pdf(material: &Materials, ray: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> f64 {
+        match material {
+            Materials::DifuseLamb(mat) => mat.pdf(ray, hit_rec, direction),
+            Materials::Metal(mat) => mat.pdf(ray, hit_rec, direction),
+            Materials::Dielectric(mat) => mat.pdf(ray, hit_rec, direction),
+            Materials::Subsurface(mat) => mat.pdf(ray, hit_rec, direction),
+            Materials::Blend(mat) => mat.pdf(ray, hit_rec, direction),
+            Materials::Principled(mat) => mat.pdf(ray, hit_rec, direction),
+            Materials::Emitter(mat) => mat.pdf(ray, hit_rec, direction),
+        }
+    }
+    pub fn emitted(material: &Materials, hit_rec: &HitRecord) -> Color3 {
+        match material {
+            Materials::DifuseLamb(mat) => mat.emitted(hit_rec),
+            Materials::Metal(mat) => mat.emitted(hit_rec),
+            Materials::Dielectric(mat) => mat.emitted(hit_rec),
+            Materials::Subsurface(mat) => mat.emitted(hit_rec),
+            Materials::Blend(mat) => mat.emitted(hit_rec),
+            Materials::Principled(mat) => mat.emitted(hit_rec),
+            Materials::Emitter(mat) => mat.emitted(hit_rec),
+        }
+    }
+    pub fn albedo(material: &Materials, hit_rec: &HitRecord) -> Color3 {
+        match material {
+            Materials::DifuseLamb(mat) => mat.albedo(hit_rec),
+            Materials::Metal(mat) => mat.albedo(hit_rec),
+            Materials::Dielectric(mat) => mat.albedo(hit_rec),
+            Materials::Subsurface(mat) => mat.albedo(hit_rec),
+            Materials::Blend(mat) => mat.albedo(hit_rec),
+            Materials::Principled(mat) => mat.albedo(hit_rec),
+            Materials::Emitter(mat) => mat.albedo(hit_rec),
+        }
+    }
+    /// Small number telling the material kinds apart, for debug views.
+    pub fn id(material: &Materials) -> usize {
+        match material {
+            Materials::DifuseLamb(_) => 0,
+            Materials::Metal(_) => 1,
+            Materials::Dielectric(_) => 2,
+            Materials::Subsurface(_) => 3,
+            Materials::Blend(_) => 4,
+            Materials::Principled(_) => 5,
+            Materials::Emitter(_) => 6,
+        }
+    }
+    pub fn is_emissive(material: &Materials) -> bool {
+        match material {
+            Materials::Emitter(_) => true,
+            Materials::Blend(mat) => Self::is_emissive(&mat.first) || Self::is_emissive(&mat.second),
+            _ => false,
+        }
+    }
+    pub fn is_specular(material: &Materials) -> bool {
+        match material {
+            Materials::DifuseLamb(mat) => mat.is_specular(),
+            Materials::Metal(mat) => mat.is_specular(),
+            Materials::Dielectric(mat) => mat.is_specular(),
+            Materials::Subsurface(mat) => mat.is_specular(),
+            Materials::Blend(mat) => mat.is_specular(),
+            Materials::Principled(mat) => mat.is_specular(),
+            Materials::Emitter(mat) => mat.is_specular(),
+        }
+    }
+    pub fn is_medium_step(material: &Materials, hit_rec: &HitRecord) -> bool {
+        match material {
+            Materials::DifuseLamb(mat) => mat.is_medium_step(hit_rec),
+            Materials::Metal(mat) => mat.is_medium_step(hit_rec),
+            Materials::Dielectric(mat) => mat.is_medium_step(hit_rec),
+            Materials::Subsurface(mat) => mat.is_medium_step(hit_rec),
+            Materials::Blend(mat) => mat.is_medium_step(hit_rec),
+            Materials::Principled(mat) => mat.is_medium_step(hit_rec),
+            Materials::Emitter(mat) => mat.is_medium_step(hit_rec),
+        }
+    }
+    /// Picks the concrete material used for one scatter event, so that light
+    /// sampling, evaluation and scattering all agree on the same blend branch.
+    pub fn resolve<'a>(material: &'a Materials, hit_rec: &HitRecord) -> &'a Materials {
+        match material {
+            Materials::Blend(mat) => Self::resolve(mat.pick(hit_rec), hit_rec),
+            _ => material,
+        }
+    }
+}
+
+
+pub struct LightUtil;
+impl LightUtil {
+    pub fn sample_li(light: &Lights, p: &Point3) -> Option<LightSample> {
+        match light {
+            Lights::Point(light) => light.sample_li(p),
+            Lights::Spot(light) => light.sample_li(p),
+            Lights::Directional(light) => light.sample_li(p),
+            Lights::Area(light) => light.sample_li(p),
+        }
+    }
+    pub fn pdf_li(light: &Lights, origin: &Point3, hit_rec: &HitRecord) -> f64 {
+        match light {
+            Lights::Point(light) => light.pdf_li(origin, hit_rec),
+            Lights::Spot(light) => light.pdf_li(origin, hit_rec),
+            Lights::Directional(light) => light.pdf_li(origin, hit_rec),
+            Lights::Area(light) => light.pdf_li(origin, hit_rec),
+        }
+    }
+    pub fn power(light: &Lights) -> f64 {
+        match light {
+            Lights::Point(light) => light.power(),
+            Lights::Spot(light) => light.power(),
+            Lights::Directional(light) => light.power(),
+            Lights::Area(light) => light.power(),
+        }
+    }
+    pub fn bounds(light: &Lights) -> Option<Aabb> {
+        match light {
+            Lights::Point(light) => light.bounds(),
+            Lights::Spot(light) => light.bounds(),
+            Lights::Directional(light) => light.bounds(),
+            Lights::Area(light) => light.bounds(),
+        }
+    }
+    pub fn sample_position(light: &Lights) -> Option<LightPosition> {
+        match light {
+            Lights::Point(light) => light.sample_position(),
+            Lights::Spot(light) => light.sample_position(),
+            Lights::Directional(light) => light.sample_position(),
+            Lights::Area(light) => light.sample_position(),
+        }
+    }
+    pub fn pdf_position(light: &Lights) -> f64 {
+        match light {
+            Lights::Point(light) => light.pdf_position(),
+            Lights::Spot(light) => light.pdf_position(),
+            Lights::Directional(light) => light.pdf_position(),
+            Lights::Area(light) => light.pdf_position(),
+        }
+    }
+    pub fn sample_direction(light: &Lights, position: &LightPosition) -> Option<(Vec3, f64)> {
+        match light {
+            Lights::Point(light) => light.sample_direction(position),
+            Lights::Spot(light) => light.sample_direction(position),
+            Lights::Directional(light) => light.sample_direction(position),
+            Lights::Area(light) => light.sample_direction(position),
+        }
+    }
+    pub fn pdf_direction(light: &Lights, normal: Option<&Vec3>, direction: &Vec3) -> f64 {
+        match light {
+            Lights::Point(light) => light.pdf_direction(normal, direction),
+            Lights::Spot(light) => light.pdf_direction(normal, direction),
+            Lights::Directional(light) => light.pdf_direction(normal, direction),
+            Lights::Area(light) => light.pdf_direction(normal, direction),
+        }
+    }
+    pub fn emission(light: &Lights, position: &LightPosition, direction: &Vec3) -> Color3 {
+        match light {
+            Lights::Point(light) => light.emission(position, direction),
+            Lights::Spot(light) => light.emission(position, direction),
+            Lights::Directional(light) => light.emission(position, direction),
+            Lights::Area(light) => light.emission(position, direction),
+        }
+    }
+    pub fn le(light: &Lights, direction: &Vec3) -> Color3 {
+        match light {
+            Lights::Point(light) => light.le(direction),
+            Lights::Spot(light) => light.le(direction),
+            Lights::Directional(light) => light.le(direction),
+            Lights::Area(light) => light.le(direction),
+        }
+    }
+}
+
+
+pub struct SkyUtil;
+impl SkyUtil {
+    pub fn radiance(sky: &Skies, direction: &Vec3) -> Color3 {
+        match sky {
+            Skies::Gradient(sky) => sky.radiance(direction),
+            Skies::Preetham(sky) => sky.radiance(direction),
+        }
+    }
+}
+
+
+pub struct IntegratorUtil;
+impl IntegratorUtil {
+    pub fn prepare(integrator: &mut Integrators, camera: &Camera, world: &HittableList, pass: u32) {
+        match integrator {
+            Integrators::Path(int) => int.prepare(camera, world, pass),
+            Integrators::Whitted(int) => int.prepare(camera, world, pass),
+            Integrators::Bidirectional(int) => int.prepare(camera, world, pass),
+            Integrators::PhotonMapping(int) => int.prepare(camera, world, pass),
+            Integrators::AmbientOcclusion(int) => int.prepare(camera, world, pass),
+            Integrators::Debug(int) => int.prepare(camera, world, pass),
+        }
+    }
+    pub fn is_progressive(integrator: &Integrators) -> bool {
+        match integrator {
+            Integrators::Path(int) => int.is_progressive(),
+            Integrators::Whitted(int) => int.is_progressive(),
+            Integrators::Bidirectional(int) => int.is_progressive(),
+            Integrators::PhotonMapping(int) => int.is_progressive(),
+            Integrators::AmbientOcclusion(int) => int.is_progressive(),
+            Integrators::Debug(int) => int.is_progressive(),
+        }
+    }
+    pub fn li_split(integrator: &Integrators, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> (LightSplit, Option<HitRecord>) {
+        match integrator {
+            Integrators::Path(int) => int.li_split(ray, camera, world, splats),
+            Integrators::Whitted(int) => int.li_split(ray, camera, world, splats),
+            Integrators::Bidirectional(int) => int.li_split(ray, camera, world, splats),
+            Integrators::PhotonMapping(int) => int.li_split(ray, camera, world, splats),
+            Integrators::AmbientOcclusion(int) => int.li_split(ray, camera, world, splats),
+            Integrators::Debug(int) => int.li_split(ray, camera, world, splats),
+        }
+    }
+    pub fn li(integrator: &Integrators, ray: &Ray, camera: &Camera, world: &HittableList, splats: &SplatBuffer) -> Color3 {
+        match integrator {
+            Integrators::Path(int) => int.li(ray, camera, world, splats),
+            Integrators::Whitted(int) => int.li(ray, camera, world, splats),
+            Integrators::Bidirectional(int) => int.li(ray, camera, world, splats),
+            Integrators::PhotonMapping(int) => int.li(ray, camera, world, splats),
+            Integrators::AmbientOcclusion(int) => int.li(ray, camera, world, splats),
+            Integrators::Debug(int) => int.li(ray, camera, world, splats),
+        }
+    }
+}
+
+
+pub struct FilterUtil;
+impl FilterUtil {
+    pub fn radius(filter: &Filters) -> f64 {
+        match filter {
+            Filters::Box(flt) => flt.radius(),
+            Filters::Tent(flt) => flt.radius(),
+            Filters::Gaussian(flt) => flt.radius(),
+            Filters::Mitchell(flt) => flt.radius(),
+            Filters::Lanczos(flt) => flt.radius(),
+        }
+    }
+    pub fn evaluate(filter: &Filters, x: f64) -> f64 {
+        match filter {
+            Filters::Box(flt) => flt.evaluate(x),
+            Filters::Tent(flt) => flt.evaluate(x),
+            Filters::Gaussian(flt) => flt.evaluate(x),
+            Filters::Mitchell(flt) => flt.evaluate(x),
+            Filters::Lanczos(flt) => flt.evaluate(x),
+        }
+    }
+}
+
+
+    // Dimensions are handed out in the order `MathUtil::rand` is called. The camera takes
+    // 0-1 for the filter offset and 2-3 for the lens, if it has one. Each bounce then
+    // draws its own in turn: the light tree one for infinite lights or not, then one to
+    // pick an infinite light or one per tree level, the light two for its point, the
+    // material one per lobe or medium choice plus two for its direction. So the dimension
+    // budget of a bounce is fixed by the materials and lights the path meets, never by
+    // luck: sampling routines must not loop on rejection, or later bounces of a pixel's
+    // samples stop lining up.
+thread_local! {
+    static SAMPLE: Cell<Option<(Samplers, SamplePoint)>> = const { Cell::new(None) };
+}
+
+pub struct SamplerUtil;
+#[allow(unused)]
+impl SamplerUtil {
+    pub fn sample(sampler: &Samplers, point: &SamplePoint) -> f64 {
+        match sampler {
+            Samplers::Independent(smp) => smp.sample(point),
+            Samplers::Stratified(smp) => smp.sample(point),
+            Samplers::Halton(smp) => smp.sample(point),
+            Samplers::Sobol(smp) => smp.sample(point),
+            Samplers::BlueNoise(smp) => smp.sample(point),
+        }
+    }
+    /// Makes `MathUtil::rand` on this thread draw the dimensions of sample `index` of
+    /// `pixel`, the `batch.0`th of `batch.1` samples the pixel takes together. The
+    /// values only depend on these and `seed`, whichever thread takes the sample.
+    pub fn start_sample(sampler: &Samplers, seed: u64, pixel: (u32, u32), index: u32, batch: (u32, u32)) {
+        MathUtil::seed(MathUtil::hash(&[seed, pixel.0 as u64, pixel.1 as u64, index as u64]));
+        let sample = match sampler {
+            Samplers::Independent(_) => None,
+            _ => Some((*sampler, SamplePoint { seed: seed as u32 ^ (seed >> 32) as u32, pixel, index, batch, dimension: 0 })),
+        };
+        SAMPLE.with(|current| current.set(sample));
+    }
+    pub fn end_sample() {
+        SAMPLE.with(|current| current.set(None));
+    }
+    /// Next dimension of the current sample, if a sample was started on this thread.
+    pub fn next() -> Option<f64> {
+        SAMPLE.with(|current| {
+            let (sampler, mut point) = current.get()?;
+            let value = Self::sample(&sampler, &point);
+            point.dimension += 1;
+            current.set(Some((sampler, point)));
+            Some(value)
+        })
+    }
+}
+
+
+pub struct TexUtil;
+impl TexUtil {
+    pub fn value(texture: &Textures, u: f64, v: f64, p: &Point3) -> Color3 {
+        match texture {
+            Textures::Solid(tex) => tex.value(u, v, p),
+            Textures::Image(tex) => tex.value(u, v, p),
+        }
+    }
+    pub fn alpha(texture: &Textures, u: f64, v: f64, p: &Point3) -> f64 {
+        match texture {
+            Textures::Solid(tex) => tex.alpha(u, v, p),
+            Textures::Image(tex) => tex.alpha(u, v, p),
+        }
+    }
 }
 
 
@@ -101,17 +606,28 @@ impl MathUtil {
         degrees * PI / 180.0
     }
     pub fn rand() -> f64 {
-        fastrand::f64()
+        SamplerUtil::next().unwrap_or_else(fastrand::f64)
+    }
+    /// Restarts the random numbers of this thread from `seed`.
+    pub fn seed(seed: u64) {
+        fastrand::seed(seed);
+    }
+    /// Seed derived from `values` (SplitMix64 finalizer, chained).
+    pub fn hash(values: &[u64]) -> u64 {
+        values.iter().fold(0x9e3779b97f4a7c15, |h, value| {
+            let mut z = (h ^ value).wrapping_add(0x9e3779b97f4a7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        })
     }
     pub fn rand_ran(min: f64, max: f64) -> f64 {
         min + (max - min) * MathUtil::rand()
     }
-    pub fn linear_to_gamma(linear: f64) -> f64 {
-        if linear > 0.0 {
-            f64::sqrt(linear)
-        }
-        else {
-            0.0
-        }
+    /// Power heuristic (beta = 2) weight of a sample drawn with density `pdf_a`
+    /// against a strategy with density `pdf_b`.
+    pub fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+        let (a, b) = (pdf_a * pdf_a, pdf_b * pdf_b);
+        if a + b <= 0.0 || !a.is_finite() { 1.0 } else { a / (a + b) }
     }
 }
\ No newline at end of file